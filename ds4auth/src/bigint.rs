@@ -0,0 +1,268 @@
+//! Just enough fixed-size unsigned bignum arithmetic to check the consistency of an RSA key, and to recover d from it.
+//! None of this is constant time, so it mustn't be used for anything involving secrets beyond that.
+
+use core::cmp::Ordering;
+
+/// Big enough for the product of two 1024-bit numbers, plus some slack for multiplication by e.
+const LIMBS: usize = 72;
+
+#[derive(Clone)]
+pub struct BigUint {
+  /// Little-endian.
+  limbs: [u32; LIMBS],
+}
+
+impl BigUint {
+  pub fn zero() -> BigUint {
+    BigUint { limbs: [0; LIMBS] }
+  }
+
+  pub fn from_u32(value: u32) -> BigUint {
+    let mut result = BigUint::zero();
+    result.limbs[0] = value;
+    result
+  }
+
+  pub fn from_be_bytes(bytes: &[u8]) -> Option<BigUint> {
+    let mut result = BigUint::zero();
+    for (i, byte) in bytes.iter().rev().enumerate() {
+      if *byte == 0 {
+        continue;
+      }
+      if i / 4 >= LIMBS {
+        return None;
+      }
+      result.limbs[i / 4] |= (*byte as u32) << (8 * (i % 4));
+    }
+    Some(result)
+  }
+
+  /// Write the value zero-extended to the size of the buffer, failing if it doesn't fit.
+  pub fn to_be_bytes(&self, buf: &mut [u8]) -> Result<(), ()> {
+    if (self.bits() + 7) / 8 > buf.len() {
+      return Err(());
+    }
+
+    let len = buf.len();
+    for (i, byte) in buf.iter_mut().enumerate() {
+      let index = len - 1 - i;
+      *byte = if index / 4 < LIMBS {
+        (self.limbs[index / 4] >> (8 * (index % 4))) as u8
+      } else {
+        0
+      };
+    }
+    Ok(())
+  }
+
+  pub fn to_u32(&self) -> Option<u32> {
+    if self.bits() <= 32 {
+      Some(self.limbs[0])
+    } else {
+      None
+    }
+  }
+
+  pub fn bits(&self) -> usize {
+    for (i, limb) in self.limbs.iter().enumerate().rev() {
+      if *limb != 0 {
+        return 32 * i + 32 - limb.leading_zeros() as usize;
+      }
+    }
+    0
+  }
+
+  fn bit(&self, index: usize) -> bool {
+    (self.limbs[index / 32] >> (index % 32)) & 1 == 1
+  }
+
+  pub fn add_u32(&self, value: u32) -> Option<BigUint> {
+    let mut result = self.clone();
+    let mut carry = value as u64;
+    for limb in result.limbs.iter_mut() {
+      if carry == 0 {
+        break;
+      }
+      let sum = *limb as u64 + carry;
+      *limb = sum as u32;
+      carry = sum >> 32;
+    }
+
+    if carry == 0 {
+      Some(result)
+    } else {
+      None
+    }
+  }
+
+  /// self - other, or None if other is larger.
+  pub fn sub(&self, other: &BigUint) -> Option<BigUint> {
+    let mut result = self.clone();
+    let mut borrow = 0i64;
+    for (limb, other_limb) in result.limbs.iter_mut().zip(other.limbs.iter()) {
+      let difference = *limb as i64 - *other_limb as i64 - borrow;
+      *limb = difference as u32;
+      borrow = if difference < 0 { 1 } else { 0 };
+    }
+
+    if borrow == 0 {
+      Some(result)
+    } else {
+      None
+    }
+  }
+
+  pub fn sub_u32(&self, value: u32) -> Option<BigUint> {
+    self.sub(&BigUint::from_u32(value))
+  }
+
+  pub fn mul(&self, other: &BigUint) -> Option<BigUint> {
+    if self.bits() + other.bits() > 32 * LIMBS {
+      return None;
+    }
+
+    let mut result = BigUint::zero();
+    for (i, &a) in self.limbs.iter().enumerate() {
+      if a == 0 {
+        continue;
+      }
+
+      let mut carry = 0u64;
+      for (j, &b) in other.limbs.iter().enumerate() {
+        if i + j >= LIMBS {
+          break;
+        }
+        let product = a as u64 * b as u64 + result.limbs[i + j] as u64 + carry;
+        result.limbs[i + j] = product as u32;
+        carry = product >> 32;
+      }
+    }
+    Some(result)
+  }
+
+  pub fn mul_u32(&self, value: u32) -> Option<BigUint> {
+    self.mul(&BigUint::from_u32(value))
+  }
+
+  pub fn div_rem_u32(&self, divisor: u32) -> (BigUint, u32) {
+    let mut quotient = BigUint::zero();
+    let mut remainder = 0u64;
+    for (i, limb) in self.limbs.iter().enumerate().rev() {
+      let dividend = (remainder << 32) | *limb as u64;
+      quotient.limbs[i] = (dividend / divisor as u64) as u32;
+      remainder = dividend % divisor as u64;
+    }
+    (quotient, remainder as u32)
+  }
+
+  /// self mod modulus, by shift and subtract.
+  pub fn rem(&self, modulus: &BigUint) -> BigUint {
+    let mut remainder = BigUint::zero();
+    for i in (0..self.bits()).rev() {
+      // remainder < modulus, which fits, so shifting it left by one can't overflow.
+      let mut carry = self.bit(i) as u32;
+      for limb in remainder.limbs.iter_mut() {
+        let next_carry = *limb >> 31;
+        *limb = (*limb << 1) | carry;
+        carry = next_carry;
+      }
+
+      if let Some(difference) = remainder.sub(modulus) {
+        remainder = difference;
+      }
+    }
+    remainder
+  }
+
+  /// The inverse of self modulo a small modulus, if there is one.
+  pub fn inverse_mod_u32(&self, modulus: u32) -> Option<u32> {
+    let (_, value) = self.div_rem_u32(modulus);
+
+    // Extended Euclidean algorithm, tracking only the coefficient of value.
+    let (mut r0, mut r1) = (modulus as i64, value as i64);
+    let (mut t0, mut t1) = (0i64, 1i64);
+    while r1 != 0 {
+      let q = r0 / r1;
+      let (r2, t2) = (r0 - q * r1, t0 - q * t1);
+      r0 = r1;
+      r1 = r2;
+      t0 = t1;
+      t1 = t2;
+    }
+
+    if r0 != 1 {
+      return None;
+    }
+    Some(t0.rem_euclid(modulus as i64) as u32)
+  }
+}
+
+impl PartialEq for BigUint {
+  fn eq(&self, other: &BigUint) -> bool {
+    self.limbs[..] == other.limbs[..]
+  }
+}
+
+impl Eq for BigUint {}
+
+impl PartialOrd for BigUint {
+  fn partial_cmp(&self, other: &BigUint) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl Ord for BigUint {
+  fn cmp(&self, other: &BigUint) -> Ordering {
+    self.limbs.iter().rev().cmp(other.limbs.iter().rev())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn big(bytes: &[u8]) -> BigUint {
+    BigUint::from_be_bytes(bytes).unwrap()
+  }
+
+  #[test]
+  fn bytes() {
+    let value = big(&[0x00, 0x01, 0x02, 0x03, 0x04, 0x05]);
+    assert_eq!(33, value.bits());
+    assert_eq!(None, value.to_u32());
+
+    let mut buf = [0xffu8; 8];
+    value.to_be_bytes(&mut buf).unwrap();
+    assert_eq!([0, 0, 0, 0x01, 0x02, 0x03, 0x04, 0x05], buf);
+    assert_eq!(Err(()), value.to_be_bytes(&mut [0u8; 4]));
+  }
+
+  #[test]
+  fn arithmetic() {
+    let a = big(&[0xff; 16]);
+    let b = a.add_u32(1).unwrap();
+    assert_eq!(129, b.bits());
+    assert!(b.sub_u32(1) == Some(a.clone()));
+    assert!(a.sub(&b).is_none());
+
+    // (2^128 - 1)^2 = 2^256 - 2^129 + 1
+    let square = a.mul(&a).unwrap();
+    let expected = big(&[0xff; 32])
+      .sub(&b.mul_u32(2).unwrap())
+      .unwrap()
+      .add_u32(2)
+      .unwrap();
+    assert!(square == expected);
+
+    let (quotient, remainder) = square.div_rem_u32(65537);
+    assert!(quotient.mul_u32(65537).unwrap().add_u32(remainder).unwrap() == square);
+    assert!(square.rem(&BigUint::from_u32(65537)) == BigUint::from_u32(remainder));
+    assert!(square.rem(&a) == BigUint::zero());
+  }
+
+  #[test]
+  fn inverse() {
+    assert_eq!(Some(4), BigUint::from_u32(3).inverse_mod_u32(11));
+    assert_eq!(None, BigUint::from_u32(6).inverse_mod_u32(9));
+  }
+}
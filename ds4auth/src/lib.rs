@@ -4,7 +4,12 @@
 extern crate log;
 
 use core::fmt;
-use ring::signature::{KeyPair, RsaKeyPair, RsaPublicKeyComponents};
+
+mod bigint;
+use bigint::BigUint;
+
+pub mod rsa;
+mod sha256;
 
 #[repr(packed)]
 pub struct DS4Signature {
@@ -16,6 +21,13 @@ pub struct DS4Signature {
   pub padding: [u8; 24],
 }
 
+/// The public key sent along with a signature, without leading zeroes.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PublicKey<'a> {
+  pub n: &'a [u8],
+  pub e: &'a [u8],
+}
+
 fn trim(mut slice: &[u8]) -> &[u8] {
   while let Some(0) = slice.first() {
     slice = &slice[1..];
@@ -42,21 +54,14 @@ impl DS4Signature {
     result
   }
 
-  pub fn public_key(&self) -> RsaPublicKeyComponents<&[u8]> {
+  pub fn public_key(&self) -> PublicKey<'_> {
     let n = trim(self.n.as_ref());
     let e = trim(self.e.as_ref());
-    RsaPublicKeyComponents { n, e }
+    PublicKey { n, e }
   }
 
   pub fn validate(&self, nonce: &[u8]) -> bool {
-    self
-      .public_key()
-      .verify(
-        &ring::signature::RSA_PSS_2048_8192_SHA256,
-        nonce,
-        self.nonce_sig.as_ref(),
-      )
-      .is_ok()
+    rsa::verify_pss_sha256(&self.n, &self.e, nonce, &self.nonce_sig)
   }
 
   pub fn as_bytes(&self) -> &[u8] {
@@ -83,61 +88,93 @@ impl fmt::Debug for DS4Signature {
   }
 }
 
-fn zero_extend_256(value: ring::io::Positive) -> Option<[u8; 256]> {
-  let mut buf = [0u8; 256];
-  let bytes = value.big_endian_without_leading_zero();
-  if bytes.len() > buf.len() {
-    None
-  } else {
-    let start = buf.len() - bytes.len();
-    &buf[start..].copy_from_slice(bytes);
-    Some(buf)
+/// Ways in which a key can fail DS4KeyEncoded::self_test.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SelfTestError {
+  Inconsistent(KeyError),
+
+  /// The key signature is blank, so the console would reject the key even if it signs correctly.
+  MissingKeySignature,
+
+  LoadFailed,
+  SignFailed,
+  InvalidSignature,
+}
+
+impl fmt::Display for SelfTestError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      SelfTestError::Inconsistent(err) => write!(f, "{}", err),
+      SelfTestError::MissingKeySignature => write!(f, "key signature is missing"),
+      SelfTestError::LoadFailed => write!(f, "failed to load key"),
+      SelfTestError::SignFailed => write!(f, "failed to sign nonce"),
+      SelfTestError::InvalidSignature => write!(f, "signature doesn't validate"),
+    }
   }
 }
 
+/// A key that's ready to sign with. Everything is stored inline, so that signing never needs a heap.
 pub struct DS4Key {
-  serial: &'static [u8; 16],
-  keypair: RsaKeyPair,
-  signature: &'static [u8; 256],
+  serial: [u8; 16],
+  key: rsa::PrivateKey,
+  n: [u8; 256],
+  e: [u8; 256],
+  signature: [u8; 256],
 }
 
 impl DS4Key {
+  /// The key in keys/, which is built into anything that uses this.
+  #[cfg(feature = "embedded_key")]
   pub fn embedded() -> Option<DS4Key> {
-    if let Ok(keypair) = RsaKeyPair::from_der(include_bytes!("../../keys/ds4.der")) {
-      let serial = include_bytes!("../../keys/ds4.serial");
-      let signature = include_bytes!("../../keys/ds4.sig");
-      Some(DS4Key {
-        serial: serial,
-        keypair,
-        signature: signature,
-      })
-    } else {
-      None
+    DS4Key::from_encoded(&DS4KeyEncoded::embedded()?)
+  }
+
+  /// Load a key from its on-flash format.
+  pub fn from_encoded(encoded: &DS4KeyEncoded) -> Option<DS4Key> {
+    match rsa::PrivateKey::from_encoded(encoded) {
+      Ok(key) => Some(DS4Key {
+        serial: encoded.serial,
+        key,
+        n: encoded.n,
+        e: encoded.e,
+        signature: encoded.sig,
+      }),
+      Err(e) => {
+        error!("failed to load encoded key: {}", e);
+        None
+      }
     }
   }
 
   pub fn sign(&self, nonce: &[u8]) -> Option<DS4Signature> {
     let mut signature = [0; 256];
-    let rand = ring::rand::SystemRandom::new();
-    if let Err(e) = self
-      .keypair
-      .sign(&ring::signature::RSA_PSS_SHA256, &rand, nonce, &mut signature)
-    {
+    if let Err(e) = self.key.sign(nonce, &mut signature) {
       error!("failed to sign: {}", e);
       return None;
     }
 
     Some(DS4Signature {
       nonce_sig: signature,
-      serial: self.serial.clone(),
-      n: zero_extend_256(self.keypair.public_key().modulus()).unwrap(),
-      e: zero_extend_256(self.keypair.public_key().exponent()).unwrap(),
-      key_sig: self.signature.clone(),
+      serial: self.serial,
+      n: self.n,
+      e: self.e,
+      key_sig: self.signature,
       padding: [0u8; 24],
     })
   }
+
+  /// Sign a fixed nonce, and check the signature.
+  fn test_sign(&self) -> Result<(), SelfTestError> {
+    let nonce = [0x5a; 256];
+    let signature = self.sign(&nonce).ok_or(SelfTestError::SignFailed)?;
+    if !signature.validate(&nonce) {
+      return Err(SelfTestError::InvalidSignature);
+    }
+    Ok(())
+  }
 }
 
+#[derive(Clone, Copy)]
 #[repr(packed)]
 /// Format of the DS4 key on flash.
 pub struct DS4KeyEncoded {
@@ -154,3 +191,488 @@ pub struct DS4KeyEncoded {
   pub dq: [u8; 128],
   pub qinv: [u8; 128],
 }
+
+/// Reasons that a key can't be converted to or from DS4KeyEncoded.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum KeyError {
+  /// The DER isn't a PKCS#1 RSAPrivateKey.
+  MalformedDer,
+
+  /// A component is too large for its field.
+  TooLarge(&'static str),
+
+  /// The components don't describe a valid key, e.g. because p * q != n.
+  Inconsistent(&'static str),
+}
+
+impl fmt::Display for KeyError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      KeyError::MalformedDer => write!(f, "malformed RSAPrivateKey"),
+      KeyError::TooLarge(component) => write!(f, "{} is too large", component),
+      KeyError::Inconsistent(reason) => write!(f, "inconsistent key: {}", reason),
+    }
+  }
+}
+
+/// Large enough for an RSAPrivateKey with a 2048-bit modulus, even if every component is as long as it can be.
+pub const DER_BUFFER_SIZE: usize = 1536;
+
+struct DerWriter<'a> {
+  buf: &'a mut [u8],
+  len: usize,
+}
+
+impl<'a> DerWriter<'a> {
+  fn push(&mut self, byte: u8) {
+    self.buf[self.len] = byte;
+    self.len += 1;
+  }
+
+  fn length(&mut self, len: usize) {
+    if len < 0x80 {
+      self.push(len as u8);
+    } else if len < 0x100 {
+      self.push(0x81);
+      self.push(len as u8);
+    } else {
+      self.push(0x82);
+      self.push((len >> 8) as u8);
+      self.push(len as u8);
+    }
+  }
+
+  fn integer(&mut self, value: &[u8]) {
+    let value = trim(value);
+    let pad = value.first().map_or(true, |byte| byte & 0x80 != 0);
+    self.push(0x02);
+    self.length(value.len() + pad as usize);
+    if pad {
+      self.push(0x00);
+    }
+    self.buf[self.len..self.len + value.len()].copy_from_slice(value);
+    self.len += value.len();
+  }
+}
+
+struct DerReader<'a> {
+  data: &'a [u8],
+}
+
+impl<'a> DerReader<'a> {
+  fn read_byte(&mut self) -> Result<u8, KeyError> {
+    let (&byte, rest) = self.data.split_first().ok_or(KeyError::MalformedDer)?;
+    self.data = rest;
+    Ok(byte)
+  }
+
+  fn read_length(&mut self) -> Result<usize, KeyError> {
+    let first = self.read_byte()?;
+    match first {
+      0x00..=0x7f => Ok(first as usize),
+      0x81 => Ok(self.read_byte()? as usize),
+      0x82 => Ok((self.read_byte()? as usize) << 8 | self.read_byte()? as usize),
+      _ => Err(KeyError::MalformedDer),
+    }
+  }
+
+  fn read_tagged(&mut self, tag: u8) -> Result<&'a [u8], KeyError> {
+    if self.read_byte()? != tag {
+      return Err(KeyError::MalformedDer);
+    }
+
+    let len = self.read_length()?;
+    if len > self.data.len() {
+      return Err(KeyError::MalformedDer);
+    }
+
+    let (value, rest) = self.data.split_at(len);
+    self.data = rest;
+    Ok(value)
+  }
+
+  /// Read an INTEGER into a zero-extended big-endian field.
+  fn read_integer(&mut self, name: &'static str, field: &mut [u8]) -> Result<(), KeyError> {
+    let value = self.read_tagged(0x02)?;
+    if value.first().map_or(true, |byte| byte & 0x80 != 0) {
+      // Empty, or negative.
+      return Err(KeyError::MalformedDer);
+    }
+
+    let value = trim(value);
+    if value.len() > field.len() {
+      return Err(KeyError::TooLarge(name));
+    }
+
+    let start = field.len() - value.len();
+    for byte in field[..start].iter_mut() {
+      *byte = 0;
+    }
+    field[start..].copy_from_slice(value);
+    Ok(())
+  }
+}
+
+fn to_biguint(value: &[u8]) -> BigUint {
+  // Every field of DS4KeyEncoded fits.
+  BigUint::from_be_bytes(value).unwrap()
+}
+
+impl DS4KeyEncoded {
+  pub const SIZE: usize = core::mem::size_of::<DS4KeyEncoded>();
+
+  /// The key in keys/, which is built into anything that uses this.
+  #[cfg(feature = "embedded_key")]
+  pub fn embedded() -> Option<DS4KeyEncoded> {
+    let der = include_bytes!("../../keys/ds4.der");
+    let serial = include_bytes!("../../keys/ds4.serial");
+    let signature = include_bytes!("../../keys/ds4.sig");
+    DS4KeyEncoded::from_der(der, serial, signature).ok()
+  }
+
+  pub fn from_bytes(bytes: &[u8]) -> Option<&DS4KeyEncoded> {
+    if bytes.len() == DS4KeyEncoded::SIZE {
+      // DS4KeyEncoded is packed, so any pointer is suitably aligned.
+      Some(unsafe { &*(bytes.as_ptr() as *const DS4KeyEncoded) })
+    } else {
+      None
+    }
+  }
+
+  /// Build a key from a PKCS#1 RSAPrivateKey, as well as the serial and signature that go with it.
+  /// d isn't stored, since it's recoverable from the rest of the key.
+  pub fn from_der(der: &[u8], serial: &[u8; 16], sig: &[u8; 256]) -> Result<DS4KeyEncoded, KeyError> {
+    let mut result = DS4KeyEncoded {
+      serial: *serial,
+      n: [0; 256],
+      e: [0; 256],
+      sig: *sig,
+      p: [0; 128],
+      q: [0; 128],
+      dp: [0; 128],
+      dq: [0; 128],
+      qinv: [0; 128],
+    };
+
+    let mut outer = DerReader { data: der };
+    let mut reader = DerReader {
+      data: outer.read_tagged(0x30)?,
+    };
+
+    let mut version = [0u8; 1];
+    reader.read_integer("version", &mut version)?;
+    if version[0] != 0 {
+      // Multi-prime keys aren't supported.
+      return Err(KeyError::MalformedDer);
+    }
+
+    let mut d = [0u8; 256];
+    reader.read_integer("n", &mut result.n)?;
+    reader.read_integer("e", &mut result.e)?;
+    reader.read_integer("d", &mut d)?;
+    reader.read_integer("p", &mut result.p)?;
+    reader.read_integer("q", &mut result.q)?;
+    reader.read_integer("dp", &mut result.dp)?;
+    reader.read_integer("dq", &mut result.dq)?;
+    reader.read_integer("qinv", &mut result.qinv)?;
+    Ok(result)
+  }
+
+  /// Whether the key is still in the state of freshly erased flash.
+  pub fn is_blank(&self) -> bool {
+    self.as_bytes().iter().all(|&byte| byte == 0xff)
+  }
+
+  pub fn as_bytes(&self) -> &[u8] {
+    unsafe {
+      core::slice::from_raw_parts(
+        (self as *const DS4KeyEncoded) as *const u8,
+        core::mem::size_of::<DS4KeyEncoded>(),
+      )
+    }
+  }
+
+  /// Check that the CRT parameters are consistent with n and e.
+  pub fn validate(&self) -> Result<(), KeyError> {
+    let n = to_biguint(&self.n);
+    let p = to_biguint(&self.p);
+    let q = to_biguint(&self.q);
+    let dp = to_biguint(&self.dp);
+    let dq = to_biguint(&self.dq);
+    let qinv = to_biguint(&self.qinv);
+    let e = to_biguint(&self.e);
+    let one = BigUint::from_u32(1);
+
+    if n.bits() != 2048 {
+      return Err(KeyError::Inconsistent("n isn't 2048 bits"));
+    }
+
+    if p <= one || q <= one || p.mul(&q) != Some(n) {
+      return Err(KeyError::Inconsistent("p * q != n"));
+    }
+
+    let p_minus_one = p.sub_u32(1).unwrap();
+    let q_minus_one = q.sub_u32(1).unwrap();
+    // e is only limited by the size of its field, so it can be too large to multiply by.
+    if dp >= p_minus_one || dp.mul(&e).ok_or(KeyError::TooLarge("e"))?.rem(&p_minus_one) != one {
+      return Err(KeyError::Inconsistent("dp * e != 1 (mod p - 1)"));
+    }
+
+    if dq >= q_minus_one || dq.mul(&e).ok_or(KeyError::TooLarge("e"))?.rem(&q_minus_one) != one {
+      return Err(KeyError::Inconsistent("dq * e != 1 (mod q - 1)"));
+    }
+
+    if qinv >= p || qinv.mul(&q).map(|x| x.rem(&p)).as_ref() != Some(&one) {
+      return Err(KeyError::Inconsistent("qinv * q != 1 (mod p)"));
+    }
+
+    Ok(())
+  }
+
+  /// Check that the CRT parameters are consistent and that there's a key signature, then load the key and check that
+  /// it signs correctly.
+  ///
+  /// The key signature itself can only be checked against the public key of the CA that issued it, with
+  /// verify_key_signature.
+  pub fn self_test(&self) -> Result<DS4Key, SelfTestError> {
+    self.validate().map_err(SelfTestError::Inconsistent)?;
+    if self.sig.iter().all(|&byte| byte == 0) || self.sig.iter().all(|&byte| byte == 0xff) {
+      return Err(SelfTestError::MissingKeySignature);
+    }
+
+    let key = DS4Key::from_encoded(self).ok_or(SelfTestError::LoadFailed)?;
+    key.test_sign()?;
+    Ok(key)
+  }
+
+  /// serial | n | e, which is what the key signature signs.
+  fn key_signature_message(&self) -> [u8; 528] {
+    let mut message = [0u8; 528];
+    message[..16].copy_from_slice(&self.serial);
+    message[16..272].copy_from_slice(&self.n);
+    message[272..].copy_from_slice(&self.e);
+    message
+  }
+
+  /// Check the key signature against the public key of the CA that issued it.
+  pub fn verify_key_signature(&self, ca_n: &[u8; 256], ca_e: &[u8]) -> bool {
+    rsa::verify_pss_sha256(ca_n, ca_e, &self.key_signature_message(), &{ self.sig })
+  }
+
+  /// Recover d = e^-1 mod (p - 1)(q - 1).
+  ///
+  /// e is small, so instead of a full modular inverse, find the k for which 1 + k * phi is divisible by e, which makes
+  /// d = (1 + k * phi) / e.
+  fn private_exponent(&self) -> Result<BigUint, KeyError> {
+    let e = to_biguint(&self.e).to_u32().ok_or(KeyError::TooLarge("e"))?;
+    let p_minus_one = to_biguint(&self.p)
+      .sub_u32(1)
+      .ok_or(KeyError::Inconsistent("p is zero"))?;
+    let q_minus_one = to_biguint(&self.q)
+      .sub_u32(1)
+      .ok_or(KeyError::Inconsistent("q is zero"))?;
+    let phi = p_minus_one.mul(&q_minus_one).ok_or(KeyError::TooLarge("p * q"))?;
+
+    // k * phi = -1 (mod e)
+    let phi_inverse = phi
+      .inverse_mod_u32(e)
+      .ok_or(KeyError::Inconsistent("e isn't invertible"))?;
+    let k = e - phi_inverse;
+
+    let numerator = phi
+      .mul_u32(k)
+      .and_then(|x| x.add_u32(1))
+      .ok_or(KeyError::TooLarge("d"))?;
+    let (d, remainder) = numerator.div_rem_u32(e);
+    debug_assert_eq!(0, remainder);
+    Ok(d)
+  }
+
+  /// Encode the key as a PKCS#1 RSAPrivateKey, which is what ring and most other things want.
+  pub fn to_der<'a>(&self, buf: &'a mut [u8; DER_BUFFER_SIZE]) -> Result<&'a [u8], KeyError> {
+    let mut d = [0u8; 256];
+    self
+      .private_exponent()?
+      .to_be_bytes(&mut d)
+      .map_err(|_| KeyError::TooLarge("d"))?;
+
+    // Leave room for the SEQUENCE header, which can't be written until the length of its contents is known.
+    const HEADER_SIZE: usize = 4;
+    let mut body = DerWriter {
+      buf: &mut buf[HEADER_SIZE..],
+      len: 0,
+    };
+    body.integer(&[0]);
+    body.integer(&self.n);
+    body.integer(&self.e);
+    body.integer(&d);
+    body.integer(&self.p);
+    body.integer(&self.q);
+    body.integer(&self.dp);
+    body.integer(&self.dq);
+    body.integer(&self.qinv);
+    let body_len = body.len;
+
+    let mut header = [0u8; HEADER_SIZE];
+    let mut header_writer = DerWriter {
+      buf: &mut header,
+      len: 0,
+    };
+    header_writer.push(0x30);
+    header_writer.length(body_len);
+    let header_len = header_writer.len;
+
+    let start = HEADER_SIZE - header_len;
+    buf[start..HEADER_SIZE].copy_from_slice(&header[..header_len]);
+    Ok(&buf[start..HEADER_SIZE + body_len])
+  }
+}
+
+fn write_hex(f: &mut fmt::Formatter<'_>, bytes: &[u8]) -> fmt::Result {
+  for byte in bytes {
+    write!(f, "{:02x}", byte)?;
+  }
+  Ok(())
+}
+
+impl fmt::Display for DS4KeyEncoded {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let n = to_biguint(&self.n);
+    let e = to_biguint(&self.e);
+    let trimmed_n = trim(&self.n);
+
+    write!(f, "serial: ")?;
+    write_hex(f, &{ self.serial })?;
+    writeln!(f)?;
+
+    write!(f, "modulus: {} bits, ", n.bits())?;
+    if trimmed_n.len() > 8 {
+      write_hex(f, &trimmed_n[..4])?;
+      write!(f, "...")?;
+      write_hex(f, &trimmed_n[trimmed_n.len() - 4..])?;
+    } else {
+      write_hex(f, trimmed_n)?;
+    }
+    writeln!(f)?;
+
+    match e.to_u32() {
+      Some(e) => writeln!(f, "public exponent: {}", e)?,
+      None => writeln!(f, "public exponent: {} bits", e.bits())?,
+    }
+
+    write!(f, "key signature: ")?;
+    if self.sig.iter().all(|&byte| byte == 0) {
+      write!(f, "empty")?;
+    } else {
+      write_hex(f, &self.sig[..8])?;
+      write!(f, "...")?;
+    }
+    writeln!(f)?;
+
+    match self.validate() {
+      Ok(()) => write!(f, "CRT parameters: consistent"),
+      Err(e) => write!(f, "CRT parameters: {}", e),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const TEST_KEY: &[u8] = include_bytes!("../testdata/rsa2048.der");
+
+  fn encoded() -> DS4KeyEncoded {
+    DS4KeyEncoded::from_der(TEST_KEY, &[0x11; 16], &[0x22; 256]).unwrap()
+  }
+
+  #[test]
+  fn der_round_trip() {
+    let encoded = encoded();
+    assert_eq!(Ok(()), encoded.validate());
+    assert_eq!([0x11; 16], { encoded.serial });
+    assert_eq!(&[0x01, 0x00, 0x01], &encoded.e[253..]);
+
+    let mut buf = [0u8; DER_BUFFER_SIZE];
+    assert_eq!(Ok(TEST_KEY), encoded.to_der(&mut buf));
+  }
+
+  #[test]
+  fn corrupted_p() {
+    let mut encoded = encoded();
+    encoded.p[64] ^= 0x01;
+    assert_eq!(Err(KeyError::Inconsistent("p * q != n")), encoded.validate());
+
+    encoded.p = [0; 128];
+    assert_eq!(Err(KeyError::Inconsistent("p * q != n")), encoded.validate());
+  }
+
+  #[test]
+  fn wrong_dp() {
+    let mut encoded = encoded();
+    encoded.dp[127] ^= 0x02;
+    assert_eq!(
+      Err(KeyError::Inconsistent("dp * e != 1 (mod p - 1)")),
+      encoded.validate()
+    );
+
+    // An e that fills its field can't be multiplied by, which isn't the same thing as dp being wrong.
+    let mut encoded = self::encoded();
+    encoded.e = encoded.n;
+    assert_eq!(Err(KeyError::TooLarge("e")), encoded.validate());
+  }
+
+  #[test]
+  fn truncated_der() {
+    for &len in [0, 1, 4, 40, TEST_KEY.len() / 2, TEST_KEY.len() - 1].iter() {
+      assert_eq!(
+        Err(KeyError::MalformedDer),
+        DS4KeyEncoded::from_der(&TEST_KEY[..len], &[0; 16], &[0; 256]).map(|_| ()),
+        "len = {}",
+        len
+      );
+    }
+
+    // The outer length has to agree with the contents, too.
+    let mut buf = [0u8; DER_BUFFER_SIZE];
+    let der = &mut buf[..TEST_KEY.len()];
+    der.copy_from_slice(TEST_KEY);
+    der[3] -= 1;
+    assert_eq!(
+      Err(KeyError::MalformedDer),
+      DS4KeyEncoded::from_der(der, &[0; 16], &[0; 256]).map(|_| ())
+    );
+  }
+
+  #[test]
+  fn self_test() {
+    assert!(encoded().self_test().is_ok());
+
+    let mut encoded = self::encoded();
+    encoded.sig = [0; 256];
+    assert_eq!(Some(SelfTestError::MissingKeySignature), encoded.self_test().err());
+
+    let mut encoded = self::encoded();
+    encoded.q[100] ^= 0x80;
+    assert_eq!(
+      Some(SelfTestError::Inconsistent(KeyError::Inconsistent("p * q != n"))),
+      encoded.self_test().err()
+    );
+  }
+
+  #[test]
+  fn key_signature() {
+    // Stand in for the CA by signing the key with itself.
+    let mut encoded = encoded();
+    let ca = rsa::PrivateKey::from_encoded(&encoded).unwrap();
+    let mut sig = [0u8; 256];
+    ca.sign(&encoded.key_signature_message(), &mut sig).unwrap();
+    encoded.sig = sig;
+
+    let (ca_n, ca_e) = (encoded.n, encoded.e);
+    assert!(encoded.verify_key_signature(&ca_n, &ca_e));
+
+    encoded.serial[0] ^= 0x01;
+    assert!(!encoded.verify_key_signature(&ca_n, &ca_e));
+  }
+}
@@ -1,6 +1,104 @@
-fn main() {
-  let keypair = ds4auth::DS4Key::embedded().unwrap();
-  let nonce = [0u8; 256];
-  let signature = keypair.sign(&nonce).unwrap();
-  assert!(signature.validate(&nonce));
+use std::convert::TryFrom;
+
+use ds4auth::{DS4KeyEncoded, DER_BUFFER_SIZE};
+
+fn usage() -> ! {
+  eprintln!("usage: ds4auth pack KEY.der SERIAL SIG OUTPUT");
+  eprintln!("       ds4auth unpack INPUT KEY.der SERIAL SIG");
+  eprintln!("       ds4auth info INPUT [CA_N CA_E]");
+  eprintln!();
+  eprintln!("  pack: combine a PKCS#1 DER private key and its serial and signature into the DS4KeyEncoded format");
+  eprintln!("  unpack: split a DS4KeyEncoded key back into a PKCS#1 DER private key, serial, and signature");
+  eprintln!("  info: describe a DS4KeyEncoded key, and check that it works, and optionally that its key signature was");
+  eprintln!("        issued by the CA with the given (big-endian, raw) public key");
+  std::process::exit(1);
+}
+
+fn read(path: &str) -> Result<Vec<u8>, String> {
+  std::fs::read(path).map_err(|err| format!("failed to read {}: {}", path, err))
+}
+
+fn read_array<T: for<'a> TryFrom<&'a [u8]>>(path: &str) -> Result<T, String> {
+  let data = read(path)?;
+  T::try_from(&data[..]).map_err(|_| format!("{} has the wrong size ({} bytes)", path, data.len()))
+}
+
+fn write(path: &str, data: &[u8]) -> Result<(), String> {
+  std::fs::write(path, data).map_err(|err| format!("failed to write {}: {}", path, err))
+}
+
+fn read_encoded(path: &str) -> Result<DS4KeyEncoded, String> {
+  let data = read(path)?;
+  DS4KeyEncoded::from_bytes(&data)
+    .copied()
+    .ok_or_else(|| format!("{} has the wrong size ({} bytes)", path, data.len()))
+}
+
+/// Check the key for consistency, sign a nonce with it, and check the result.
+fn test_sign(encoded: &DS4KeyEncoded) -> Result<(), String> {
+  encoded.self_test().map(|_| ()).map_err(|err| err.to_string())
+}
+
+fn pack(der_path: &str, serial_path: &str, sig_path: &str, output_path: &str) -> Result<(), String> {
+  let der = read(der_path)?;
+  let serial: [u8; 16] = read_array(serial_path)?;
+  let sig: [u8; 256] = read_array(sig_path)?;
+
+  let encoded = DS4KeyEncoded::from_der(&der, &serial, &sig).map_err(|err| err.to_string())?;
+  test_sign(&encoded)?;
+
+  write(output_path, encoded.as_bytes())?;
+  println!("{}", encoded);
+  Ok(())
+}
+
+fn unpack(input_path: &str, der_path: &str, serial_path: &str, sig_path: &str) -> Result<(), String> {
+  let encoded = read_encoded(input_path)?;
+  encoded.validate().map_err(|err| err.to_string())?;
+
+  let mut buf = [0u8; DER_BUFFER_SIZE];
+  let der = encoded.to_der(&mut buf).map_err(|err| err.to_string())?;
+  write(der_path, der)?;
+  write(serial_path, &{ encoded.serial })?;
+  write(sig_path, &{ encoded.sig })?;
+  Ok(())
+}
+
+fn info(input_path: &str, ca_paths: Option<(&str, &str)>) -> Result<(), String> {
+  let encoded = read_encoded(input_path)?;
+  println!("{}", encoded);
+
+  let result = test_sign(&encoded);
+  match result {
+    Ok(()) => println!("self-test: passed"),
+    Err(ref err) => println!("self-test: {}", err),
+  }
+
+  if let Some((n_path, e_path)) = ca_paths {
+    let ca_n: [u8; 256] = read_array(n_path)?;
+    let ca_e = read(e_path)?;
+    if encoded.verify_key_signature(&ca_n, &ca_e) {
+      println!("key signature: issued by CA");
+    } else {
+      println!("key signature: not issued by CA");
+      return result.and(Err("key signature doesn't verify".to_string()));
+    }
+  }
+  result
+}
+
+pub fn main() {
+  let args: Vec<String> = std::env::args().skip(1).collect();
+  let result = match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+    ["pack", der, serial, sig, output] => pack(der, serial, sig, output),
+    ["unpack", input, der, serial, sig] => unpack(input, der, serial, sig),
+    ["info", input] => info(input, None),
+    ["info", input, ca_n, ca_e] => info(input, Some((ca_n, ca_e))),
+    _ => usage(),
+  };
+
+  if let Err(err) = result {
+    eprintln!("error: {}", err);
+    std::process::exit(1);
+  }
 }
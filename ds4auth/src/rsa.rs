@@ -0,0 +1,510 @@
+//! Allocation-free RSA-PSS-SHA256 signing and verification, using the CRT parameters of a DS4KeyEncoded.
+//!
+//! The arithmetic is Montgomery multiplication (CIOS) on 32-bit limbs, which the Cortex-M3 does with UMULL/UMLAL, and
+//! exponentiation uses a fixed 4-bit window with table lookups that touch every entry. Each signature is checked with
+//! the public exponent before it's returned, since a fault in one half of a CRT signature gives away the factors.
+
+use crate::sha256::{self, Sha256};
+use crate::{DS4KeyEncoded, KeyError};
+
+pub const SIGNATURE_LEN: usize = 256;
+pub const SALT_LEN: usize = 32;
+const HASH_LEN: usize = sha256::DIGEST_LEN;
+
+/// Enough for the 2048-bit modulus.
+const LIMBS: usize = 64;
+
+/// Enough for the 1024-bit primes.
+const HALF_LIMBS: usize = 32;
+
+type Limbs = [u32; LIMBS];
+
+fn one() -> Limbs {
+  let mut one = [0; LIMBS];
+  one[0] = 1;
+  one
+}
+
+/// Read a big-endian number into little-endian limbs, which must be exactly the right size.
+fn limbs_from_be_bytes(bytes: &[u8], limbs: &mut [u32]) {
+  debug_assert_eq!(bytes.len(), limbs.len() * 4);
+  for (limb, chunk) in limbs.iter_mut().zip(bytes.rchunks(4)) {
+    *limb = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+  }
+}
+
+fn limbs_to_be_bytes(limbs: &[u32], bytes: &mut [u8]) {
+  debug_assert_eq!(bytes.len(), limbs.len() * 4);
+  for (limb, chunk) in limbs.iter().zip(bytes.rchunks_mut(4)) {
+    chunk.copy_from_slice(&limb.to_be_bytes());
+  }
+}
+
+/// a -= b, returning the borrow.
+fn sub_in_place(a: &mut [u32], b: &[u32]) -> u32 {
+  let mut borrow = 0u64;
+  for (a, &b) in a.iter_mut().zip(b) {
+    let difference = (*a as u64).wrapping_sub(b as u64).wrapping_sub(borrow);
+    *a = difference as u32;
+    borrow = difference >> 63;
+  }
+  borrow as u32
+}
+
+/// a += b, returning the carry.
+fn add_in_place(a: &mut [u32], b: &[u32]) -> u32 {
+  let mut carry = 0u64;
+  for (a, &b) in a.iter_mut().zip(b) {
+    let sum = *a as u64 + b as u64 + carry;
+    *a = sum as u32;
+    carry = sum >> 32;
+  }
+  carry as u32
+}
+
+/// a = mask ? b : a, for a mask of all zeroes or all ones.
+fn select(a: &mut [u32], b: &[u32], mask: u32) {
+  for (a, &b) in a.iter_mut().zip(b) {
+    *a = (*a & !mask) | (b & mask);
+  }
+}
+
+/// An odd modulus, and what's needed to do Montgomery multiplication with it.
+struct Modulus {
+  len: usize,
+  m: Limbs,
+
+  /// -m^-1 mod 2^32.
+  n0: u32,
+
+  /// R^2 and R^3 mod m, where R = 2^(32 * len).
+  r2: Limbs,
+  r3: Limbs,
+}
+
+impl Modulus {
+  fn new(bytes: &[u8], name: &'static str) -> Result<Modulus, KeyError> {
+    let len = bytes.len() / 4;
+    let mut m = [0; LIMBS];
+    limbs_from_be_bytes(bytes, &mut m[..len]);
+    if m[0] & 1 == 0 || m[len - 1] == 0 {
+      return Err(KeyError::Inconsistent(name));
+    }
+
+    // Newton's method doubles the number of correct bits each time, and m is its own inverse mod 8.
+    let mut inverse = m[0];
+    for _ in 0..4 {
+      inverse = inverse.wrapping_mul(2u32.wrapping_sub(m[0].wrapping_mul(inverse)));
+    }
+
+    let mut modulus = Modulus {
+      len,
+      m,
+      n0: inverse.wrapping_neg(),
+      r2: [0; LIMBS],
+      r3: [0; LIMBS],
+    };
+
+    // Double 1 until it's R^2, which is slow, but only happens once per key.
+    let mut r2 = one();
+    for _ in 0..64 * len {
+      let mut carry = 0;
+      for limb in r2[..len].iter_mut() {
+        let next_carry = *limb >> 31;
+        *limb = (*limb << 1) | carry;
+        carry = next_carry;
+      }
+      modulus.reduce_once(&mut r2, carry);
+    }
+    modulus.r2 = r2;
+    modulus.r3 = modulus.mul(&modulus.r2, &modulus.r2);
+    Ok(modulus)
+  }
+
+  /// Subtract m from a value that's less than 2m, with a carry out of the top limb.
+  fn reduce_once(&self, a: &mut Limbs, carry: u32) {
+    let len = self.len;
+    let mut difference = *a;
+    let borrow = sub_in_place(&mut difference[..len], &self.m[..len]);
+    let mask = 0u32.wrapping_sub(carry | (borrow ^ 1));
+    select(&mut a[..len], &difference[..len], mask);
+  }
+
+  /// a * b * R^-1 mod m, for a * b < R * m.
+  fn mul(&self, a: &[u32], b: &[u32]) -> Limbs {
+    let len = self.len;
+    let m = &self.m[..len];
+    let mut t = [0u32; LIMBS + 2];
+
+    for &b_i in &b[..len] {
+      let mut carry = 0u64;
+      for (t_j, &a_j) in t[..len].iter_mut().zip(&a[..len]) {
+        let sum = *t_j as u64 + a_j as u64 * b_i as u64 + carry;
+        *t_j = sum as u32;
+        carry = sum >> 32;
+      }
+      let sum = t[len] as u64 + carry;
+      t[len] = sum as u32;
+      t[len + 1] = (sum >> 32) as u32;
+
+      // Add a multiple of m that clears the bottom limb, and shift it out.
+      let u = t[0].wrapping_mul(self.n0) as u64;
+      let mut carry = (t[0] as u64 + u * m[0] as u64) >> 32;
+      for j in 1..len {
+        let sum = t[j] as u64 + u * m[j] as u64 + carry;
+        t[j - 1] = sum as u32;
+        carry = sum >> 32;
+      }
+      let sum = t[len] as u64 + carry;
+      t[len - 1] = sum as u32;
+      t[len] = t[len + 1] + (sum >> 32) as u32;
+    }
+
+    let mut result = [0; LIMBS];
+    result[..len].copy_from_slice(&t[..len]);
+    self.reduce_once(&mut result, t[len]);
+    result
+  }
+
+  /// a - b mod m, for a, b < m.
+  fn sub(&self, a: &[u32], b: &[u32]) -> Limbs {
+    let len = self.len;
+    let mut result = [0; LIMBS];
+    result[..len].copy_from_slice(&a[..len]);
+    let borrow = sub_in_place(&mut result[..len], &b[..len]);
+
+    let mut sum = result;
+    add_in_place(&mut sum[..len], &self.m[..len]);
+    select(&mut result[..len], &sum[..len], 0u32.wrapping_sub(borrow));
+    result
+  }
+
+  /// a + b mod m, for a, b < m.
+  fn add(&self, a: &[u32], b: &[u32]) -> Limbs {
+    let len = self.len;
+    let mut result = [0; LIMBS];
+    result[..len].copy_from_slice(&a[..len]);
+    let carry = add_in_place(&mut result[..len], &b[..len]);
+    self.reduce_once(&mut result, carry);
+    result
+  }
+
+  /// Convert a number that's twice as long as the modulus into the Montgomery domain.
+  fn reduce_wide(&self, a: &[u32]) -> Limbs {
+    let (low, high) = a.split_at(self.len);
+    // a * R = high * R^2 + low * R
+    self.add(&self.mul(high, &self.r3), &self.mul(low, &self.r2))
+  }
+
+  /// base^exponent, in the Montgomery domain, for a secret exponent.
+  fn exp(&self, base: &Limbs, exponent: &[u8]) -> Limbs {
+    assert!(self.len <= HALF_LIMBS);
+    let len = self.len;
+
+    let mut table = [[0u32; HALF_LIMBS]; 16];
+    let one = self.mul(&one(), &self.r2);
+    table[0][..len].copy_from_slice(&one[..len]);
+    table[1][..len].copy_from_slice(&base[..len]);
+    for i in 2..16 {
+      let entry = self.mul(&table[i - 1], base);
+      table[i][..len].copy_from_slice(&entry[..len]);
+    }
+
+    let mut result = one;
+    for byte in exponent {
+      for &window in &[byte >> 4, byte & 0xf] {
+        for _ in 0..4 {
+          result = self.mul(&result, &result);
+        }
+
+        let mut entry = [0u32; HALF_LIMBS];
+        for (i, row) in table.iter().enumerate() {
+          let is_window = ((i as u32 ^ window as u32).wrapping_sub(1)) >> 31;
+          select(&mut entry, row, 0u32.wrapping_sub(is_window));
+        }
+        result = self.mul(&result, &entry);
+      }
+    }
+    result
+  }
+
+  /// base^exponent, in the Montgomery domain, for a public exponent.
+  fn exp_public(&self, base: &Limbs, exponent: u32) -> Limbs {
+    let mut result = *base;
+    for bit in (0..31 - exponent.leading_zeros()).rev() {
+      result = self.mul(&result, &result);
+      if exponent & (1 << bit) != 0 {
+        result = self.mul(&result, base);
+      }
+    }
+    result
+  }
+}
+
+/// Length of DB, the masked part of the EMSA-PSS encoding.
+const DB_LEN: usize = SIGNATURE_LEN - HASH_LEN - 1;
+
+/// H = SHA256(0x00 * 8 || SHA256(message) || salt)
+fn pss_hash(message: &[u8], salt: &[u8]) -> [u8; HASH_LEN] {
+  let message_hash = sha256::digest(message);
+  let mut context = Sha256::new();
+  context.update(&[0u8; 8]);
+  context.update(&message_hash);
+  context.update(salt);
+  context.finish()
+}
+
+/// XOR MGF1-SHA256(seed) into data.
+fn mgf1_xor(seed: &[u8], data: &mut [u8]) {
+  for (counter, chunk) in data.chunks_mut(HASH_LEN).enumerate() {
+    let mut context = Sha256::new();
+    context.update(seed);
+    context.update(&(counter as u32).to_be_bytes());
+    for (byte, mask) in chunk.iter_mut().zip(context.finish().iter()) {
+      *byte ^= mask;
+    }
+  }
+}
+
+/// Fill in the EMSA-PSS encoding of a message for a 2048-bit modulus, with SHA-256 as both the hash and MGF1's hash.
+fn pss_encode(message: &[u8], salt: &[u8; SALT_LEN], encoded: &mut [u8; SIGNATURE_LEN]) {
+  let hash = pss_hash(message, salt);
+
+  // maskedDB || H || 0xbc, where DB = 0x00... || 0x01 || salt.
+  let (db, trailer) = encoded.split_at_mut(DB_LEN);
+  for byte in db.iter_mut() {
+    *byte = 0;
+  }
+  db[DB_LEN - SALT_LEN - 1] = 0x01;
+  db[DB_LEN - SALT_LEN..].copy_from_slice(salt);
+  mgf1_xor(&hash, db);
+
+  // The encoding is one bit shorter than the modulus, so that it's always smaller.
+  db[0] &= 0x7f;
+  trailer[..HASH_LEN].copy_from_slice(&hash);
+  trailer[HASH_LEN] = 0xbc;
+}
+
+/// Check an EMSA-PSS encoding produced by pss_encode, with any salt.
+fn pss_verify(message: &[u8], encoded: &[u8; SIGNATURE_LEN]) -> bool {
+  let (masked_db, trailer) = encoded.split_at(DB_LEN);
+  if trailer[HASH_LEN] != 0xbc || masked_db[0] & 0x80 != 0 {
+    return false;
+  }
+
+  let hash = &trailer[..HASH_LEN];
+  let mut db = [0u8; DB_LEN];
+  db.copy_from_slice(masked_db);
+  mgf1_xor(hash, &mut db);
+  db[0] &= 0x7f;
+
+  let (padding, salt) = db.split_at(DB_LEN - SALT_LEN);
+  let (zeroes, one) = padding.split_at(padding.len() - 1);
+  if zeroes.iter().any(|&byte| byte != 0) || one[0] != 0x01 {
+    return false;
+  }
+
+  &pss_hash(message, salt)[..] == hash
+}
+
+/// The public exponent, which has to be small and odd.
+fn public_exponent(bytes: &[u8]) -> Result<u32, KeyError> {
+  let (high, low) = bytes.split_at(bytes.len().saturating_sub(4));
+  if high.iter().any(|&byte| byte != 0) {
+    return Err(KeyError::TooLarge("e"));
+  }
+
+  let e = low.iter().fold(0u32, |e, &byte| e << 8 | byte as u32);
+  if e < 3 || e & 1 == 0 {
+    return Err(KeyError::Inconsistent("e"));
+  }
+  Ok(e)
+}
+
+/// Check an RSA-PSS-SHA256 signature made by a 2048-bit key, with a salt as long as the digest.
+pub fn verify_pss_sha256(n: &[u8; SIGNATURE_LEN], e: &[u8], message: &[u8], signature: &[u8; SIGNATURE_LEN]) -> bool {
+  let e = match public_exponent(e) {
+    Ok(e) => e,
+    Err(_) => return false,
+  };
+
+  if n[0] & 0x80 == 0 {
+    return false;
+  }
+  let n = match Modulus::new(n, "n") {
+    Ok(n) => n,
+    Err(_) => return false,
+  };
+
+  let mut s = [0; LIMBS];
+  limbs_from_be_bytes(signature, &mut s);
+  let mut difference = s;
+  if sub_in_place(&mut difference, &n.m) == 0 {
+    // s >= n
+    return false;
+  }
+
+  let m = n.mul(&n.exp_public(&n.mul(&s, &n.r2), e), &one());
+  let mut encoded = [0u8; SIGNATURE_LEN];
+  limbs_to_be_bytes(&m, &mut encoded);
+  pss_verify(message, &encoded)
+}
+
+/// A 2048-bit RSA private key, with everything precomputed that doesn't depend on the message.
+pub struct PrivateKey {
+  n: Modulus,
+  e: u32,
+  p: Modulus,
+  q: Modulus,
+  dp: [u8; 128],
+  dq: [u8; 128],
+  qinv: Limbs,
+}
+
+impl PrivateKey {
+  /// The key isn't checked for consistency, beyond what's needed to not panic, but every signature is.
+  pub fn from_encoded(encoded: &DS4KeyEncoded) -> Result<PrivateKey, KeyError> {
+    let e = public_exponent(&encoded.e)?;
+    let mut qinv = [0; LIMBS];
+    limbs_from_be_bytes(&encoded.qinv, &mut qinv[..HALF_LIMBS]);
+
+    Ok(PrivateKey {
+      n: Modulus::new(&encoded.n, "n")?,
+      e,
+      p: Modulus::new(&encoded.p, "p")?,
+      q: Modulus::new(&encoded.q, "q")?,
+      dp: encoded.dp,
+      dq: encoded.dq,
+      qinv,
+    })
+  }
+
+  /// Sign a message with RSA-PSS-SHA256, with a salt derived from the key and the message, since there's nothing to draw
+  /// a random one from. Signing the same message twice gives the same signature, which is fine for PSS.
+  pub fn sign(&self, message: &[u8], signature: &mut [u8; SIGNATURE_LEN]) -> Result<(), KeyError> {
+    let mut context = Sha256::new();
+    context.update(&self.dp);
+    context.update(&self.dq);
+    context.update(message);
+
+    let salt = context.finish();
+    self.sign_pss_sha256(message, &salt, signature)
+  }
+
+  /// Sign a message with RSA-PSS, using SHA-256 for the message digest and MGF1, and a salt as long as the digest.
+  pub fn sign_pss_sha256(
+    &self,
+    message: &[u8],
+    salt: &[u8; SALT_LEN],
+    signature: &mut [u8; SIGNATURE_LEN],
+  ) -> Result<(), KeyError> {
+    let mut encoded = [0u8; SIGNATURE_LEN];
+    pss_encode(message, salt, &mut encoded);
+    let mut m = [0; LIMBS];
+    limbs_from_be_bytes(&encoded, &mut m);
+
+    let (p, q) = (&self.p, &self.q);
+    let s_p = p.exp(&p.reduce_wide(&m), &self.dp);
+    let s_q = q.mul(&q.exp(&q.reduce_wide(&m), &self.dq), &one());
+
+    // Garner's formula: s = s_q + q * (qinv * (s_p - s_q) mod p).
+    let difference = p.sub(&s_p, &p.mul(&s_q, &p.r2));
+    let h = p.mul(&difference, &self.qinv);
+
+    let mut s = [0; LIMBS];
+    for (i, &h_i) in h[..HALF_LIMBS].iter().enumerate() {
+      let mut carry = 0u64;
+      for (s_j, &q_j) in s[i..i + HALF_LIMBS].iter_mut().zip(&q.m[..HALF_LIMBS]) {
+        let sum = *s_j as u64 + h_i as u64 * q_j as u64 + carry;
+        *s_j = sum as u32;
+        carry = sum >> 32;
+      }
+      s[i + HALF_LIMBS] = carry as u32;
+    }
+    add_in_place(&mut s, &s_q);
+
+    let n = &self.n;
+    let check = n.mul(&n.exp_public(&n.mul(&s, &n.r2), self.e), &one());
+    if check[..] != m[..] {
+      error!("RSA signature failed verification");
+      return Err(KeyError::Inconsistent("signature doesn't verify"));
+    }
+
+    limbs_to_be_bytes(&s, signature);
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use ring::signature::{KeyPair, RsaKeyPair, RSA_PSS_2048_8192_SHA256, RSA_PSS_SHA256};
+
+  const TEST_KEY: &[u8] = include_bytes!("../testdata/rsa2048.der");
+
+  fn encoded() -> DS4KeyEncoded {
+    DS4KeyEncoded::from_der(TEST_KEY, &[0x11; 16], &[0x22; 256]).unwrap()
+  }
+
+  #[test]
+  fn matches_ring() {
+    let key = PrivateKey::from_encoded(&encoded()).unwrap();
+    let keypair = RsaKeyPair::from_der(TEST_KEY).unwrap();
+
+    for (i, message) in [&[0u8; 256][..], &[0x5a; 256][..], b"", b"passinglink"]
+      .iter()
+      .enumerate()
+    {
+      let salt = [i as u8 * 37; SALT_LEN];
+      let mut signature = [0u8; SIGNATURE_LEN];
+      key.sign_pss_sha256(message, &salt, &mut signature).unwrap();
+
+      let mut expected = [0u8; SIGNATURE_LEN];
+      let rng = ring::test::rand::FixedSliceRandom { bytes: &salt };
+      keypair.sign(&RSA_PSS_SHA256, &rng, message, &mut expected).unwrap();
+      assert_eq!(&expected[..], &signature[..]);
+
+      let public_key = ring::signature::UnparsedPublicKey::new(&RSA_PSS_2048_8192_SHA256, keypair.public_key());
+      assert!(public_key.verify(message, &signature).is_ok());
+    }
+  }
+
+  #[test]
+  fn verify() {
+    let encoded = encoded();
+    let key = PrivateKey::from_encoded(&encoded).unwrap();
+    let keypair = RsaKeyPair::from_der(TEST_KEY).unwrap();
+    let message = [0x5a; 256];
+
+    let mut signature = [0u8; SIGNATURE_LEN];
+    key.sign(&message, &mut signature).unwrap();
+    assert!(verify_pss_sha256(&encoded.n, &encoded.e, &message, &signature));
+    assert!(!verify_pss_sha256(&encoded.n, &encoded.e, &message[1..], &signature));
+
+    // Signing is deterministic, but the salt still depends on the message.
+    let mut again = [0u8; SIGNATURE_LEN];
+    key.sign(&message, &mut again).unwrap();
+    assert_eq!(&signature[..], &again[..]);
+    key.sign(&message[1..], &mut again).unwrap();
+    assert_ne!(&signature[..], &again[..]);
+
+    let rng = ring::rand::SystemRandom::new();
+    keypair.sign(&RSA_PSS_SHA256, &rng, &message, &mut signature).unwrap();
+    assert!(verify_pss_sha256(&encoded.n, &encoded.e, &message, &signature));
+
+    signature[100] ^= 1;
+    assert!(!verify_pss_sha256(&encoded.n, &encoded.e, &message, &signature));
+  }
+
+  #[test]
+  fn bad_key() {
+    let mut encoded = encoded();
+    encoded.dp[64] ^= 1;
+    let key = PrivateKey::from_encoded(&encoded).unwrap();
+    let mut signature = [0u8; SIGNATURE_LEN];
+    assert!(key.sign_pss_sha256(b"", &[0; SALT_LEN], &mut signature).is_err());
+
+    encoded.p[127] &= !1;
+    assert!(PrivateKey::from_encoded(&encoded).is_err());
+  }
+}
@@ -0,0 +1,167 @@
+//! SHA-256, for the RSA-PSS message digest and MGF1.
+
+const K: [u32; 64] = [
+  0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5, 0xd807aa98,
+  0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786,
+  0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8,
+  0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13,
+  0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819,
+  0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a,
+  0x5b9cca4f, 0x682e6ff3, 0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7,
+  0xc67178f2,
+];
+
+const INITIAL_STATE: [u32; 8] = [
+  0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+pub const DIGEST_LEN: usize = 32;
+const BLOCK_LEN: usize = 64;
+
+pub struct Sha256 {
+  state: [u32; 8],
+  block: [u8; BLOCK_LEN],
+
+  /// Number of bytes in block.
+  buffered: usize,
+
+  /// Total number of bytes hashed.
+  length: u64,
+}
+
+impl Sha256 {
+  pub fn new() -> Sha256 {
+    Sha256 {
+      state: INITIAL_STATE,
+      block: [0; BLOCK_LEN],
+      buffered: 0,
+      length: 0,
+    }
+  }
+
+  pub fn update(&mut self, mut data: &[u8]) {
+    self.length += data.len() as u64;
+    while !data.is_empty() {
+      let len = (BLOCK_LEN - self.buffered).min(data.len());
+      self.block[self.buffered..self.buffered + len].copy_from_slice(&data[..len]);
+      self.buffered += len;
+      data = &data[len..];
+
+      if self.buffered == BLOCK_LEN {
+        compress(&mut self.state, &self.block);
+        self.buffered = 0;
+      }
+    }
+  }
+
+  pub fn finish(mut self) -> [u8; DIGEST_LEN] {
+    let bits = self.length.wrapping_mul(8);
+
+    // 0x80, then zeroes until there's exactly room for the length at the end of a block.
+    let padding = if self.buffered < BLOCK_LEN - 8 {
+      BLOCK_LEN - 8 - self.buffered
+    } else {
+      2 * BLOCK_LEN - 8 - self.buffered
+    };
+    let mut pad = [0u8; BLOCK_LEN];
+    pad[0] = 0x80;
+    self.update(&pad[..padding]);
+    self.update(&bits.to_be_bytes());
+    debug_assert_eq!(0, self.buffered);
+
+    let mut digest = [0u8; DIGEST_LEN];
+    for (chunk, word) in digest.chunks_mut(4).zip(self.state.iter()) {
+      chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+  }
+}
+
+pub fn digest(data: &[u8]) -> [u8; DIGEST_LEN] {
+  let mut context = Sha256::new();
+  context.update(data);
+  context.finish()
+}
+
+fn compress(state: &mut [u32; 8], block: &[u8; BLOCK_LEN]) {
+  let mut w = [0u32; 64];
+  for (word, chunk) in w.iter_mut().zip(block.chunks(4)) {
+    *word = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+  }
+  for i in 16..64 {
+    let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+    let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+    w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+  }
+
+  let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+  for i in 0..64 {
+    let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+    let ch = (e & f) ^ (!e & g);
+    let t1 = h
+      .wrapping_add(s1)
+      .wrapping_add(ch)
+      .wrapping_add(K[i])
+      .wrapping_add(w[i]);
+    let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+    let maj = (a & b) ^ (a & c) ^ (b & c);
+    let t2 = s0.wrapping_add(maj);
+
+    h = g;
+    g = f;
+    f = e;
+    e = d.wrapping_add(t1);
+    d = c;
+    c = b;
+    b = a;
+    a = t1.wrapping_add(t2);
+  }
+
+  for (word, value) in state.iter_mut().zip([a, b, c, d, e, f, g, h].iter()) {
+    *word = word.wrapping_add(*value);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn known_answers() {
+    assert_eq!(
+      [
+        0xe3, 0xb0, 0xc4, 0x42, 0x98, 0xfc, 0x1c, 0x14, 0x9a, 0xfb, 0xf4, 0xc8, 0x99, 0x6f, 0xb9, 0x24, 0x27, 0xae,
+        0x41, 0xe4, 0x64, 0x9b, 0x93, 0x4c, 0xa4, 0x95, 0x99, 0x1b, 0x78, 0x52, 0xb8, 0x55,
+      ],
+      digest(b"")
+    );
+    assert_eq!(
+      [
+        0xba, 0x78, 0x16, 0xbf, 0x8f, 0x01, 0xcf, 0xea, 0x41, 0x41, 0x40, 0xde, 0x5d, 0xae, 0x22, 0x23, 0xb0, 0x03,
+        0x61, 0xa3, 0x96, 0x17, 0x7a, 0x9c, 0xb4, 0x10, 0xff, 0x61, 0xf2, 0x00, 0x15, 0xad,
+      ],
+      digest(b"abc")
+    );
+  }
+
+  #[test]
+  fn matches_ring() {
+    let data: [u8; 300] = {
+      let mut data = [0u8; 300];
+      for (i, byte) in data.iter_mut().enumerate() {
+        *byte = (i * 7 + 3) as u8;
+      }
+      data
+    };
+
+    // Every length around the block and padding boundaries, fed in uneven pieces.
+    for len in 0..data.len() {
+      let mut context = Sha256::new();
+      for piece in data[..len].chunks(13) {
+        context.update(piece);
+      }
+      let expected = ring::digest::digest(&ring::digest::SHA256, &data[..len]);
+      assert_eq!(expected.as_ref(), &context.finish()[..], "len = {}", len);
+    }
+  }
+}
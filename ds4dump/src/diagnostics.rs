@@ -0,0 +1,69 @@
+//! Reading the handshake and signing statistics from the vendor diagnostics report.
+
+use std::convert::TryInto;
+
+use passinglink_core::auth::{self, AuthStateType, AuthStats, SigningStats};
+
+use crate::transport::Transport;
+
+/// Contents of the 0xa3 report.
+#[derive(Clone, Copy, Debug)]
+pub struct Diagnostics {
+  pub state: AuthStateType,
+  pub handshakes: AuthStats,
+
+  /// In microseconds.
+  pub signing: SigningStats,
+}
+
+pub fn read_diagnostics(transport: &mut dyn Transport) -> Result<Diagnostics, String> {
+  let buf = transport.get_feature_report(auth::REPORT_DIAGNOSTICS, auth::DIAGNOSTICS_REPORT_SIZE)?;
+  if buf.len() != auth::DIAGNOSTICS_REPORT_SIZE || buf[0] != auth::REPORT_DIAGNOSTICS {
+    return Err(format!("malformed 0xa3 report: {:x?}", buf));
+  }
+
+  let state = AuthStateType::from_u8(buf[1]).ok_or_else(|| format!("unknown auth state {}", buf[1]))?;
+  let value = |i: usize| u32::from_le_bytes(buf[4 + 4 * i..8 + 4 * i].try_into().unwrap());
+  Ok(Diagnostics {
+    state,
+    handshakes: AuthStats {
+      completed: value(0),
+      aborted: value(1),
+      timed_out: value(2),
+    },
+    signing: SigningStats {
+      count: value(3),
+      last: value(4),
+      min: value(5),
+      average: value(6),
+      max: value(7),
+    },
+  })
+}
+
+fn millis(micros: u32) -> f64 {
+  micros as f64 / 1000.0
+}
+
+pub fn print(diagnostics: &Diagnostics) {
+  let handshakes = &diagnostics.handshakes;
+  let signing = &diagnostics.signing;
+  println!("auth state: {:?}", diagnostics.state);
+  println!(
+    "handshakes: {} completed, {} aborted, {} timed out",
+    handshakes.completed, handshakes.aborted, handshakes.timed_out
+  );
+
+  if signing.count == 0 {
+    println!("signing: no signatures yet");
+  } else {
+    println!(
+      "signing: {} signatures, last {:.1} ms, min {:.1} ms, average {:.1} ms, max {:.1} ms",
+      signing.count,
+      millis(signing.last),
+      millis(signing.min),
+      millis(signing.average),
+      millis(signing.max)
+    );
+  }
+}
@@ -0,0 +1,126 @@
+use std::time::{Duration, Instant};
+
+use passinglink_core::provision;
+
+use crate::transport::Transport;
+
+/// How long to wait for the device to sign a nonce. The firmware takes a few seconds.
+pub const SIGNING_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+pub fn crc(data: &[u8]) -> u32 {
+  crc::crc32::checksum_ieee(data)
+}
+
+/// Build the 64 byte 0xf0 report carrying one part of a nonce.
+pub fn nonce_packet(nonce: &[u8; 256], nonce_id: u8, part: u8) -> [u8; 64] {
+  let start = 56 * part as usize;
+  let nonce_len = if part == 4 { 32 } else { 56 };
+  provision::packet(0xf0, [nonce_id, part], &nonce[start..start + nonce_len])
+}
+
+pub fn send_nonce(transport: &mut dyn Transport, nonce: &[u8; 256], nonce_id: u8) -> Result<(), String> {
+  for part in 0..5 {
+    transport
+      .send_feature_report(&nonce_packet(nonce, nonce_id, part))
+      .map_err(|err| format!("nonce {} part {}/5: {}", nonce_id, part + 1, err))?;
+  }
+  Ok(())
+}
+
+/// Send a nonce, retrying the first part until the device accepts it, in case it's still busy with a previous one.
+pub fn send_nonce_when_idle(transport: &mut dyn Transport, nonce: &[u8; 256], nonce_id: u8) -> Result<(), String> {
+  let deadline = Instant::now() + SIGNING_TIMEOUT;
+  while transport
+    .send_feature_report(&nonce_packet(nonce, nonce_id, 0))
+    .is_err()
+  {
+    if Instant::now() > deadline {
+      return Err(format!("device never accepted nonce {}", nonce_id));
+    }
+    std::thread::sleep(POLL_INTERVAL);
+  }
+
+  for part in 1..5 {
+    transport
+      .send_feature_report(&nonce_packet(nonce, nonce_id, part))
+      .map_err(|err| format!("nonce {} part {}/5: {}", nonce_id, part + 1, err))?;
+  }
+  Ok(())
+}
+
+/// Contents of the 0xf2 report.
+#[derive(Clone, Copy, Debug)]
+pub struct Status {
+  pub nonce_id: u8,
+  pub ready: bool,
+}
+
+pub fn read_status(transport: &mut dyn Transport) -> Result<Status, String> {
+  let buf = transport.get_feature_report(0xf2, 16)?;
+  if buf.len() < 3 || buf[0] != 0xf2 {
+    return Err(format!("malformed 0xf2 report: {:x?}", buf));
+  }
+
+  Ok(Status {
+    nonce_id: buf[1],
+    ready: buf[2] == 0,
+  })
+}
+
+pub fn wait_for_signature(transport: &mut dyn Transport, nonce_id: u8) -> Result<(), String> {
+  let deadline = Instant::now() + SIGNING_TIMEOUT;
+  loop {
+    let status = read_status(transport)?;
+    if status.ready {
+      if status.nonce_id != nonce_id {
+        return Err(format!(
+          "signature ready for nonce {}, expected {}",
+          status.nonce_id, nonce_id
+        ));
+      }
+      return Ok(());
+    }
+
+    if Instant::now() > deadline {
+      return Err(format!("timed out waiting for signature of nonce {}", nonce_id));
+    }
+    std::thread::sleep(POLL_INTERVAL);
+  }
+}
+
+pub fn read_signature(transport: &mut dyn Transport, nonce_id: u8) -> Result<[u8; 1064], String> {
+  let mut signature = [0u8; 1064];
+  for part in 0..19 {
+    let buf = transport.get_feature_report(0xf1, 64)?;
+    if buf.len() != 64 || buf[0] != 0xf1 {
+      return Err(format!("malformed 0xf1 report: {:x?}", buf));
+    }
+
+    if buf[1] != nonce_id || buf[2] != part as u8 {
+      return Err(format!(
+        "expected nonce {} part {}, received nonce {} part {}",
+        nonce_id, part, buf[1], buf[2]
+      ));
+    }
+
+    if crc(&buf[..60]).to_le_bytes() != buf[60..] {
+      return Err(format!("crc mismatch in signature part {}", part));
+    }
+
+    signature[(part * 56)..((part + 1) * 56)].copy_from_slice(&buf[4..60]);
+  }
+  Ok(signature)
+}
+
+/// Run a complete handshake, and check that the response is a valid signature of the nonce.
+pub fn authenticate(transport: &mut dyn Transport, nonce: &[u8; 256], nonce_id: u8) -> Result<[u8; 1064], String> {
+  send_nonce_when_idle(transport, nonce, nonce_id)?;
+  wait_for_signature(transport, nonce_id)?;
+  let signature = read_signature(transport, nonce_id)?;
+  if !ds4auth::DS4Signature::parse(signature).validate(nonce) {
+    return Err(format!("invalid signature for nonce {}", nonce_id));
+  }
+  Ok(signature)
+}
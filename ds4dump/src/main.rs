@@ -1,116 +1,102 @@
 use std::time::Duration;
 
-use hidapi::HidDevice;
 use ring::signature::{KeyPair, RsaKeyPair};
 
 use ds4auth::*;
 
-fn read(device: &HidDevice, report: u8, length: usize) -> Option<Vec<u8>> {
-  let mut result = vec![0; length];
-  result[0] = report;
-  if let Ok(bytes) = device.get_feature_report(&mut result) {
-    result.resize(bytes, 0);
-    Some(result)
-  } else {
-    None
-  }
+mod diagnostics;
+mod handshake;
+mod provision;
+mod scenarios;
+mod transport;
+
+use transport::{CoreTransport, HidapiTransport, MockUsbTransport, Transport};
+
+/// How long the simulated devices take to sign a nonce.
+const SIMULATED_SIGNING_DELAY: Duration = Duration::from_millis(250);
+
+fn usage() -> ! {
+  eprintln!("usage: ds4dump [dump]");
+  eprintln!("       ds4dump test [hidapi|core|mock-usb]");
+  eprintln!("       ds4dump provision KEY");
+  eprintln!("       ds4dump diag");
+  eprintln!();
+  eprintln!("  dump: authenticate against a physical device, and compare the response against ../keys");
+  eprintln!("  test: run handshake conformance scenarios against a physical device (hidapi), the firmware's");
+  eprintln!("        auth state machine called directly (core), or the firmware's USB HID class over a mock bus");
+  eprintln!("        (mock-usb)");
+  eprintln!("  provision: write a key in the DS4KeyEncoded format to a physical device, which must have been plugged");
+  eprintln!("             in with Start held");
+  eprintln!("  diag: show a physical device's handshake counts and signing times");
+  std::process::exit(1);
 }
 
-fn crc(data: &[u8]) -> u32 {
-  crc::crc32::checksum_ieee(data)
-}
+fn open_transport(name: &str) -> Result<Box<dyn Transport>, String> {
+  if name == "hidapi" {
+    return Ok(Box::new(HidapiTransport::open()?));
+  }
 
-fn read_with_crc(device: &HidDevice, report: u8, length: usize) -> Option<Vec<u8>> {
-  let buf = read(device, report, length);
-  if let Some(bytes) = buf {
-    let calculated_crc = crc(&bytes[..(bytes.len() - 4)]).to_le_bytes();
-    let actual_crc = &bytes[(bytes.len() - 4)..];
-    if &calculated_crc != actual_crc {
-      println!("warning: crc mismatch");
-    }
-    Some(bytes)
-  } else {
-    None
+  let key = DS4Key::embedded().ok_or_else(|| "failed to load key".to_string())?;
+  match name {
+    "core" => Ok(Box::new(CoreTransport::new(key, SIMULATED_SIGNING_DELAY))),
+    "mock-usb" => Ok(Box::new(MockUsbTransport::new(key, SIMULATED_SIGNING_DELAY)?)),
+    _ => usage(),
   }
 }
 
-fn write_with_crc(device: &HidDevice, data: &[u8]) {
-  let mut buf = Vec::new();
-  buf.extend_from_slice(data);
-  let checksum = crc(data).to_le_bytes();
-  buf.extend_from_slice(&checksum[..]);
-  println!("writing: {:x?}", buf);
-  device.send_feature_report(&buf).unwrap()
-}
+fn test(transport_name: &str) {
+  let mut transport = open_transport(transport_name).unwrap_or_else(|err| {
+    eprintln!("error: {}", err);
+    std::process::exit(1);
+  });
 
-fn send_nonce(device: &HidDevice, nonce: &[u8; 256], nonce_id: u8) {
-  let mut buf = [0u8; 60];
-  println!("sending nonce");
-  for i in 0..5 {
-    buf[0] = 0xf0;
-    buf[1] = nonce_id;
-    buf[2] = i;
-
-    let start = 56 * i as usize;
-    let nonce_len = if i == 4 { 32 } else { 56 };
-    let nonce_data = &nonce[start..start + nonce_len];
-
-    buf[4..4 + nonce_len].copy_from_slice(nonce_data);
-    println!("writing nonce {}: {}/5", nonce_id, i + 1);
-    write_with_crc(device, &buf);
+  if !scenarios::run(transport.as_mut()) {
+    std::process::exit(1);
   }
 }
 
-fn read_signature(device: &HidDevice) -> [u8; 1064] {
-  let mut signature = [0u8; 1064];
-  for i in 0..19 {
-    let buf = read_with_crc(device, 0xf1, 64).expect("failed to read signature");
-    println!("0xf1 = {:?}", buf);
-    assert_eq!(i as u8, buf[2]);
-    signature[(i * 56)..((i + 1) * 56)].copy_from_slice(&buf[4..60]);
+fn provision(path: &str) {
+  let key = std::fs::read(path).unwrap_or_else(|err| {
+    eprintln!("error: failed to read {}: {}", path, err);
+    std::process::exit(1);
+  });
+
+  let result = HidapiTransport::open().and_then(|mut transport| provision::provision(&mut transport, &key));
+  if let Err(err) = result {
+    eprintln!("error: {}", err);
+    std::process::exit(1);
   }
-  signature
+  println!("key provisioned");
 }
 
-pub fn main() {
-  let hidapi = hidapi::HidApi::new().unwrap();
-  let devices = [
-    (0x054c, 0x05c4), // Sony DualShock 4
-    (0x1532, 0x0401), // Razer Panthera
-    (0x1209, 0x214d), // Passing Link
-  ];
-
-  let device = devices
-    .iter()
-    .find_map(|(vid, pid)| hidapi.open(*vid, *pid).ok())
-    .expect("failed to open device");
-
-  let manufacturer = device.get_manufacturer_string().unwrap().unwrap();
-  let product = device.get_product_string().unwrap().unwrap();
-  println!("Successfully opened {} {}", manufacturer, product);
+fn diag() {
+  let result = HidapiTransport::open().and_then(|mut transport| diagnostics::read_diagnostics(&mut transport));
+  match result {
+    Ok(diagnostics) => diagnostics::print(&diagnostics),
+    Err(err) => {
+      eprintln!("error: {}", err);
+      std::process::exit(1);
+    }
+  }
+}
 
-  device.set_blocking_mode(true).expect("failed to set blocking mode");
+fn dump() {
+  let mut transport = HidapiTransport::open().expect("failed to open device");
+  let transport: &mut dyn Transport = &mut transport;
 
   let nonce = [0; 256];
 
-  let f2 = read_with_crc(&device, 0xf2, 16).expect("failed to read 0xf2");
+  let f2 = handshake::read_status(transport).expect("failed to read 0xf2");
   println!("0xf2 = {:?}", f2);
 
   std::thread::sleep(Duration::from_millis(1000));
 
-  send_nonce(&device, &nonce, 1);
-
-  loop {
-    let f2 = read_with_crc(&device, 0xf2, 16).expect("failed to read 0xf2");
-    println!("0xf2 = {:?}", f2);
-    if f2[2] == 0 {
-      break;
-    }
-
-    std::thread::sleep(Duration::from_millis(1000));
-  }
+  println!("sending nonce");
+  handshake::send_nonce(transport, &nonce, 1).expect("failed to send nonce");
+  handshake::wait_for_signature(transport, 1).expect("failed to wait for signature");
 
-  let signature = DS4Signature::parse(read_signature(&device));
+  let signature = handshake::read_signature(transport, 1).expect("failed to read signature");
+  let signature = DS4Signature::parse(signature);
   println!("received signature: {:?}", signature);
   if signature.validate(&nonce) {
     println!("valid signature received");
@@ -140,3 +126,15 @@ pub fn main() {
   assert_eq!(serial, signature.serial.as_ref());
   println!("serial matches");
 }
+
+pub fn main() {
+  let args: Vec<String> = std::env::args().skip(1).collect();
+  match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+    [] | ["dump"] => dump(),
+    ["test"] => test("hidapi"),
+    ["test", transport] => test(transport),
+    ["provision", path] => provision(path),
+    ["diag"] => diag(),
+    _ => usage(),
+  }
+}
@@ -0,0 +1,68 @@
+//! Writing a DS4 key to a device with the vendor feature reports in passinglink_core::provision.
+
+use std::convert::TryFrom;
+use std::time::Instant;
+
+use passinglink_core::provision::{self, ProvisionState, KEY_SIZE, PART_COUNT, PART_SIZE};
+
+use crate::handshake::{crc, POLL_INTERVAL, SIGNING_TIMEOUT};
+use crate::transport::Transport;
+
+/// Contents of the 0xa2 report.
+pub fn read_status(transport: &mut dyn Transport) -> Result<(ProvisionState, u8), String> {
+  let buf = transport
+    .get_feature_report(provision::REPORT_KEY_STATUS, 16)
+    .map_err(|err| format!("{} (was the device plugged in with Start held?)", err))?;
+  if buf.len() < 3 || buf[0] != provision::REPORT_KEY_STATUS {
+    return Err(format!("malformed 0xa2 report: {:x?}", buf));
+  }
+
+  let state = ProvisionState::from_u8(buf[1]).ok_or_else(|| format!("unknown provisioning state {}", buf[1]))?;
+  Ok((state, buf[2]))
+}
+
+/// Send a DS4KeyEncoded blob to the device, and wait for it to be written to flash.
+pub fn provision(transport: &mut dyn Transport, key: &[u8]) -> Result<(), String> {
+  let key =
+    <&[u8; KEY_SIZE]>::try_from(key).map_err(|_| format!("key is {} bytes, expected {}", key.len(), KEY_SIZE))?;
+
+  // Catch bad keys here, instead of waiting for the device to reject them.
+  provision::test_sign(key).map_err(|_| "key failed to produce a valid signature".to_string())?;
+
+  let (state, _) = read_status(transport)?;
+  if state == ProvisionState::Pending || state == ProvisionState::Busy {
+    return Err("device is busy with a previous key".to_string());
+  }
+
+  for part in 0..PART_COUNT {
+    let start = part as usize * PART_SIZE;
+    let end = (start + PART_SIZE).min(KEY_SIZE);
+    transport
+      .send_feature_report(&provision::packet(
+        provision::REPORT_KEY_DATA,
+        [part, 0],
+        &key[start..end],
+      ))
+      .map_err(|err| format!("key part {}/{}: {}", part + 1, PART_COUNT, err))?;
+  }
+
+  let key_crc = crc(key).to_le_bytes();
+  transport
+    .send_feature_report(&provision::packet(provision::REPORT_KEY_COMMIT, [0, 0], &key_crc))
+    .map_err(|err| format!("failed to commit key: {}", err))?;
+
+  let deadline = Instant::now() + SIGNING_TIMEOUT;
+  loop {
+    match read_status(transport)?.0 {
+      ProvisionState::Pending | ProvisionState::Busy => {}
+      ProvisionState::Done => return Ok(()),
+      ProvisionState::Failed => return Err("device failed to verify or write the key".to_string()),
+      state => return Err(format!("device returned to state {:?}", state)),
+    }
+
+    if Instant::now() > deadline {
+      return Err("timed out waiting for the key to be written".to_string());
+    }
+    std::thread::sleep(POLL_INTERVAL);
+  }
+}
@@ -0,0 +1,156 @@
+//! Conformance scenarios for the authentication handshake.
+//! Each one should leave the device ready for the next, as long as the device behaves.
+
+use crate::diagnostics::read_diagnostics;
+use crate::handshake::*;
+use crate::transport::Transport;
+
+type Scenario = fn(&mut dyn Transport) -> Result<(), String>;
+
+pub const SCENARIOS: &[(&str, Scenario)] = &[
+  ("full handshake", full_handshake),
+  ("re-auth with a new nonce", reauth),
+  ("wrong nonce id", wrong_nonce_id),
+  ("out-of-order parts", out_of_order_parts),
+  ("crc mismatch", crc_mismatch),
+  ("reset mid-signing", reset_mid_signing),
+  ("diagnostics", diagnostics),
+];
+
+/// Deterministic, but different for each seed.
+fn nonce(seed: u8) -> [u8; 256] {
+  let mut state = 0x2545_f491u32 ^ seed as u32;
+  let mut nonce = [0u8; 256];
+  for byte in nonce.iter_mut() {
+    state ^= state << 13;
+    state ^= state >> 17;
+    state ^= state << 5;
+    *byte = state as u8;
+  }
+  nonce
+}
+
+fn expect_rejected(transport: &mut dyn Transport, packet: &[u8], description: &str) -> Result<(), String> {
+  match transport.send_feature_report(packet) {
+    Ok(()) => Err(format!("device accepted {}", description)),
+    Err(_) => Ok(()),
+  }
+}
+
+fn expect_accepted(transport: &mut dyn Transport, packet: &[u8], description: &str) -> Result<(), String> {
+  transport
+    .send_feature_report(packet)
+    .map_err(|err| format!("device rejected {}: {}", description, err))
+}
+
+fn full_handshake(transport: &mut dyn Transport) -> Result<(), String> {
+  authenticate(transport, &nonce(1), 1).map(|_| ())
+}
+
+fn reauth(transport: &mut dyn Transport) -> Result<(), String> {
+  let first = authenticate(transport, &nonce(2), 2)?;
+  let second = authenticate(transport, &nonce(3), 3)?;
+  if first[..256] == second[..256] {
+    return Err("device returned the same signature for different nonces".to_string());
+  }
+  Ok(())
+}
+
+fn wrong_nonce_id(transport: &mut dyn Transport) -> Result<(), String> {
+  let nonce = nonce(4);
+  expect_accepted(transport, &nonce_packet(&nonce, 4, 0), "part 1")?;
+  expect_accepted(transport, &nonce_packet(&nonce, 4, 1), "part 2")?;
+  expect_rejected(
+    transport,
+    &nonce_packet(&nonce, 5, 2),
+    "part 3 with a different nonce id",
+  )?;
+
+  // The partial nonce should have been thrown away.
+  expect_rejected(transport, &nonce_packet(&nonce, 4, 3), "part 4 after a reset")?;
+  authenticate(transport, &nonce, 6).map(|_| ())
+}
+
+fn out_of_order_parts(transport: &mut dyn Transport) -> Result<(), String> {
+  let nonce = nonce(7);
+  expect_rejected(transport, &nonce_packet(&nonce, 7, 1), "part 2 as the first packet")?;
+
+  expect_accepted(transport, &nonce_packet(&nonce, 7, 0), "part 1")?;
+  expect_rejected(transport, &nonce_packet(&nonce, 7, 2), "part 3 following part 1")?;
+
+  expect_accepted(transport, &nonce_packet(&nonce, 7, 0), "part 1 after a reset")?;
+  expect_accepted(transport, &nonce_packet(&nonce, 7, 1), "part 2")?;
+  expect_rejected(transport, &nonce_packet(&nonce, 7, 1), "a repeat of part 2")?;
+
+  authenticate(transport, &nonce, 8).map(|_| ())
+}
+
+fn crc_mismatch(transport: &mut dyn Transport) -> Result<(), String> {
+  let nonce = nonce(9);
+  expect_accepted(transport, &nonce_packet(&nonce, 9, 0), "part 1")?;
+
+  let mut packet = nonce_packet(&nonce, 9, 1);
+  packet[8] ^= 0x01;
+  expect_rejected(transport, &packet, "part 2 with a bad crc")?;
+  expect_rejected(transport, &nonce_packet(&nonce, 9, 2), "part 3 after a reset")?;
+
+  authenticate(transport, &nonce, 10).map(|_| ())
+}
+
+fn reset_mid_signing(transport: &mut dyn Transport) -> Result<(), String> {
+  let abandoned = nonce(11);
+  send_nonce(transport, &abandoned, 11)?;
+  if read_status(transport)?.ready {
+    return Err("signing finished too quickly to interrupt".to_string());
+  }
+
+  // The device can't stop signing, but it has to discard the result and accept a new nonce afterwards.
+  let nonce = nonce(12);
+  expect_rejected(transport, &nonce_packet(&nonce, 12, 0), "a new nonce while signing")?;
+  authenticate(transport, &nonce, 12).map(|_| ())
+}
+
+fn diagnostics(transport: &mut dyn Transport) -> Result<(), String> {
+  let before = read_diagnostics(transport)?;
+  authenticate(transport, &nonce(13), 13)?;
+  let after = read_diagnostics(transport)?;
+
+  if after.handshakes.completed != before.handshakes.completed + 1 {
+    return Err(format!(
+      "completed handshakes went from {} to {}",
+      before.handshakes.completed, after.handshakes.completed
+    ));
+  }
+
+  let signing = after.signing;
+  if signing.count != before.signing.count + 1 {
+    return Err(format!(
+      "signature count went from {} to {}",
+      before.signing.count, signing.count
+    ));
+  }
+
+  if signing.last == 0 || signing.min > signing.average || signing.average > signing.max {
+    return Err(format!("inconsistent signing statistics: {:?}", signing));
+  }
+  Ok(())
+}
+
+/// Run every scenario, printing the result of each. Returns whether all of them passed.
+pub fn run(transport: &mut dyn Transport) -> bool {
+  println!("running {} scenarios against {}", SCENARIOS.len(), transport.name());
+
+  let mut failures = 0;
+  for (name, scenario) in SCENARIOS {
+    match scenario(transport) {
+      Ok(()) => println!("[PASS] {}", name),
+      Err(err) => {
+        println!("[FAIL] {}: {}", name, err);
+        failures += 1;
+      }
+    }
+  }
+
+  println!("{}/{} scenarios passed", SCENARIOS.len() - failures, SCENARIOS.len());
+  failures == 0
+}
@@ -0,0 +1,250 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use hidapi::HidDevice;
+use usb_device::prelude::*;
+
+use passinglink_core::auth::{Authenticator, Signer};
+use passinglink_core::hid::{Hid, HidClass, HidReportType, PS4Hid};
+use passinglink_core::mock::{MockUsbBus, MockUsbHost};
+
+/// Something that speaks the PS4 feature report protocol.
+pub trait Transport {
+  fn name(&self) -> &'static str;
+
+  /// Read a feature report. The returned data starts with the report id.
+  fn get_feature_report(&mut self, report_id: u8, length: usize) -> Result<Vec<u8>, String>;
+
+  /// Send a feature report. The first byte of data is the report id.
+  fn send_feature_report(&mut self, data: &[u8]) -> Result<(), String>;
+}
+
+/// A physical device, opened via hidapi.
+pub struct HidapiTransport {
+  device: HidDevice,
+}
+
+impl HidapiTransport {
+  pub fn open() -> Result<HidapiTransport, String> {
+    let hidapi = hidapi::HidApi::new().map_err(|err| format!("failed to initialize hidapi: {}", err))?;
+    let devices = [
+      (0x054c, 0x05c4), // Sony DualShock 4
+      (0x1532, 0x0401), // Razer Panthera
+      (0x1209, 0x214d), // Passing Link
+    ];
+
+    let device = devices
+      .iter()
+      .find_map(|(vid, pid)| hidapi.open(*vid, *pid).ok())
+      .ok_or_else(|| "failed to open device".to_string())?;
+
+    let manufacturer = device
+      .get_manufacturer_string()
+      .ok()
+      .and_then(|s| s)
+      .unwrap_or_default();
+    let product = device.get_product_string().ok().and_then(|s| s).unwrap_or_default();
+    println!("Successfully opened {} {}", manufacturer, product);
+
+    device
+      .set_blocking_mode(true)
+      .map_err(|err| format!("failed to set blocking mode: {}", err))?;
+    Ok(HidapiTransport { device })
+  }
+}
+
+impl Transport for HidapiTransport {
+  fn name(&self) -> &'static str {
+    "hidapi"
+  }
+
+  fn get_feature_report(&mut self, report_id: u8, length: usize) -> Result<Vec<u8>, String> {
+    let mut result = vec![0; length];
+    result[0] = report_id;
+    let bytes = self
+      .device
+      .get_feature_report(&mut result)
+      .map_err(|err| format!("failed to read report {:#x}: {}", report_id, err))?;
+    result.resize(bytes, 0);
+    Ok(result)
+  }
+
+  fn send_feature_report(&mut self, data: &[u8]) -> Result<(), String> {
+    self
+      .device
+      .send_feature_report(data)
+      .map_err(|err| format!("failed to send report {:#x}: {}", data[0], err))
+  }
+}
+
+/// Wraps a signer to take at least as long as the real device does, so that requests that arrive mid-signing can be
+/// exercised. Signing times are recorded like the firmware does.
+struct SlowSigner<S: Signer> {
+  auth: &'static Authenticator,
+  signer: S,
+  delay: Duration,
+}
+
+impl<S: Signer> Signer for SlowSigner<S> {
+  fn sign(&self, nonce: &[u8; 256], response: &mut [u8; 1064]) -> Result<(), ()> {
+    let start = Instant::now();
+    std::thread::sleep(self.delay);
+    let result = self.signer.sign(nonce, response);
+    if result.is_ok() {
+      self.auth.record_signing_time(start.elapsed().as_micros() as u32);
+    }
+    result
+  }
+}
+
+/// Stand-in for the firmware's idle loop, which does the signing in the background.
+struct Worker {
+  stop: Arc<AtomicBool>,
+  thread: Option<JoinHandle<()>>,
+}
+
+impl Worker {
+  fn spawn<S: Signer + Send + 'static>(auth: &'static Authenticator, signer: S, delay: Duration) -> Worker {
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread = {
+      let stop = stop.clone();
+      let signer = SlowSigner { auth, signer, delay };
+      std::thread::spawn(move || {
+        while !stop.load(Ordering::SeqCst) {
+          if !auth.perform_work(Some(&signer)) {
+            std::thread::sleep(Duration::from_millis(1));
+          }
+        }
+      })
+    };
+
+    Worker {
+      stop,
+      thread: Some(thread),
+    }
+  }
+}
+
+impl Drop for Worker {
+  fn drop(&mut self) {
+    self.stop.store(true, Ordering::SeqCst);
+    if let Some(thread) = self.thread.take() {
+      let _ = thread.join();
+    }
+  }
+}
+
+fn leak_authenticator() -> &'static Authenticator {
+  Box::leak(Box::new(Authenticator::new()))
+}
+
+/// The firmware's PS4Hid and auth state machine, called directly.
+pub struct CoreTransport {
+  hid: PS4Hid,
+  _worker: Worker,
+}
+
+impl CoreTransport {
+  pub fn new<S: Signer + Send + 'static>(signer: S, signing_delay: Duration) -> CoreTransport {
+    let auth = leak_authenticator();
+    CoreTransport {
+      hid: PS4Hid::new(auth).with_diagnostics(),
+      _worker: Worker::spawn(auth, signer, signing_delay),
+    }
+  }
+}
+
+impl Transport for CoreTransport {
+  fn name(&self) -> &'static str {
+    "core"
+  }
+
+  fn get_feature_report(&mut self, report_id: u8, length: usize) -> Result<Vec<u8>, String> {
+    self
+      .hid
+      .get_report(HidReportType::Feature, report_id, Some(length as u16))
+      .map(|data| data[..data.len().min(length)].to_vec())
+      .map_err(|_| format!("report {:#x} rejected", report_id))
+  }
+
+  fn send_feature_report(&mut self, data: &[u8]) -> Result<(), String> {
+    self
+      .hid
+      .set_report(HidReportType::Feature, data[0], data)
+      .map_err(|_| format!("report {:#x} rejected", data[0]))
+  }
+}
+
+/// The firmware's PS4Hid behind HidClass, driven with control transfers over a mock USB bus.
+pub struct MockUsbTransport {
+  host: MockUsbHost<HidClass<'static, PS4Hid, MockUsbBus>>,
+  _worker: Worker,
+}
+
+impl MockUsbTransport {
+  const REQUEST_TYPE_CLASS_INTERFACE_IN: u8 = 0xa1;
+  const REQUEST_TYPE_CLASS_INTERFACE_OUT: u8 = 0x21;
+  const GET_REPORT: u8 = 0x01;
+  const SET_REPORT: u8 = 0x09;
+  const REPORT_TYPE_FEATURE: u8 = 0x03;
+
+  pub fn new<S: Signer + Send + 'static>(signer: S, signing_delay: Duration) -> Result<MockUsbTransport, String> {
+    let auth = leak_authenticator();
+    let hid = PS4Hid::new(auth).with_diagnostics();
+    let identity = hid.identity();
+    let mut host = MockUsbHost::new(|alloc| {
+      let class = HidClass::new(hid, alloc);
+      let device = UsbDeviceBuilder::new(alloc, UsbVidPid(identity.vid, identity.pid))
+        .manufacturer(identity.manufacturer)
+        .product(identity.product)
+        .max_packet_size_0(64)
+        .build();
+      (device, class)
+    });
+
+    host
+      .enumerate()
+      .map_err(|err| format!("failed to enumerate mock device: {:?}", err))?;
+
+    Ok(MockUsbTransport {
+      host,
+      _worker: Worker::spawn(auth, signer, signing_delay),
+    })
+  }
+}
+
+impl Transport for MockUsbTransport {
+  fn name(&self) -> &'static str {
+    "mock-usb"
+  }
+
+  fn get_feature_report(&mut self, report_id: u8, length: usize) -> Result<Vec<u8>, String> {
+    let value = u16::from_be_bytes([MockUsbTransport::REPORT_TYPE_FEATURE, report_id]);
+    self
+      .host
+      .control_in(
+        MockUsbTransport::REQUEST_TYPE_CLASS_INTERFACE_IN,
+        MockUsbTransport::GET_REPORT,
+        value,
+        0,
+        length as u16,
+      )
+      .map_err(|err| format!("failed to read report {:#x}: {:?}", report_id, err))
+  }
+
+  fn send_feature_report(&mut self, data: &[u8]) -> Result<(), String> {
+    let value = u16::from_be_bytes([MockUsbTransport::REPORT_TYPE_FEATURE, data[0]]);
+    self
+      .host
+      .control_out(
+        MockUsbTransport::REQUEST_TYPE_CLASS_INTERFACE_OUT,
+        MockUsbTransport::SET_REPORT,
+        value,
+        0,
+        data,
+      )
+      .map_err(|err| format!("failed to send report {:#x}: {:?}", data[0], err))
+  }
+}
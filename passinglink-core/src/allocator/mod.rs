@@ -0,0 +1,483 @@
+//! A fixed-size-class allocator, suitable for use as a `#[global_allocator]` on a target without a real heap.
+//!
+//! Memory comes from pools of equally sized slots, declared smallest first. An allocation is served from the smallest
+//! class that it fits in, or from the next larger one if that class is exhausted. Every operation is O(1) per class,
+//! lock-free, and safe to use from interrupt handlers.
+//!
+//! Each class keeps track of its high-water mark, and every allocation can optionally be traced, which together are
+//! enough to size the pools from real data instead of guesswork.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use core::ptr::{self, NonNull};
+use core::sync::atomic::Ordering::SeqCst;
+use core::sync::atomic::{AtomicBool, AtomicU16, AtomicU32};
+
+mod trace;
+pub use trace::{Trace, TraceEvent, TraceKind, HISTOGRAM_BUCKETS};
+
+/// Alignment of every slot. Allocations that need more than this fail.
+pub const SLOT_ALIGN: usize = 8;
+
+/// Index used to terminate a free list.
+const NONE: u16 = u16::MAX;
+
+#[derive(Clone, Copy)]
+#[repr(C, align(8))]
+struct Slot<const SIZE: usize>([u8; SIZE]);
+
+/// COUNT slots of SIZE bytes each.
+///
+/// Freed slots go on an intrusive free list, whose links live beside the slots rather than in them. Slots that have
+/// never been handed out aren't on the list at all: they're taken in order once the list is empty, which lets the pool
+/// be built by a const fn.
+pub struct Pool<const SIZE: usize, const COUNT: usize> {
+  slots: UnsafeCell<[Slot<SIZE>; COUNT]>,
+  allocated: [AtomicBool; COUNT],
+  next: [AtomicU16; COUNT],
+
+  /// The first free slot in the low 16 bits, and a generation count in the high 16 bits. The generation changes on
+  /// every update, so that an interrupt handler popping and pushing back the head can't trick a compare and swap that
+  /// it interrupted into installing a stale link.
+  head: AtomicU32,
+
+  /// Number of slots that have ever been handed out.
+  touched: AtomicU16,
+
+  used: AtomicU16,
+  high_water: AtomicU16,
+}
+
+unsafe impl<const SIZE: usize, const COUNT: usize> Sync for Pool<SIZE, COUNT> {}
+
+impl<const SIZE: usize, const COUNT: usize> Pool<SIZE, COUNT> {
+  #[allow(clippy::declare_interior_mutable_const)]
+  const FREE: AtomicBool = AtomicBool::new(false);
+
+  #[allow(clippy::declare_interior_mutable_const)]
+  const LINK: AtomicU16 = AtomicU16::new(NONE);
+
+  pub const fn new() -> Pool<SIZE, COUNT> {
+    assert!(
+      SIZE & (SLOT_ALIGN - 1) == 0,
+      "slot size must be a multiple of the slot alignment"
+    );
+    assert!(COUNT < NONE as usize, "too many slots");
+    Pool {
+      slots: UnsafeCell::new([Slot([0; SIZE]); COUNT]),
+      allocated: [Self::FREE; COUNT],
+      next: [Self::LINK; COUNT],
+      head: AtomicU32::new(NONE as u32),
+      touched: AtomicU16::new(0),
+      used: AtomicU16::new(0),
+      high_water: AtomicU16::new(0),
+    }
+  }
+
+  fn slot(&self, index: usize) -> *mut u8 {
+    unsafe { (*self.slots.get())[index].0.as_mut_ptr() }
+  }
+
+  /// The index of the slot that starts at ptr, if there is one.
+  fn index_of(&self, ptr: *mut u8) -> Option<usize> {
+    let base = self.slot(0) as usize;
+    let offset = (ptr as usize).wrapping_sub(base);
+    if offset >= SIZE * COUNT {
+      return None;
+    }
+
+    let index = offset / SIZE;
+    if index * SIZE != offset {
+      panic!("freed pointer {:p} from the middle of a {} byte slot", ptr, SIZE);
+    }
+    Some(index)
+  }
+
+  fn pop(&self) -> Option<usize> {
+    let mut head = self.head.load(SeqCst);
+    loop {
+      let index = head as u16;
+      if index == NONE {
+        break;
+      }
+
+      let next = self.next[index as usize].load(SeqCst);
+      let new_head = (head & 0xffff_0000).wrapping_add(0x1_0000) | next as u32;
+      match self.head.compare_exchange(head, new_head, SeqCst, SeqCst) {
+        Ok(_) => return Some(index as usize),
+        Err(current) => head = current,
+      }
+    }
+
+    let mut touched = self.touched.load(SeqCst);
+    while (touched as usize) < COUNT {
+      match self.touched.compare_exchange(touched, touched + 1, SeqCst, SeqCst) {
+        Ok(_) => return Some(touched as usize),
+        Err(current) => touched = current,
+      }
+    }
+    None
+  }
+
+  fn push(&self, index: usize) {
+    let mut head = self.head.load(SeqCst);
+    loop {
+      self.next[index].store(head as u16, SeqCst);
+      let new_head = (head & 0xffff_0000).wrapping_add(0x1_0000) | index as u32;
+      match self.head.compare_exchange(head, new_head, SeqCst, SeqCst) {
+        Ok(_) => return,
+        Err(current) => head = current,
+      }
+    }
+  }
+}
+
+/// Where an allocation lives: the index of its class, counting from the smallest, and its slot within the class.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Block {
+  pub class: usize,
+  pub slot: usize,
+}
+
+impl Block {
+  /// Move a block from the numbering of a group's members to the numbering of the whole group.
+  fn shift(self, classes: usize) -> Block {
+    Block {
+      class: self.class + classes,
+      slot: self.slot,
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ClassStats {
+  pub size: usize,
+  pub count: usize,
+  pub used: usize,
+  pub high_water: usize,
+}
+
+/// A size class, or an ordered group of them.
+pub trait SizeClass {
+  /// Number of classes in the group.
+  const CLASSES: usize;
+
+  /// Allocate a block that satisfies layout, if there's room for one.
+  fn alloc(&self, layout: Layout) -> Option<(NonNull<u8>, Block)>;
+
+  /// Free a block, returning None if it didn't come from here.
+  ///
+  /// # Safety
+  /// ptr must not be used after it's freed.
+  unsafe fn dealloc(&self, ptr: *mut u8) -> Option<Block>;
+
+  /// Report the usage of each class, smallest first.
+  fn stats(&self, f: &mut dyn FnMut(ClassStats));
+}
+
+impl<const SIZE: usize, const COUNT: usize> SizeClass for Pool<SIZE, COUNT> {
+  const CLASSES: usize = 1;
+
+  fn alloc(&self, layout: Layout) -> Option<(NonNull<u8>, Block)> {
+    if layout.size() > SIZE || layout.align() > SLOT_ALIGN {
+      return None;
+    }
+
+    let index = self.pop()?;
+    self.allocated[index].store(true, SeqCst);
+    let used = self.used.fetch_add(1, SeqCst) + 1;
+    self.high_water.fetch_max(used, SeqCst);
+    let block = Block { class: 0, slot: index };
+    NonNull::new(self.slot(index)).map(|ptr| (ptr, block))
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8) -> Option<Block> {
+    let index = self.index_of(ptr)?;
+    if !self.allocated[index].swap(false, SeqCst) {
+      panic!("double free of {:p}", ptr);
+    }
+    self.push(index);
+    self.used.fetch_sub(1, SeqCst);
+    Some(Block { class: 0, slot: index })
+  }
+
+  fn stats(&self, f: &mut dyn FnMut(ClassStats)) {
+    f(ClassStats {
+      size: SIZE,
+      count: COUNT,
+      used: self.used.load(SeqCst) as usize,
+      high_water: self.high_water.load(SeqCst) as usize,
+    })
+  }
+}
+
+impl<A: SizeClass, B: SizeClass> SizeClass for (A, B) {
+  const CLASSES: usize = A::CLASSES + B::CLASSES;
+
+  fn alloc(&self, layout: Layout) -> Option<(NonNull<u8>, Block)> {
+    self.0.alloc(layout).or_else(|| {
+      let (ptr, block) = self.1.alloc(layout)?;
+      Some((ptr, block.shift(A::CLASSES)))
+    })
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8) -> Option<Block> {
+    self
+      .0
+      .dealloc(ptr)
+      .or_else(|| self.1.dealloc(ptr).map(|block| block.shift(A::CLASSES)))
+  }
+
+  fn stats(&self, f: &mut dyn FnMut(ClassStats)) {
+    self.0.stats(f);
+    self.1.stats(f);
+  }
+}
+
+impl<A: SizeClass, B: SizeClass, C: SizeClass> SizeClass for (A, B, C) {
+  const CLASSES: usize = A::CLASSES + B::CLASSES + C::CLASSES;
+
+  fn alloc(&self, layout: Layout) -> Option<(NonNull<u8>, Block)> {
+    (&self.0, (&self.1, &self.2)).alloc(layout)
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8) -> Option<Block> {
+    (&self.0, (&self.1, &self.2)).dealloc(ptr)
+  }
+
+  fn stats(&self, f: &mut dyn FnMut(ClassStats)) {
+    self.0.stats(f);
+    self.1.stats(f);
+    self.2.stats(f);
+  }
+}
+
+impl<S: SizeClass> SizeClass for &S {
+  const CLASSES: usize = S::CLASSES;
+
+  fn alloc(&self, layout: Layout) -> Option<(NonNull<u8>, Block)> {
+    (*self).alloc(layout)
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8) -> Option<Block> {
+    (*self).dealloc(ptr)
+  }
+
+  fn stats(&self, f: &mut dyn FnMut(ClassStats)) {
+    (*self).stats(f)
+  }
+}
+
+/// Something that wants to hear about every allocation.
+pub trait Tracer {
+  /// block is None for an allocation that failed.
+  fn record(&self, kind: TraceKind, layout: Layout, block: Option<Block>);
+
+  /// Log whatever has been collected.
+  fn dump(&self) {}
+}
+
+/// Tracing turned off.
+impl Tracer for () {
+  fn record(&self, _kind: TraceKind, _layout: Layout, _block: Option<Block>) {}
+}
+
+/// A GlobalAlloc over a group of size classes, e.g.
+/// `Allocator<(Pool<128, 11>, Pool<256, 10>, Pool<512, 1>)>`, optionally traced by `Allocator::with_tracer`.
+///
+/// Running out of memory returns null, which leaves the decision of what to do about it to `alloc_error_handler`.
+pub struct Allocator<P, T = ()> {
+  pools: P,
+  tracer: T,
+}
+
+impl<P> Allocator<P> {
+  pub const fn new(pools: P) -> Allocator<P> {
+    Allocator { pools, tracer: () }
+  }
+}
+
+impl<P: SizeClass, T: Tracer> Allocator<P, T> {
+  pub const fn with_tracer(pools: P, tracer: T) -> Allocator<P, T> {
+    Allocator { pools, tracer }
+  }
+
+  pub fn tracer(&self) -> &T {
+    &self.tracer
+  }
+
+  pub fn stats(&self, f: &mut dyn FnMut(ClassStats)) {
+    self.pools.stats(f)
+  }
+
+  /// Log the usage of each class, followed by whatever the tracer has.
+  pub fn dump(&self) {
+    info!("allocator state:");
+    self.pools.stats(&mut |stats| {
+      info!(
+        "  {}: {}/{} used, high water {}",
+        stats.size, stats.used, stats.count, stats.high_water
+      );
+    });
+    self.tracer.dump();
+  }
+}
+
+unsafe impl<P: SizeClass, T: Tracer> GlobalAlloc for Allocator<P, T> {
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    match self.pools.alloc(layout) {
+      Some((ptr, block)) => {
+        self.tracer.record(TraceKind::Alloc, layout, Some(block));
+        ptr.as_ptr()
+      }
+      None => {
+        self.tracer.record(TraceKind::Alloc, layout, None);
+        ptr::null_mut()
+      }
+    }
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    match self.pools.dealloc(ptr) {
+      Some(block) => self.tracer.record(TraceKind::Free, layout, Some(block)),
+      None => panic!("freed pointer {:p} that wasn't allocated here", ptr),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::Arc;
+  use std::vec::Vec;
+
+  type TestAllocator = Allocator<(Pool<16, 2>, Pool<64, 2>, Pool<256, 1>)>;
+
+  fn allocator() -> TestAllocator {
+    Allocator::new((Pool::new(), Pool::new(), Pool::new()))
+  }
+
+  fn layout(size: usize, align: usize) -> Layout {
+    Layout::from_size_align(size, align).unwrap()
+  }
+
+  fn class_of(allocator: &TestAllocator, ptr: *mut u8) -> usize {
+    let pools = &allocator.pools;
+    if pools.0.index_of(ptr).is_some() {
+      16
+    } else if pools.1.index_of(ptr).is_some() {
+      64
+    } else if pools.2.index_of(ptr).is_some() {
+      256
+    } else {
+      panic!("pointer outside of every pool");
+    }
+  }
+
+  #[test]
+  fn round_up() {
+    let allocator = allocator();
+    unsafe {
+      for &(size, class) in &[(1, 16), (16, 16), (17, 64), (64, 64), (65, 256), (256, 256)] {
+        let ptr = allocator.alloc(layout(size, 1));
+        assert_eq!(class, class_of(&allocator, ptr), "size {}", size);
+        allocator.dealloc(ptr, layout(size, 1));
+      }
+
+      assert!(allocator.alloc(layout(257, 1)).is_null());
+    }
+  }
+
+  #[test]
+  fn alignment() {
+    let allocator = allocator();
+    unsafe {
+      for &align in &[1, 2, 4, 8] {
+        let ptr = allocator.alloc(layout(24, align));
+        assert_eq!(0, ptr as usize % align);
+        allocator.dealloc(ptr, layout(24, align));
+      }
+
+      assert!(allocator.alloc(layout(24, 16)).is_null());
+    }
+  }
+
+  #[test]
+  fn exhaustion() {
+    let allocator = allocator();
+    unsafe {
+      // Full classes spill over into larger ones.
+      let ptrs: Vec<_> = (0..5).map(|_| allocator.alloc(layout(8, 8))).collect();
+      let classes: Vec<_> = ptrs.iter().map(|&ptr| class_of(&allocator, ptr)).collect();
+      assert_eq!(vec![16, 16, 64, 64, 256], classes);
+      assert!(allocator.alloc(layout(8, 8)).is_null());
+
+      // Freed slots are reused, most recently freed first.
+      allocator.dealloc(ptrs[1], layout(8, 8));
+      allocator.dealloc(ptrs[0], layout(8, 8));
+      assert_eq!(ptrs[0], allocator.alloc(layout(8, 8)));
+      assert_eq!(ptrs[1], allocator.alloc(layout(8, 8)));
+    }
+  }
+
+  #[test]
+  #[should_panic(expected = "double free")]
+  fn double_free() {
+    let allocator = allocator();
+    unsafe {
+      let ptr = allocator.alloc(layout(8, 8));
+      allocator.dealloc(ptr, layout(8, 8));
+      allocator.dealloc(ptr, layout(8, 8));
+    }
+  }
+
+  #[test]
+  #[should_panic(expected = "wasn't allocated here")]
+  fn foreign_free() {
+    let allocator = allocator();
+    let mut value = 0u64;
+    unsafe { allocator.dealloc(&mut value as *mut u64 as *mut u8, layout(8, 8)) }
+  }
+
+  #[test]
+  #[should_panic(expected = "middle of a 64 byte slot")]
+  fn interior_free() {
+    let allocator = allocator();
+    unsafe {
+      let ptr = allocator.alloc(layout(32, 8));
+      allocator.dealloc(ptr.add(8), layout(32, 8));
+    }
+  }
+
+  #[test]
+  fn concurrent() {
+    let allocator: Arc<Allocator<Pool<64, 16>>> = Arc::new(Allocator::new(Pool::new()));
+    let threads: Vec<_> = (0..4u8)
+      .map(|thread| {
+        let allocator = allocator.clone();
+        std::thread::spawn(move || unsafe {
+          for _ in 0..10000 {
+            let ptr = allocator.alloc(layout(64, 8));
+            if ptr.is_null() {
+              continue;
+            }
+
+            // Nobody else can have the same slot.
+            ptr::write_bytes(ptr, thread, 64);
+            std::thread::yield_now();
+            assert!((0..64).all(|i| *ptr.add(i) == thread));
+            allocator.dealloc(ptr, layout(64, 8));
+          }
+        })
+      })
+      .collect();
+
+    for thread in threads {
+      thread.join().unwrap();
+    }
+
+    // Everything was returned.
+    let ptrs: Vec<_> = (0..16).map(|_| unsafe { allocator.alloc(layout(64, 8)) }).collect();
+    assert!(ptrs.iter().all(|ptr| !ptr.is_null()));
+  }
+}
@@ -0,0 +1,290 @@
+//! Allocation tracing: the most recent allocations and frees in a ring buffer, and a histogram of requested sizes.
+//!
+//! Events are packed into atomics, so recording one is safe from anywhere, but an event that's recorded while the
+//! trace is being read can come out garbled.
+
+use core::alloc::Layout;
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::Ordering::SeqCst;
+
+use super::{Block, Tracer};
+
+/// Bucket i of the histogram counts allocations of more than 2^(i-1) bytes, up to 2^i. The last bucket also counts
+/// everything larger.
+pub const HISTOGRAM_BUCKETS: usize = 16;
+
+/// Stand-in for a missing class or slot.
+const NONE: u32 = 0xffff;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TraceKind {
+  Alloc,
+  Free,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TraceEvent {
+  pub kind: TraceKind,
+
+  /// Sizes are clamped to 65535.
+  pub size: usize,
+  pub align: usize,
+
+  /// None for an allocation that failed.
+  pub block: Option<Block>,
+
+  /// When the event happened, according to the trace's clock.
+  pub tick: u32,
+}
+
+impl TraceEvent {
+  fn pack(&self) -> [u32; 3] {
+    let (class, slot) = match self.block {
+      Some(block) => (block.class as u32, block.slot as u32),
+      None => (NONE, NONE),
+    };
+    let kind = match self.kind {
+      TraceKind::Alloc => 0,
+      TraceKind::Free => 1,
+    };
+    [
+      self.tick,
+      (self.size.min(0xffff) as u32) | slot << 16,
+      kind | (self.align.trailing_zeros() << 8) | class << 16,
+    ]
+  }
+
+  fn unpack(words: [u32; 3]) -> TraceEvent {
+    let slot = words[1] >> 16;
+    let class = words[2] >> 16;
+    TraceEvent {
+      kind: if words[2] & 1 == 0 {
+        TraceKind::Alloc
+      } else {
+        TraceKind::Free
+      },
+      size: (words[1] & 0xffff) as usize,
+      align: 1 << ((words[2] >> 8) & 0x1f),
+      block: if class == NONE {
+        None
+      } else {
+        Some(Block {
+          class: class as usize,
+          slot: slot as usize,
+        })
+      },
+      tick: words[0],
+    }
+  }
+}
+
+fn bucket(size: usize) -> usize {
+  let bits = 8 * core::mem::size_of::<usize>() as u32 - (size.max(1) - 1).leading_zeros();
+  (bits as usize).min(HISTOGRAM_BUCKETS - 1)
+}
+
+/// The last N events, plus a histogram and failure count covering every allocation since boot.
+pub struct Trace<const N: usize> {
+  clock: fn() -> u32,
+  events: [[AtomicU32; 3]; N],
+
+  /// Total number of events recorded, of which the last N are in events.
+  recorded: AtomicU32,
+
+  histogram: [AtomicU32; HISTOGRAM_BUCKETS],
+  failures: AtomicU32,
+}
+
+impl<const N: usize> Trace<N> {
+  #[allow(clippy::declare_interior_mutable_const)]
+  const ZERO: AtomicU32 = AtomicU32::new(0);
+
+  #[allow(clippy::declare_interior_mutable_const)]
+  const EMPTY: [AtomicU32; 3] = [Self::ZERO, Self::ZERO, Self::ZERO];
+
+  /// clock provides the tick for each event, e.g. the cycle counter.
+  pub const fn new(clock: fn() -> u32) -> Trace<N> {
+    assert!(N > 0, "trace must have room for at least one event");
+    Trace {
+      clock,
+      events: [Self::EMPTY; N],
+      recorded: AtomicU32::new(0),
+      histogram: [Self::ZERO; HISTOGRAM_BUCKETS],
+      failures: AtomicU32::new(0),
+    }
+  }
+
+  /// Call f on each of the events still in the buffer, oldest first.
+  pub fn events(&self, f: &mut dyn FnMut(TraceEvent)) {
+    let recorded = self.recorded.load(SeqCst) as usize;
+    for i in recorded.saturating_sub(N)..recorded {
+      let words = &self.events[i % N];
+      f(TraceEvent::unpack([
+        words[0].load(SeqCst),
+        words[1].load(SeqCst),
+        words[2].load(SeqCst),
+      ]));
+    }
+  }
+
+  pub fn histogram(&self) -> [u32; HISTOGRAM_BUCKETS] {
+    let mut result = [0; HISTOGRAM_BUCKETS];
+    for (count, bucket) in result.iter_mut().zip(self.histogram.iter()) {
+      *count = bucket.load(SeqCst);
+    }
+    result
+  }
+
+  pub fn failures(&self) -> u32 {
+    self.failures.load(SeqCst)
+  }
+}
+
+impl<const N: usize> Tracer for Trace<N> {
+  fn record(&self, kind: TraceKind, layout: Layout, block: Option<Block>) {
+    if kind == TraceKind::Alloc {
+      self.histogram[bucket(layout.size())].fetch_add(1, SeqCst);
+      if block.is_none() {
+        self.failures.fetch_add(1, SeqCst);
+      }
+    }
+
+    let event = TraceEvent {
+      kind,
+      size: layout.size(),
+      align: layout.align(),
+      block,
+      tick: (self.clock)(),
+    };
+
+    let index = self.recorded.fetch_add(1, SeqCst) as usize % N;
+    for (word, value) in self.events[index].iter().zip(event.pack().iter()) {
+      word.store(*value, SeqCst);
+    }
+  }
+
+  fn dump(&self) {
+    info!("allocation sizes:");
+    for (i, &count) in self.histogram().iter().enumerate() {
+      if count == 0 {
+        continue;
+      }
+
+      if i == HISTOGRAM_BUCKETS - 1 {
+        info!("  > {}: {}", 1 << (i - 1), count);
+      } else {
+        info!("  <= {}: {}", 1 << i, count);
+      }
+    }
+    info!("failed allocations: {}", self.failures());
+
+    let recorded = self.recorded.load(SeqCst);
+    info!("last {} of {} events:", (recorded as usize).min(N), recorded);
+    self.events(&mut |event| match (event.kind, event.block) {
+      (TraceKind::Alloc, Some(block)) => info!(
+        "  [{}] alloc {} (align {}) -> class {} slot {}",
+        event.tick, event.size, event.align, block.class, block.slot
+      ),
+      (TraceKind::Alloc, None) => info!("  [{}] alloc {} (align {}) failed", event.tick, event.size, event.align),
+      (TraceKind::Free, Some(block)) => info!(
+        "  [{}] free {} from class {} slot {}",
+        event.tick, event.size, block.class, block.slot
+      ),
+      (TraceKind::Free, None) => info!("  [{}] free {}", event.tick, event.size),
+    });
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::{Allocator, ClassStats, Pool};
+  use super::*;
+  use core::alloc::GlobalAlloc;
+  use std::vec::Vec;
+
+  static TICK: AtomicU32 = AtomicU32::new(0);
+
+  fn tick() -> u32 {
+    TICK.fetch_add(1, SeqCst)
+  }
+
+  fn layout(size: usize, align: usize) -> Layout {
+    Layout::from_size_align(size, align).unwrap()
+  }
+
+  #[test]
+  fn buckets() {
+    assert_eq!(0, bucket(0));
+    assert_eq!(0, bucket(1));
+    assert_eq!(1, bucket(2));
+    assert_eq!(7, bucket(128));
+    assert_eq!(8, bucket(129));
+    assert_eq!(HISTOGRAM_BUCKETS - 1, bucket(1 << 20));
+  }
+
+  #[test]
+  fn trace() {
+    let allocator = Allocator::with_tracer((Pool::<16, 1>::new(), Pool::<64, 1>::new()), Trace::<4>::new(tick));
+    unsafe {
+      let a = allocator.alloc(layout(12, 4));
+      let b = allocator.alloc(layout(12, 4));
+      assert!(allocator.alloc(layout(12, 4)).is_null());
+      allocator.dealloc(a, layout(12, 4));
+      allocator.dealloc(b, layout(12, 4));
+      let c = allocator.alloc(layout(100, 8));
+      assert!(c.is_null());
+    }
+
+    let mut events = Vec::new();
+    allocator.tracer().events(&mut |event| events.push(event));
+    let summary: Vec<_> = events
+      .iter()
+      .map(|event| {
+        (
+          event.kind,
+          event.size,
+          event.align,
+          event.block.map(|block| block.class),
+        )
+      })
+      .collect();
+
+    // Only the last 4 of 6 events are left.
+    assert_eq!(
+      vec![
+        (TraceKind::Alloc, 12, 4, None),
+        (TraceKind::Free, 12, 4, Some(0)),
+        (TraceKind::Free, 12, 4, Some(1)),
+        (TraceKind::Alloc, 100, 8, None),
+      ],
+      summary
+    );
+    assert!(events.windows(2).all(|pair| pair[0].tick < pair[1].tick));
+
+    let histogram = allocator.tracer().histogram();
+    assert_eq!(3, histogram[bucket(12)]);
+    assert_eq!(1, histogram[bucket(100)]);
+    assert_eq!(4, histogram.iter().sum::<u32>());
+    assert_eq!(2, allocator.tracer().failures());
+
+    let mut stats = Vec::new();
+    allocator.stats(&mut |class| stats.push(class));
+    assert_eq!(
+      vec![
+        ClassStats {
+          size: 16,
+          count: 1,
+          used: 0,
+          high_water: 1
+        },
+        ClassStats {
+          size: 64,
+          count: 1,
+          used: 0,
+          high_water: 1
+        },
+      ],
+      stats
+    );
+  }
+}
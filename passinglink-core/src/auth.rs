@@ -0,0 +1,739 @@
+use core::cell::UnsafeCell;
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::Ordering::SeqCst;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub enum AuthStateType {
+  Waiting = 0,
+  ReceivingNonce = 1,
+  ReadyToSign = 2,
+  Signing = 3,
+  SendingSignature = 4,
+  Resetting = 5,
+}
+
+impl AuthStateType {
+  pub fn from_u8(value: u8) -> Option<AuthStateType> {
+    match value {
+      0 => Some(AuthStateType::Waiting),
+      1 => Some(AuthStateType::ReceivingNonce),
+      2 => Some(AuthStateType::ReadyToSign),
+      3 => Some(AuthStateType::Signing),
+      4 => Some(AuthStateType::SendingSignature),
+      5 => Some(AuthStateType::Resetting),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Copy, Clone, Debug)]
+#[repr(packed)]
+struct AuthState {
+  state: AuthStateType,
+  nonce_id: u8,
+  next_part: u8,
+  padding: u8,
+}
+
+impl AuthState {
+  fn from_u32(value: u32) -> AuthState {
+    unsafe { core::mem::transmute_copy(&value) }
+  }
+
+  fn to_u32(self) -> u32 {
+    unsafe { core::mem::transmute_copy(&self) }
+  }
+}
+
+/// Something that can answer the console's challenge.
+pub trait Signer {
+  /// Sign a 256 byte nonce, and fill in the 1064 byte response that gets sent back to the console.
+  fn sign(&self, nonce: &[u8; 256], response: &mut [u8; 1064]) -> Result<(), ()>;
+}
+
+#[cfg(feature = "ds4auth")]
+impl Signer for ds4auth::DS4Key {
+  fn sign(&self, nonce: &[u8; 256], response: &mut [u8; 1064]) -> Result<(), ()> {
+    let signature = ds4auth::DS4Key::sign(self, nonce).ok_or(())?;
+    response.copy_from_slice(signature.as_bytes());
+    Ok(())
+  }
+}
+
+/// Interval at which Authenticator::expire_stalled should be called.
+/// A handshake that makes no progress for an entire interval is abandoned, so stalls expire after one to two intervals.
+pub const STALL_CHECK_INTERVAL_MS: u32 = 2500;
+
+/// Running totals of how handshakes have ended.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct AuthStats {
+  pub completed: u32,
+
+  /// Handshakes that were reset, either by the console or because signing failed.
+  pub aborted: u32,
+
+  /// Handshakes that were abandoned by expire_stalled.
+  pub timed_out: u32,
+}
+
+/// How long signing has taken so far, in microseconds.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct SigningStats {
+  pub count: u32,
+  pub last: u32,
+  pub min: u32,
+  pub average: u32,
+  pub max: u32,
+}
+
+/// Vendor feature report with the contents of AuthStats and SigningStats:
+///   [0xa3, state, 0, 0, completed, aborted, timed out, count, last, min, average, max]
+/// with each of the statistics as a little-endian u32.
+pub const REPORT_DIAGNOSTICS: u8 = 0xa3;
+pub const DIAGNOSTICS_REPORT_SIZE: usize = 36;
+
+fn crc(data: &[u8]) -> u32 {
+  crc::crc32::checksum_ieee(data)
+}
+
+/// State of the PS4 authentication handshake.
+///
+/// The nonce is received and the signature is sent from the USB interrupt handlers, while the signing itself is too
+/// slow for that and happens in perform_work, called from the idle loop. The two sides synchronize via an atomic
+/// state word, and the buffer is only touched by whichever side the state says owns it.
+pub struct Authenticator {
+  state: AtomicU32,
+  data: UnsafeCell<[u8; 1064]>,
+
+  /// Bumped on every state change, so that expire_stalled can tell whether anything has happened since it last ran.
+  activity: AtomicU32,
+  last_activity: AtomicU32,
+
+  completed: AtomicU32,
+  aborted: AtomicU32,
+  timed_out: AtomicU32,
+
+  // Only written by record_signing_time, from the same context as perform_work.
+  signing_count: AtomicU32,
+  signing_last: AtomicU32,
+  signing_min: AtomicU32,
+  signing_average: AtomicU32,
+  signing_max: AtomicU32,
+}
+
+unsafe impl Sync for Authenticator {}
+
+impl Authenticator {
+  pub const fn new() -> Authenticator {
+    Authenticator {
+      state: AtomicU32::new(0),
+      data: UnsafeCell::new([0; 1064]),
+      activity: AtomicU32::new(0),
+      last_activity: AtomicU32::new(0),
+      completed: AtomicU32::new(0),
+      aborted: AtomicU32::new(0),
+      timed_out: AtomicU32::new(0),
+      signing_count: AtomicU32::new(0),
+      signing_last: AtomicU32::new(0),
+      signing_min: AtomicU32::new(0),
+      signing_average: AtomicU32::new(0),
+      signing_max: AtomicU32::new(0),
+    }
+  }
+
+  fn load(&self) -> AuthState {
+    AuthState::from_u32(self.state.load(SeqCst))
+  }
+
+  // Activity is bumped before the state changes, so that expire_stalled can never see a new state with old activity.
+  // A failed compare_and_swap bumps it spuriously, which only delays expiry.
+  fn store(&self, state: AuthState) {
+    self.activity.fetch_add(1, SeqCst);
+    self.state.store(state.to_u32(), SeqCst);
+  }
+
+  fn compare_and_swap(&self, current: AuthState, new: AuthState) -> bool {
+    self.activity.fetch_add(1, SeqCst);
+    self
+      .state
+      .compare_exchange(current.to_u32(), new.to_u32(), SeqCst, SeqCst)
+      .is_ok()
+  }
+
+  #[allow(clippy::mut_from_ref)]
+  unsafe fn data(&self) -> &mut [u8; 1064] {
+    &mut *self.data.get()
+  }
+
+  pub fn state(&self) -> AuthStateType {
+    self.load().state
+  }
+
+  /// Whether a nonce is waiting for perform_work.
+  pub fn has_work(&self) -> bool {
+    self.state() == AuthStateType::ReadyToSign
+  }
+
+  pub fn stats(&self) -> AuthStats {
+    AuthStats {
+      completed: self.completed.load(SeqCst),
+      aborted: self.aborted.load(SeqCst),
+      timed_out: self.timed_out.load(SeqCst),
+    }
+  }
+
+  pub fn signing_stats(&self) -> SigningStats {
+    SigningStats {
+      count: self.signing_count.load(SeqCst),
+      last: self.signing_last.load(SeqCst),
+      min: self.signing_min.load(SeqCst),
+      average: self.signing_average.load(SeqCst),
+      max: self.signing_max.load(SeqCst),
+    }
+  }
+
+  /// Record how long a successful signature took, as measured by the Signer passed to perform_work.
+  pub fn record_signing_time(&self, micros: u32) {
+    let stats = self.signing_stats();
+    let count = stats.count + 1;
+    let (min, max) = if stats.count == 0 {
+      (micros, micros)
+    } else {
+      (stats.min.min(micros), stats.max.max(micros))
+    };
+
+    // A running average, since the total would overflow after a few hundred signatures.
+    let average = (stats.average as i64 + (micros as i64 - stats.average as i64) / count as i64) as u32;
+
+    self.signing_last.store(micros, SeqCst);
+    self.signing_min.store(min, SeqCst);
+    self.signing_average.store(average, SeqCst);
+    self.signing_max.store(max, SeqCst);
+    self.signing_count.store(count, SeqCst);
+
+    info!(
+      "signed nonce in {} ms (min {} ms, average {} ms, max {} ms)",
+      micros / 1000,
+      min / 1000,
+      average / 1000,
+      max / 1000
+    );
+  }
+
+  /// Fill in the diagnostics report.
+  pub fn get_diagnostics(&self, buf: &mut [u8]) {
+    for byte in buf.iter_mut() {
+      *byte = 0;
+    }
+
+    let stats = self.stats();
+    let signing = self.signing_stats();
+    let values = [
+      stats.completed,
+      stats.aborted,
+      stats.timed_out,
+      signing.count,
+      signing.last,
+      signing.min,
+      signing.average,
+      signing.max,
+    ];
+
+    buf[0] = REPORT_DIAGNOSTICS;
+    buf[1] = self.state() as u8;
+    for (i, value) in values.iter().enumerate() {
+      buf[4 + 4 * i..8 + 4 * i].copy_from_slice(&value.to_le_bytes());
+    }
+  }
+
+  // Try to move back into the waiting state.
+  // If the worker task is currently signing, we probably shouldn't be corrupting memory out from under it,
+  // so move into a resetting state to let it finish.
+  fn reset_state(&self) -> Result<(), ()> {
+    loop {
+      let current_state = self.load();
+      match current_state.state {
+        AuthStateType::Resetting => {
+          info!("attempted to reset while already resetting");
+        }
+
+        AuthStateType::Signing => {
+          let mut new_state = current_state;
+          new_state.state = AuthStateType::Resetting;
+          if !self.compare_and_swap(current_state, new_state) {
+            continue;
+          }
+          warn!("worker task is currently signing, changed signing state to resetting");
+          self.aborted.fetch_add(1, SeqCst);
+        }
+
+        AuthStateType::Waiting => {
+          info!("signing state is already waiting");
+        }
+
+        _ => {
+          let mut new_state = current_state;
+          new_state.state = AuthStateType::Waiting;
+          new_state.nonce_id = 0;
+          new_state.next_part = 0;
+          if !self.compare_and_swap(current_state, new_state) {
+            continue;
+          }
+          info!("reset signing state to waiting");
+          self.aborted.fetch_add(1, SeqCst);
+        }
+      }
+
+      return Err(());
+    }
+  }
+
+  /// Abandon whatever handshake is in progress, e.g. at the request of the debug console.
+  pub fn reset(&self) {
+    let _ = self.reset_state();
+  }
+
+  pub fn set_nonce(&self, bytes: &[u8]) -> Result<(), ()> {
+    if bytes.len() != 64 {
+      error!("received nonce packet of incorrect length");
+      return self.reset_state();
+    }
+
+    let received_crc = &bytes[(bytes.len() - 4)..];
+    let calculated_crc = crc(&bytes[..(bytes.len() - 4)]).to_le_bytes();
+
+    if received_crc != calculated_crc {
+      error!("CRC mismatch for nonce packet: {:?}", bytes);
+      return self.reset_state();
+    }
+
+    let received_nonce_id = bytes[1];
+    let received_nonce_part = bytes[2];
+    info!(
+      "received data for nonce {}, part {}/5",
+      received_nonce_id,
+      received_nonce_part + 1
+    );
+
+    let state = self.load();
+    match state.state {
+      AuthStateType::Waiting => {
+        if received_nonce_part != 0 {
+          error!("received non-zero nonce part first?");
+          return self.reset_state();
+        }
+      }
+
+      AuthStateType::ReceivingNonce => {
+        if received_nonce_id != state.nonce_id {
+          error!(
+            "received wrong nonce id (expected {}, got {})",
+            state.nonce_id, received_nonce_id
+          );
+          return self.reset_state();
+        }
+
+        if received_nonce_part != state.next_part {
+          error!(
+            "received wrong nonce part (expected {}, got {})",
+            state.next_part, received_nonce_part
+          );
+          return self.reset_state();
+        }
+      }
+
+      _ => {
+        error!("received nonce while in unexpected state: {:?}", state);
+        return self.reset_state();
+      }
+    }
+
+    let last_packet = received_nonce_part == 4;
+    let nonce_start = (56 * received_nonce_part) as usize;
+    let nonce_len = if last_packet { 32 } else { 56 };
+    let nonce_data = &bytes[4..];
+    unsafe { self.data()[nonce_start..nonce_start + nonce_len].copy_from_slice(&nonce_data[..nonce_len]) }
+
+    if last_packet {
+      info!("done receiving nonce, transitioning to signing state");
+      self.store(AuthState {
+        state: AuthStateType::ReadyToSign,
+        nonce_id: received_nonce_id,
+        next_part: 0,
+        padding: 0,
+      });
+    } else {
+      self.store(AuthState {
+        state: AuthStateType::ReceivingNonce,
+        nonce_id: received_nonce_id,
+        next_part: received_nonce_part + 1,
+        padding: 0,
+      });
+    }
+
+    Ok(())
+  }
+
+  pub fn signature_ready(&self) -> bool {
+    self.load().state == AuthStateType::SendingSignature
+  }
+
+  pub fn get_nonce_id(&self) -> u8 {
+    self.load().nonce_id
+  }
+
+  pub fn get_signature_chunk(&self, buf: &mut [u8]) -> Result<(), ()> {
+    let state = self.load();
+    if state.state != AuthStateType::SendingSignature {
+      error!("received requests for signature when not sending signature");
+      return Err(());
+    }
+
+    let nonce_id = state.nonce_id;
+    let part = state.next_part;
+    let done = part == 18;
+    let offset = part as usize * 56;
+    let data = unsafe { &self.data()[offset..offset + 56] };
+
+    buf[0] = 0xf1;
+    buf[1] = nonce_id;
+    buf[2] = part;
+    buf[3] = 0;
+    buf[4..60].copy_from_slice(data);
+    let crc_bytes = crc(&buf[..60]).to_le_bytes();
+    buf[60..].copy_from_slice(&crc_bytes);
+
+    let next_state = if done {
+      AuthState::from_u32(0)
+    } else {
+      AuthState {
+        state: AuthStateType::SendingSignature,
+        nonce_id,
+        next_part: part + 1,
+        padding: 0,
+      }
+    };
+
+    self.store(next_state);
+    info!("sending part {}/19 of signature for nonce {}", part + 1, nonce_id);
+    if done {
+      self.completed.fetch_add(1, SeqCst);
+      info!("handshake complete: {:?}", self.stats());
+    }
+    Ok(())
+  }
+
+  /// Abandon the handshake if the console has stopped partway through sending the nonce or receiving the signature.
+  /// This should be called every STALL_CHECK_INTERVAL_MS, and returns whether the handshake was abandoned.
+  pub fn expire_stalled(&self) -> bool {
+    let activity = self.activity.load(SeqCst);
+    let stalled = self.last_activity.swap(activity, SeqCst) == activity;
+
+    let state = self.load();
+    match state.state {
+      AuthStateType::ReceivingNonce | AuthStateType::SendingSignature if stalled => {}
+      _ => return false,
+    }
+
+    // If the console makes progress in the meantime, this fails and the handshake carries on.
+    let waiting = AuthState::from_u32(0);
+    if !self.compare_and_swap(state, waiting) {
+      return false;
+    }
+
+    self.timed_out.fetch_add(1, SeqCst);
+    warn!("handshake timed out in state {:?}: {:?}", state.state, self.stats());
+    true
+  }
+
+  /// Sign the nonce, if one has been received.
+  /// Returns whether any work was done, so that callers can go to sleep if there's nothing to do.
+  pub fn perform_work<S: Signer>(&self, signer: Option<&S>) -> bool {
+    let state = self.load();
+    if state.state != AuthStateType::ReadyToSign {
+      return false;
+    }
+
+    let mut new_state = state;
+    new_state.state = AuthStateType::Signing;
+    if !self.compare_and_swap(state, new_state) {
+      info!("worker cas failed, retrying");
+      return true;
+    }
+
+    info!("starting to sign nonce");
+
+    let mut nonce = [0u8; 256];
+    let mut response = [0u8; 1064];
+    unsafe {
+      nonce.copy_from_slice(&self.data()[0..256]);
+    }
+
+    if signer.map(|signer| signer.sign(&nonce, &mut response)) == Some(Ok(())) {
+      unsafe {
+        self.data().copy_from_slice(&response);
+      }
+      info!("done signing nonce");
+    } else {
+      error!("failed to sign nonce");
+      let _ = self.reset_state();
+    }
+
+    loop {
+      let state = self.load();
+      let mut new_state = state;
+      if state.state == AuthStateType::Resetting {
+        new_state.state = AuthStateType::Waiting;
+        new_state.nonce_id = 0;
+        new_state.next_part = 0;
+      } else if state.state == AuthStateType::Signing {
+        new_state.state = AuthStateType::SendingSignature;
+        new_state.nonce_id = state.nonce_id;
+        new_state.next_part = 0;
+      } else {
+        // This should be impossible, but just in case...
+        error!(
+          "invalid state transition detected: worker encountered state {:?}",
+          state.state
+        );
+        new_state.state = AuthStateType::Waiting;
+        new_state.nonce_id = 0;
+        new_state.next_part = 0;
+      }
+
+      if self.compare_and_swap(state, new_state) {
+        break;
+      }
+    }
+
+    true
+  }
+}
+
+/// "Signs" a nonce by inverting it, so that the response can be checked without doing any crypto.
+#[cfg(test)]
+pub(crate) struct MockSigner;
+
+#[cfg(test)]
+impl Signer for MockSigner {
+  fn sign(&self, nonce: &[u8; 256], response: &mut [u8; 1064]) -> Result<(), ()> {
+    for (i, byte) in response.iter_mut().enumerate() {
+      *byte = !nonce[i % 256];
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::provision;
+  use std::boxed::Box;
+  use std::vec::Vec;
+
+  /// Resets the handshake from under the worker while it's signing, as the console would by sending a new nonce.
+  struct InterruptingSigner(&'static Authenticator);
+
+  impl Signer for InterruptingSigner {
+    fn sign(&self, nonce: &[u8; 256], response: &mut [u8; 1064]) -> Result<(), ()> {
+      assert_eq!(Err(()), self.0.set_nonce(&[0u8; 64]));
+      assert_eq!(AuthStateType::Resetting, self.0.state());
+      MockSigner.sign(nonce, response)
+    }
+  }
+
+  fn authenticator() -> &'static Authenticator {
+    Box::leak(Box::new(Authenticator::new()))
+  }
+
+  fn nonce() -> Vec<u8> {
+    (0..256).map(|i| (i * 7) as u8).collect()
+  }
+
+  fn nonce_packet(nonce_id: u8, part: u8, nonce: &[u8]) -> [u8; 64] {
+    let start = part as usize * 56;
+    let len = if part == 4 { 32 } else { 56 };
+    provision::packet(0xf0, [nonce_id, part], &nonce[start..start + len])
+  }
+
+  fn send_nonce(auth: &Authenticator, nonce_id: u8, nonce: &[u8]) {
+    for part in 0..5 {
+      assert_eq!(Ok(()), auth.set_nonce(&nonce_packet(nonce_id, part, nonce)));
+    }
+  }
+
+  #[test]
+  fn full_handshake() {
+    let auth = authenticator();
+    let nonce = nonce();
+
+    assert_eq!(AuthStateType::Waiting, auth.state());
+    assert!(!auth.perform_work(Some(&MockSigner)));
+
+    send_nonce(auth, 42, &nonce);
+    assert_eq!(AuthStateType::ReadyToSign, auth.state());
+    assert!(!auth.signature_ready());
+
+    assert!(auth.perform_work(Some(&MockSigner)));
+    assert!(auth.signature_ready());
+    assert_eq!(42, auth.get_nonce_id());
+
+    let mut nonce_array = [0u8; 256];
+    nonce_array.copy_from_slice(&nonce);
+    let mut expected = [0u8; 1064];
+    MockSigner.sign(&nonce_array, &mut expected).unwrap();
+
+    let mut received = Vec::new();
+    for part in 0..19 {
+      let mut buf = [0u8; 64];
+      assert_eq!(Ok(()), auth.get_signature_chunk(&mut buf));
+      assert_eq!([0xf1, 42, part, 0], buf[..4]);
+      assert_eq!(crc(&buf[..60]).to_le_bytes(), buf[60..]);
+      received.extend_from_slice(&buf[4..60]);
+    }
+
+    assert_eq!(&expected[..], &received[..1064]);
+    assert_eq!(AuthStateType::Waiting, auth.state());
+    assert_eq!(Err(()), auth.get_signature_chunk(&mut [0u8; 64]));
+
+    assert_eq!(
+      AuthStats {
+        completed: 1,
+        aborted: 0,
+        timed_out: 0
+      },
+      auth.stats()
+    );
+  }
+
+  #[test]
+  fn crc_mismatch_resets() {
+    let auth = authenticator();
+    let nonce = nonce();
+
+    assert_eq!(Ok(()), auth.set_nonce(&nonce_packet(1, 0, &nonce)));
+    let mut packet = nonce_packet(1, 1, &nonce);
+    packet[10] ^= 1;
+    assert_eq!(Err(()), auth.set_nonce(&packet));
+    assert_eq!(AuthStateType::Waiting, auth.state());
+    assert_eq!(1, auth.stats().aborted);
+  }
+
+  #[test]
+  fn out_of_order_parts_reset() {
+    let auth = authenticator();
+    let nonce = nonce();
+
+    assert_eq!(Err(()), auth.set_nonce(&nonce_packet(1, 1, &nonce)));
+    assert_eq!(AuthStateType::Waiting, auth.state());
+
+    assert_eq!(Ok(()), auth.set_nonce(&nonce_packet(1, 0, &nonce)));
+    assert_eq!(Err(()), auth.set_nonce(&nonce_packet(1, 2, &nonce)));
+    assert_eq!(AuthStateType::Waiting, auth.state());
+
+    assert_eq!(Ok(()), auth.set_nonce(&nonce_packet(1, 0, &nonce)));
+    assert_eq!(Err(()), auth.set_nonce(&nonce_packet(2, 1, &nonce)));
+    assert_eq!(AuthStateType::Waiting, auth.state());
+  }
+
+  #[test]
+  fn manual_reset() {
+    let auth = authenticator();
+    auth.reset();
+    assert_eq!(0, auth.stats().aborted);
+
+    assert_eq!(Ok(()), auth.set_nonce(&nonce_packet(1, 0, &nonce())));
+    auth.reset();
+    assert_eq!(AuthStateType::Waiting, auth.state());
+    assert_eq!(1, auth.stats().aborted);
+  }
+
+  #[test]
+  fn signing_failure_resets() {
+    let auth = authenticator();
+    send_nonce(auth, 1, &nonce());
+    assert!(auth.perform_work::<MockSigner>(None));
+    assert_eq!(AuthStateType::Waiting, auth.state());
+  }
+
+  #[test]
+  fn reset_while_signing() {
+    let auth = authenticator();
+    send_nonce(auth, 1, &nonce());
+    assert!(auth.perform_work(Some(&InterruptingSigner(auth))));
+    assert_eq!(AuthStateType::Waiting, auth.state());
+
+    // The next handshake should work normally.
+    send_nonce(auth, 2, &nonce());
+    assert!(auth.perform_work(Some(&MockSigner)));
+    assert!(auth.signature_ready());
+  }
+
+  #[test]
+  fn signing_stats() {
+    let auth = authenticator();
+    assert_eq!(SigningStats::default(), auth.signing_stats());
+
+    auth.record_signing_time(3000);
+    auth.record_signing_time(1000);
+    auth.record_signing_time(2000);
+    assert_eq!(
+      SigningStats {
+        count: 3,
+        last: 2000,
+        min: 1000,
+        average: 2000,
+        max: 3000,
+      },
+      auth.signing_stats()
+    );
+
+    send_nonce(auth, 1, &nonce());
+    let mut buf = [0xffu8; DIAGNOSTICS_REPORT_SIZE];
+    auth.get_diagnostics(&mut buf);
+    assert_eq!(&[REPORT_DIAGNOSTICS, AuthStateType::ReadyToSign as u8, 0, 0], &buf[..4]);
+    assert_eq!(&[0; 12], &buf[4..16]);
+    assert_eq!(&3u32.to_le_bytes(), &buf[16..20]);
+    assert_eq!(&2000u32.to_le_bytes(), &buf[20..24]);
+    assert_eq!(&3000u32.to_le_bytes(), &buf[32..36]);
+  }
+
+  #[test]
+  fn stalled_handshake_expires() {
+    let auth = authenticator();
+    let nonce = nonce();
+
+    // The first check after some progress never expires anything.
+    assert_eq!(Ok(()), auth.set_nonce(&nonce_packet(1, 0, &nonce)));
+    assert!(!auth.expire_stalled());
+    assert_eq!(Ok(()), auth.set_nonce(&nonce_packet(1, 1, &nonce)));
+    assert!(!auth.expire_stalled());
+    assert!(auth.expire_stalled());
+    assert_eq!(AuthStateType::Waiting, auth.state());
+
+    // Waiting for our own signature isn't a stall.
+    send_nonce(auth, 2, &nonce);
+    assert!(auth.has_work());
+    assert!(!auth.expire_stalled());
+    assert!(!auth.expire_stalled());
+    assert!(auth.perform_work(Some(&MockSigner)));
+    assert!(!auth.has_work());
+
+    // But the console walking away partway through the signature is.
+    assert_eq!(Ok(()), auth.get_signature_chunk(&mut [0u8; 64]));
+    assert!(!auth.expire_stalled());
+    assert!(auth.expire_stalled());
+    assert_eq!(AuthStateType::Waiting, auth.state());
+    assert_eq!(Err(()), auth.get_signature_chunk(&mut [0u8; 64]));
+
+    assert_eq!(
+      AuthStats {
+        completed: 0,
+        aborted: 0,
+        timed_out: 2
+      },
+      auth.stats()
+    );
+  }
+}
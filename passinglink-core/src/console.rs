@@ -0,0 +1,274 @@
+//! Line-based debug console, fed a byte at a time from the UART receive interrupt.
+//!
+//! Like provisioning, the interrupt handler fills a fixed-size buffer until a line is complete, and then hands it off
+//! to the idle loop to parse and run. Anything received while a line is waiting is dropped, so the interrupt handler
+//! never has to wait for anything.
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::sync::atomic::Ordering::SeqCst;
+use core::sync::atomic::{AtomicBool, AtomicU8};
+
+use crate::input::SocdType;
+
+pub const LINE_SIZE: usize = 64;
+
+const LOG_USAGE: &str = "log level <off|error|warn|info|debug|trace>";
+const AUTH_USAGE: &str = "auth reset";
+const SOCD_USAGE: &str = "socd <horizontal|vertical|both> <neutral|last|first|negative|positive>";
+
+/// Each command, and what it does.
+pub const HELP: &[(&str, &str)] = &[
+  ("status", "show the mode, USB state and handshake state"),
+  ("inputs", "show the current inputs"),
+  (LOG_USAGE, "change the log level"),
+  (SOCD_USAGE, "change how opposing directions resolve"),
+  (AUTH_USAGE, "abandon the current handshake"),
+  ("selftest", "reload the key and test it"),
+  ("heap", "show allocator usage, and log the allocation trace"),
+  ("reboot", "reset the controller"),
+];
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Command {
+  Help,
+  Status,
+  Inputs,
+  LogLevel(log::LevelFilter),
+  Socd(SocdAxis, SocdType),
+  AuthReset,
+  SelfTest,
+  Heap,
+  Reboot,
+}
+
+/// Which of the lever's axes a socd command applies to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SocdAxis {
+  Horizontal,
+  Vertical,
+  Both,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CommandError {
+  Unknown,
+
+  /// A known command with the wrong arguments, along with how it should have been used.
+  Usage(&'static str),
+}
+
+impl fmt::Display for CommandError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      CommandError::Unknown => write!(f, "unknown command, try help"),
+      CommandError::Usage(usage) => write!(f, "usage: {}", usage),
+    }
+  }
+}
+
+impl Command {
+  pub fn parse(line: &str) -> Result<Command, CommandError> {
+    let mut words = line.split_whitespace();
+    match (words.next(), words.next(), words.next(), words.next()) {
+      (Some("help"), None, _, _) => Ok(Command::Help),
+      (Some("status"), None, _, _) => Ok(Command::Status),
+      (Some("inputs"), None, _, _) => Ok(Command::Inputs),
+      (Some("log"), Some("level"), Some(level), None) => level
+        .parse()
+        .map(Command::LogLevel)
+        .map_err(|_| CommandError::Usage(LOG_USAGE)),
+      (Some("log"), _, _, _) => Err(CommandError::Usage(LOG_USAGE)),
+      (Some("socd"), Some(axis), Some(mode), None) => {
+        let axis = match axis {
+          "horizontal" => SocdAxis::Horizontal,
+          "vertical" => SocdAxis::Vertical,
+          "both" => SocdAxis::Both,
+          _ => return Err(CommandError::Usage(SOCD_USAGE)),
+        };
+        let mode = mode.parse().map_err(|_| CommandError::Usage(SOCD_USAGE))?;
+        Ok(Command::Socd(axis, mode))
+      }
+      (Some("socd"), _, _, _) => Err(CommandError::Usage(SOCD_USAGE)),
+      (Some("auth"), Some("reset"), None, _) => Ok(Command::AuthReset),
+      (Some("auth"), _, _, _) => Err(CommandError::Usage(AUTH_USAGE)),
+      (Some("selftest"), None, _, _) => Ok(Command::SelfTest),
+      (Some("heap"), None, _, _) => Ok(Command::Heap),
+      (Some("reboot"), None, _, _) => Ok(Command::Reboot),
+      _ => Err(CommandError::Unknown),
+    }
+  }
+}
+
+/// What became of a received byte, so that the caller knows what to echo.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Received {
+  /// A character was added to the line.
+  Char(u8),
+
+  /// The last character was erased.
+  Erase,
+
+  /// The line is complete, and waiting for the idle loop.
+  Line,
+
+  Ignored,
+}
+
+/// The interrupt handler only touches the line while nothing is pending, and the idle loop only while something is, in
+/// the same way as provision::Provisioner.
+pub struct Console {
+  pending: AtomicBool,
+  len: AtomicU8,
+  line: UnsafeCell<[u8; LINE_SIZE]>,
+}
+
+unsafe impl Sync for Console {}
+
+impl Console {
+  pub const fn new() -> Console {
+    Console {
+      pending: AtomicBool::new(false),
+      len: AtomicU8::new(0),
+      line: UnsafeCell::new([0; LINE_SIZE]),
+    }
+  }
+
+  #[allow(clippy::mut_from_ref)]
+  unsafe fn line(&self) -> &mut [u8; LINE_SIZE] {
+    &mut *self.line.get()
+  }
+
+  /// Whether a line is waiting for take.
+  pub fn pending(&self) -> bool {
+    self.pending.load(SeqCst)
+  }
+
+  /// Handle a byte from the UART. Only printable ASCII is accepted, and characters past the end of the line are dropped.
+  pub fn receive(&self, byte: u8) -> Received {
+    if self.pending() {
+      return Received::Ignored;
+    }
+
+    let len = self.len.load(SeqCst);
+    match byte {
+      b'\r' | b'\n' if len > 0 => {
+        self.pending.store(true, SeqCst);
+        Received::Line
+      }
+
+      // Backspace and delete.
+      0x08 | 0x7f if len > 0 => {
+        self.len.store(len - 1, SeqCst);
+        Received::Erase
+      }
+
+      0x20..=0x7e if (len as usize) < LINE_SIZE => {
+        unsafe { self.line()[len as usize] = byte }
+        self.len.store(len + 1, SeqCst);
+        Received::Char(byte)
+      }
+
+      _ => Received::Ignored,
+    }
+  }
+
+  /// Parse the pending line, if there is one, and start on the next.
+  pub fn take(&self) -> Option<Result<Command, CommandError>> {
+    if !self.pending() {
+      return None;
+    }
+
+    let len = self.len.load(SeqCst) as usize;
+    let line = unsafe { &self.line()[..len] };
+
+    // Only ASCII gets into the line.
+    let result = Command::parse(core::str::from_utf8(line).unwrap());
+
+    self.len.store(0, SeqCst);
+    self.pending.store(false, SeqCst);
+    Some(result)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::vec::Vec;
+
+  fn send(console: &Console, bytes: &[u8]) -> Vec<Received> {
+    bytes.iter().map(|&byte| console.receive(byte)).collect()
+  }
+
+  #[test]
+  fn parse() {
+    assert_eq!(Ok(Command::Status), Command::parse("status"));
+    assert_eq!(Ok(Command::Inputs), Command::parse("  inputs "));
+    assert_eq!(Ok(Command::AuthReset), Command::parse("auth reset"));
+    assert_eq!(Ok(Command::Heap), Command::parse("heap"));
+    assert_eq!(
+      Ok(Command::LogLevel(log::LevelFilter::Debug)),
+      Command::parse("log level debug")
+    );
+
+    assert_eq!(Err(CommandError::Usage(LOG_USAGE)), Command::parse("log level loud"));
+    assert_eq!(Err(CommandError::Usage(LOG_USAGE)), Command::parse("log"));
+    assert_eq!(Err(CommandError::Usage(AUTH_USAGE)), Command::parse("auth"));
+    assert_eq!(
+      Ok(Command::Socd(SocdAxis::Vertical, SocdType::LastInputWins)),
+      Command::parse("socd vertical last")
+    );
+    assert_eq!(
+      Ok(Command::Socd(SocdAxis::Both, SocdType::Neutral)),
+      Command::parse("socd both neutral")
+    );
+    assert_eq!(Err(CommandError::Usage(SOCD_USAGE)), Command::parse("socd up neutral"));
+    assert_eq!(
+      Err(CommandError::Usage(SOCD_USAGE)),
+      Command::parse("socd both sideways")
+    );
+    assert_eq!(Err(CommandError::Usage(SOCD_USAGE)), Command::parse("socd"));
+    assert_eq!(Err(CommandError::Unknown), Command::parse("reboot now"));
+    assert_eq!(Err(CommandError::Unknown), Command::parse("rm -rf /"));
+  }
+
+  #[test]
+  fn line_editing() {
+    let console = Console::new();
+    assert_eq!(None, console.take());
+
+    // Empty lines are ignored, so CRLF only ends one line.
+    assert_eq!(vec![Received::Ignored, Received::Ignored], send(&console, b"\r\n"));
+
+    assert_eq!(
+      vec![Received::Char(b's'), Received::Char(b'x'), Received::Erase],
+      send(&console, b"sx\x7f")
+    );
+    send(&console, b"tatus");
+    assert!(!console.pending());
+    assert_eq!(Received::Line, console.receive(b'\r'));
+    assert!(console.pending());
+
+    // Nothing gets in until the line has been taken.
+    assert_eq!(Received::Ignored, console.receive(b'x'));
+    assert_eq!(Some(Ok(Command::Status)), console.take());
+    assert_eq!(None, console.take());
+
+    send(&console, b"reboot\n");
+    assert_eq!(Some(Ok(Command::Reboot)), console.take());
+  }
+
+  #[test]
+  fn long_line() {
+    let console = Console::new();
+    let received = send(&console, &[b'a'; LINE_SIZE + 8]);
+    assert_eq!(Received::Char(b'a'), received[LINE_SIZE - 1]);
+    assert_eq!(Received::Ignored, received[LINE_SIZE]);
+
+    // Control characters don't count either.
+    assert_eq!(Received::Ignored, console.receive(0x1b));
+
+    console.receive(b'\n');
+    assert_eq!(Some(Err(CommandError::Unknown)), console.take());
+  }
+}
@@ -0,0 +1,355 @@
+use crate::hid::{DeviceIdentity, Hid, HidProtocol, HidReportType};
+use crate::input::{ButtonType, DeviceInputs};
+
+/// HID keyboard usage IDs (USB HID Usage Tables, section 10).
+#[allow(unused)]
+pub mod keycode {
+  pub const NONE: u8 = 0x00;
+  pub const ERROR_ROLL_OVER: u8 = 0x01;
+
+  pub const A: u8 = 0x04;
+  pub const B: u8 = 0x05;
+  pub const C: u8 = 0x06;
+  pub const N: u8 = 0x11;
+  pub const P: u8 = 0x13;
+  pub const V: u8 = 0x19;
+  pub const X: u8 = 0x1B;
+  pub const Z: u8 = 0x1D;
+
+  pub const NUM_1: u8 = 0x1E;
+  pub const NUM_5: u8 = 0x22;
+
+  pub const ENTER: u8 = 0x28;
+  pub const ESCAPE: u8 = 0x29;
+  pub const TAB: u8 = 0x2B;
+  pub const SPACE: u8 = 0x2C;
+
+  pub const RIGHT: u8 = 0x4F;
+  pub const LEFT: u8 = 0x50;
+  pub const DOWN: u8 = 0x51;
+  pub const UP: u8 = 0x52;
+
+  pub const LEFT_CTRL: u8 = 0xE0;
+  pub const LEFT_SHIFT: u8 = 0xE1;
+  pub const LEFT_ALT: u8 = 0xE2;
+  pub const LEFT_GUI: u8 = 0xE3;
+  pub const RIGHT_CTRL: u8 = 0xE4;
+  pub const RIGHT_SHIFT: u8 = 0xE5;
+  pub const RIGHT_ALT: u8 = 0xE6;
+  pub const RIGHT_GUI: u8 = 0xE7;
+}
+
+/// Keycodes to send for each input, with keycode::NONE meaning unmapped.
+#[derive(Clone, Copy, Debug)]
+pub struct KeyMap {
+  pub up: u8,
+  pub down: u8,
+  pub left: u8,
+  pub right: u8,
+
+  /// Indexed by ButtonType.
+  pub buttons: [u8; 14],
+}
+
+impl KeyMap {
+  /// MAME's default player 1 controls, with the face buttons laid out as in a Vewlix-style arcade panel.
+  pub const fn default() -> KeyMap {
+    KeyMap {
+      up: keycode::UP,
+      down: keycode::DOWN,
+      left: keycode::LEFT,
+      right: keycode::RIGHT,
+      buttons: [
+        keycode::NUM_1,      // Start
+        keycode::NUM_5,      // Select (coin)
+        keycode::TAB,        // Home (MAME's menu)
+        keycode::LEFT_ALT,   // North (button 2)
+        keycode::Z,          // East (button 5)
+        keycode::LEFT_SHIFT, // South (button 4)
+        keycode::LEFT_CTRL,  // West (button 1)
+        keycode::C,          // L1 (button 7)
+        keycode::V,          // L2 (button 8)
+        keycode::B,          // L3
+        keycode::SPACE,      // R1 (button 3)
+        keycode::X,          // R2 (button 6)
+        keycode::N,          // R3
+        keycode::P,          // Trackpad (pause)
+      ],
+    }
+  }
+
+  pub fn button(&self, button: ButtonType) -> u8 {
+    self.buttons[button as usize]
+  }
+
+  pub fn set_button(&mut self, button: ButtonType, keycode: u8) {
+    self.buttons[button as usize] = keycode;
+  }
+}
+
+/// Report protocol report: a modifier byte, a reserved byte, and a bitmap of keycodes 0x00 to 0x7F.
+/// The first two bytes match the boot protocol report, so that hosts that get confused have a chance of working.
+#[allow(unused)]
+#[repr(packed)]
+struct KeyboardReport {
+  modifiers: u8,
+  reserved: u8,
+  keys: [u8; 16],
+}
+
+/// Boot protocol report, as defined in appendix B of the HID specification.
+#[allow(unused)]
+#[repr(packed)]
+struct BootKeyboardReport {
+  modifiers: u8,
+  reserved: u8,
+  keys: [u8; 6],
+}
+
+/// Determine the lever's direction from whichever of the dpad or the sticks it's driving.
+fn lever_directions(inputs: &DeviceInputs) -> (Option<bool>, Option<bool>) {
+  let axis = |value: u8| {
+    if value < 64 {
+      Some(false)
+    } else if value > 192 {
+      Some(true)
+    } else {
+      None
+    }
+  };
+
+  let (horizontal, vertical) = inputs.hat_dpad.to_directions();
+  let horizontal = horizontal
+    .or_else(|| axis(inputs.axis_left_stick_x.get()))
+    .or_else(|| axis(inputs.axis_right_stick_x.get()));
+  let vertical = vertical
+    .or_else(|| axis(255 - inputs.axis_left_stick_y.get()))
+    .or_else(|| axis(255 - inputs.axis_right_stick_y.get()));
+  (horizontal, vertical)
+}
+
+/// Call f with the keycode of every mapped input that's currently active.
+fn for_each_key(keymap: &KeyMap, inputs: &DeviceInputs, mut f: impl FnMut(u8)) {
+  let (horizontal, vertical) = lever_directions(inputs);
+  let directions = [
+    (vertical == Some(true), keymap.up),
+    (vertical == Some(false), keymap.down),
+    (horizontal == Some(false), keymap.left),
+    (horizontal == Some(true), keymap.right),
+  ];
+
+  for &(active, key) in directions.iter() {
+    if active && key != keycode::NONE {
+      f(key);
+    }
+  }
+
+  for &button in ButtonType::ALL.iter() {
+    let key = keymap.button(button);
+    if inputs.button(button).get() && key != keycode::NONE {
+      f(key);
+    }
+  }
+}
+
+fn modifier_bit(key: u8) -> Option<u8> {
+  if key >= keycode::LEFT_CTRL && key <= keycode::RIGHT_GUI {
+    Some(1 << (key - keycode::LEFT_CTRL))
+  } else {
+    None
+  }
+}
+
+impl KeyboardReport {
+  fn new() -> KeyboardReport {
+    KeyboardReport {
+      modifiers: 0,
+      reserved: 0,
+      keys: [0u8; 16],
+    }
+  }
+
+  fn update(&mut self, keymap: &KeyMap, inputs: &DeviceInputs) {
+    let mut modifiers = 0;
+    let mut keys = [0u8; 16];
+    for_each_key(keymap, inputs, |key| {
+      if let Some(bit) = modifier_bit(key) {
+        modifiers |= bit;
+      } else if key < 0x80 {
+        keys[(key / 8) as usize] |= 1 << (key % 8);
+      }
+    });
+
+    self.modifiers = modifiers;
+    self.keys = keys;
+  }
+}
+
+impl BootKeyboardReport {
+  fn new() -> BootKeyboardReport {
+    BootKeyboardReport {
+      modifiers: 0,
+      reserved: 0,
+      keys: [0u8; 6],
+    }
+  }
+
+  fn update(&mut self, keymap: &KeyMap, inputs: &DeviceInputs) {
+    let mut modifiers = 0;
+    let mut keys = [0u8; 6];
+    let mut count = 0;
+    for_each_key(keymap, inputs, |key| {
+      if let Some(bit) = modifier_bit(key) {
+        modifiers |= bit;
+      } else if !keys[..count.min(keys.len())].contains(&key) {
+        if count < keys.len() {
+          keys[count] = key;
+        }
+        count += 1;
+      }
+    });
+
+    // The boot protocol reports phantom state in every key slot when too many keys are pressed.
+    if count > keys.len() {
+      keys = [keycode::ERROR_ROLL_OVER; 6];
+    }
+
+    self.modifiers = modifiers;
+    self.keys = keys;
+  }
+}
+
+/// N-key rollover keyboard, for use with emulators and PC games that prefer keyboard input.
+pub struct KeyboardHid {
+  keymap: KeyMap,
+  protocol: HidProtocol,
+  report: KeyboardReport,
+  boot_report: BootKeyboardReport,
+}
+
+impl KeyboardHid {
+  pub fn new(keymap: KeyMap) -> KeyboardHid {
+    KeyboardHid {
+      keymap,
+      protocol: HidProtocol::Report,
+      report: KeyboardReport::new(),
+      boot_report: BootKeyboardReport::new(),
+    }
+  }
+
+  pub fn keymap(&self) -> &KeyMap {
+    &self.keymap
+  }
+
+  pub fn set_keymap(&mut self, keymap: KeyMap) {
+    self.keymap = keymap;
+  }
+}
+
+impl Hid for KeyboardHid {
+  fn identity(&self) -> DeviceIdentity {
+    DeviceIdentity {
+      vid: 0x1209,
+      pid: 0x214F,
+      manufacturer: "jmgao",
+      product: "Passing Link (Keyboard)",
+    }
+  }
+
+  fn update_inputs(&mut self, inputs: &DeviceInputs) {
+    self.report.update(&self.keymap, inputs);
+    self.boot_report.update(&self.keymap, inputs);
+  }
+
+  fn interface_protocol(&self) -> (u8, u8) {
+    (
+      0x01, // Boot interface subclass
+      0x01, // Keyboard
+    )
+  }
+
+  fn set_protocol(&mut self, protocol: HidProtocol) {
+    self.protocol = protocol;
+  }
+
+  #[rustfmt::skip]
+  fn report_descriptor(&self) -> &[u8] {
+    &[
+      0x05, 0x01,        // Usage Page (Generic Desktop Ctrls)
+      0x09, 0x06,        // Usage (Keyboard)
+      0xA1, 0x01,        // Collection (Application)
+      0x05, 0x07,        //   Usage Page (Kbrd/Keypad)
+      0x19, 0xE0,        //   Usage Minimum (0xE0)
+      0x29, 0xE7,        //   Usage Maximum (0xE7)
+      0x15, 0x00,        //   Logical Minimum (0)
+      0x25, 0x01,        //   Logical Maximum (1)
+      0x75, 0x01,        //   Report Size (1)
+      0x95, 0x08,        //   Report Count (8)
+      0x81, 0x02,        //   Input (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position)
+      0x75, 0x08,        //   Report Size (8)
+      0x95, 0x01,        //   Report Count (1)
+      0x81, 0x01,        //   Input (Const,Array,Abs,No Wrap,Linear,Preferred State,No Null Position)
+
+      0x05, 0x08,        //   Usage Page (LEDs)
+      0x19, 0x01,        //   Usage Minimum (Num Lock)
+      0x29, 0x05,        //   Usage Maximum (Kana)
+      0x75, 0x01,        //   Report Size (1)
+      0x95, 0x05,        //   Report Count (5)
+      0x91, 0x02,        //   Output (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position,Non-volatile)
+      0x75, 0x03,        //   Report Size (3)
+      0x95, 0x01,        //   Report Count (1)
+      0x91, 0x01,        //   Output (Const,Array,Abs,No Wrap,Linear,Preferred State,No Null Position,Non-volatile)
+
+      0x05, 0x07,        //   Usage Page (Kbrd/Keypad)
+      0x19, 0x00,        //   Usage Minimum (0x00)
+      0x29, 0x7F,        //   Usage Maximum (0x7F)
+      0x15, 0x00,        //   Logical Minimum (0)
+      0x25, 0x01,        //   Logical Maximum (1)
+      0x75, 0x01,        //   Report Size (1)
+      0x95, 0x80,        //   Report Count (128)
+      0x81, 0x02,        //   Input (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position)
+      0xC0,              // End Collection
+    ]
+  }
+
+  fn set_report(&mut self, report_type: HidReportType, report_id: u8, data: &[u8]) -> Result<(), ()> {
+    if report_type == HidReportType::Output && report_id == 0 {
+      // Keyboard LEDs: we don't have any, but hosts (especially BIOSes) get upset if we refuse.
+      debug!("KeyboardHid: LED state = {:?}", data);
+      Ok(())
+    } else {
+      warn!(
+        "KeyboardHid::set_report({:?}, {:#x}, {} bytes) unhandled, data = {:?}",
+        report_type,
+        report_id,
+        data.len(),
+        data
+      );
+      Err(())
+    }
+  }
+
+  fn get_report(&mut self, report_type: HidReportType, report_id: u8, _length: Option<u16>) -> Result<&[u8], ()> {
+    if report_type == HidReportType::Input && report_id == 0 {
+      let slice = match self.protocol {
+        HidProtocol::Boot => unsafe {
+          core::slice::from_raw_parts(
+            (&self.boot_report) as *const BootKeyboardReport as *const u8,
+            core::mem::size_of_val(&self.boot_report),
+          )
+        },
+
+        HidProtocol::Report => unsafe {
+          core::slice::from_raw_parts(
+            (&self.report) as *const KeyboardReport as *const u8,
+            core::mem::size_of_val(&self.report),
+          )
+        },
+      };
+      Ok(slice)
+    } else {
+      error!("KeyboardHid: unexpected report: ({:?}, {:#x})", report_type, report_id);
+      Err(())
+    }
+  }
+}
@@ -0,0 +1,518 @@
+use core::convert::TryFrom;
+
+use usb_device::class_prelude::*;
+use usb_device::control::{Recipient, RequestType};
+use usb_device::{UsbDirection, UsbError};
+
+use crate::input::DeviceInputs;
+
+mod keyboard;
+pub use keyboard::{keycode, KeyMap, KeyboardHid};
+
+mod pc;
+pub use pc::PCHid;
+
+mod ps3;
+pub use ps3::PS3Hid;
+
+mod ps4;
+pub use ps4::{PS4Hid, PS4OutputReport};
+
+mod switch;
+pub use switch::SwitchHid;
+
+const DESCRIPTOR_TYPE_REPORT: u8 = 0x22;
+
+#[derive(Prim, Clone, Copy, Debug, PartialEq)]
+#[prim(ty = "u8")]
+pub enum HidRequest {
+  GetReport = 0x01,
+  GetIdle = 0x02,
+  GetProtocol = 0x03,
+
+  SetReport = 0x09,
+  SetIdle = 0x0a,
+  SetProtocol = 0x0b,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HidReportType {
+  Input,
+  Output,
+  Feature,
+  Reserved(u8),
+}
+
+impl From<u8> for HidReportType {
+  fn from(x: u8) -> HidReportType {
+    match x {
+      1 => HidReportType::Input,
+      2 => HidReportType::Output,
+      3 => HidReportType::Feature,
+      _ => HidReportType::Reserved(x),
+    }
+  }
+}
+
+#[derive(Prim, Clone, Copy, Debug, PartialEq)]
+#[prim(ty = "u8")]
+pub enum HidProtocol {
+  Boot = 0x00,
+  Report = 0x01,
+}
+
+/// USB device descriptor values that a Hid implementation needs in order to be recognized by the host.
+#[derive(Clone, Copy, Debug)]
+pub struct DeviceIdentity {
+  pub vid: u16,
+  pub pid: u16,
+  pub manufacturer: &'static str,
+  pub product: &'static str,
+}
+
+/// Scratch space for feature reports that are built on request, rather than returned from static data.
+pub(crate) struct FeatureBuffer([u8; 64]);
+
+impl FeatureBuffer {
+  pub(crate) const fn new() -> FeatureBuffer {
+    FeatureBuffer([0u8; 64])
+  }
+
+  /// Copy a canned feature report in, zero padded, and return as many bytes of it as the host asked for.
+  pub(crate) fn canned(&mut self, data: &[u8], length: Option<u16>) -> &[u8] {
+    for byte in self.0.iter_mut() {
+      *byte = 0;
+    }
+    self.0[..data.len()].copy_from_slice(data);
+
+    let len = length.map(|len| len as usize).unwrap_or(data.len()).min(self.0.len());
+    &self.0[..len]
+  }
+}
+
+impl core::ops::Deref for FeatureBuffer {
+  type Target = [u8; 64];
+
+  fn deref(&self) -> &[u8; 64] {
+    &self.0
+  }
+}
+
+impl core::ops::DerefMut for FeatureBuffer {
+  fn deref_mut(&mut self) -> &mut [u8; 64] {
+    &mut self.0
+  }
+}
+
+pub trait Hid {
+  fn identity(&self) -> DeviceIdentity;
+
+  /// Update the input report with the current state of the inputs.
+  fn update_inputs(&mut self, inputs: &DeviceInputs);
+
+  /// Interface subclass and protocol. Devices that support the boot protocol should return (1, 1) for keyboards and
+  /// (1, 2) for mice.
+  fn interface_protocol(&self) -> (u8, u8) {
+    (0x00, 0x00)
+  }
+
+  /// Called when the host switches between the boot and report protocols, only for boot interfaces.
+  fn set_protocol(&mut self, _protocol: HidProtocol) {}
+
+  /// Called with each report received on the interrupt OUT endpoint.
+  /// If the report descriptor uses report IDs, the first byte is the report ID.
+  fn output_report(&mut self, data: &[u8]) {
+    debug!("Hid::output_report unhandled, data = {:?}", data);
+  }
+
+  fn report_descriptor(&self) -> &[u8];
+  fn get_report(&mut self, report_type: HidReportType, report_id: u8, length: Option<u16>) -> Result<&[u8], ()>;
+  fn set_report(&mut self, report_type: HidReportType, report_id: u8, data: &[u8]) -> Result<(), ()>;
+}
+
+/// Hid implementation selected at boot.
+pub enum AnyHid {
+  Keyboard(KeyboardHid),
+  PC(PCHid),
+  PS3(PS3Hid),
+  PS4(PS4Hid),
+  Switch(SwitchHid),
+}
+
+impl AnyHid {
+  pub fn name(&self) -> &'static str {
+    match self {
+      AnyHid::Keyboard(_) => "Keyboard",
+      AnyHid::PC(_) => "PC",
+      AnyHid::PS3(_) => "PS3",
+      AnyHid::PS4(_) => "PS4",
+      AnyHid::Switch(_) => "Switch",
+    }
+  }
+
+  /// The rumble and lightbar state requested by the host, if the selected mode supports it and the host has sent one.
+  pub fn ps4_output(&self) -> Option<PS4OutputReport> {
+    match self {
+      AnyHid::PS4(hid) => hid.output(),
+      _ => None,
+    }
+  }
+}
+
+impl Hid for AnyHid {
+  fn identity(&self) -> DeviceIdentity {
+    match self {
+      AnyHid::Keyboard(hid) => hid.identity(),
+      AnyHid::PC(hid) => hid.identity(),
+      AnyHid::PS3(hid) => hid.identity(),
+      AnyHid::PS4(hid) => hid.identity(),
+      AnyHid::Switch(hid) => hid.identity(),
+    }
+  }
+
+  fn update_inputs(&mut self, inputs: &DeviceInputs) {
+    match self {
+      AnyHid::Keyboard(hid) => hid.update_inputs(inputs),
+      AnyHid::PC(hid) => hid.update_inputs(inputs),
+      AnyHid::PS3(hid) => hid.update_inputs(inputs),
+      AnyHid::PS4(hid) => hid.update_inputs(inputs),
+      AnyHid::Switch(hid) => hid.update_inputs(inputs),
+    }
+  }
+
+  fn interface_protocol(&self) -> (u8, u8) {
+    match self {
+      AnyHid::Keyboard(hid) => hid.interface_protocol(),
+      AnyHid::PC(hid) => hid.interface_protocol(),
+      AnyHid::PS3(hid) => hid.interface_protocol(),
+      AnyHid::PS4(hid) => hid.interface_protocol(),
+      AnyHid::Switch(hid) => hid.interface_protocol(),
+    }
+  }
+
+  fn set_protocol(&mut self, protocol: HidProtocol) {
+    match self {
+      AnyHid::Keyboard(hid) => hid.set_protocol(protocol),
+      AnyHid::PC(hid) => hid.set_protocol(protocol),
+      AnyHid::PS3(hid) => hid.set_protocol(protocol),
+      AnyHid::PS4(hid) => hid.set_protocol(protocol),
+      AnyHid::Switch(hid) => hid.set_protocol(protocol),
+    }
+  }
+
+  fn output_report(&mut self, data: &[u8]) {
+    match self {
+      AnyHid::Keyboard(hid) => hid.output_report(data),
+      AnyHid::PC(hid) => hid.output_report(data),
+      AnyHid::PS3(hid) => hid.output_report(data),
+      AnyHid::PS4(hid) => hid.output_report(data),
+      AnyHid::Switch(hid) => hid.output_report(data),
+    }
+  }
+
+  fn report_descriptor(&self) -> &[u8] {
+    match self {
+      AnyHid::Keyboard(hid) => hid.report_descriptor(),
+      AnyHid::PC(hid) => hid.report_descriptor(),
+      AnyHid::PS3(hid) => hid.report_descriptor(),
+      AnyHid::PS4(hid) => hid.report_descriptor(),
+      AnyHid::Switch(hid) => hid.report_descriptor(),
+    }
+  }
+
+  fn get_report(&mut self, report_type: HidReportType, report_id: u8, length: Option<u16>) -> Result<&[u8], ()> {
+    match self {
+      AnyHid::Keyboard(hid) => hid.get_report(report_type, report_id, length),
+      AnyHid::PC(hid) => hid.get_report(report_type, report_id, length),
+      AnyHid::PS3(hid) => hid.get_report(report_type, report_id, length),
+      AnyHid::PS4(hid) => hid.get_report(report_type, report_id, length),
+      AnyHid::Switch(hid) => hid.get_report(report_type, report_id, length),
+    }
+  }
+
+  fn set_report(&mut self, report_type: HidReportType, report_id: u8, data: &[u8]) -> Result<(), ()> {
+    match self {
+      AnyHid::Keyboard(hid) => hid.set_report(report_type, report_id, data),
+      AnyHid::PC(hid) => hid.set_report(report_type, report_id, data),
+      AnyHid::PS3(hid) => hid.set_report(report_type, report_id, data),
+      AnyHid::PS4(hid) => hid.set_report(report_type, report_id, data),
+      AnyHid::Switch(hid) => hid.set_report(report_type, report_id, data),
+    }
+  }
+}
+
+pub struct HidClass<'a, H: Hid, B: UsbBus> {
+  hid: H,
+  interface: InterfaceNumber,
+  ep_in: EndpointIn<'a, B>,
+  ep_out: EndpointOut<'a, B>,
+  idle: u8,
+  protocol: HidProtocol,
+}
+
+impl<H: Hid, B: UsbBus> HidClass<'_, H, B> {
+  pub fn new(hid: H, alloc: &UsbBusAllocator<B>) -> HidClass<'_, H, B> {
+    let ep_in = alloc
+      .alloc(
+        Some(EndpointAddress::from_parts(4, UsbDirection::In)),
+        EndpointType::Interrupt,
+        64,
+        1,
+      )
+      .unwrap();
+
+    // TODO: Should we be allocating ep_out in HidDevice instead?
+    let ep_out = alloc
+      .alloc(
+        Some(EndpointAddress::from_parts(3, UsbDirection::Out)),
+        EndpointType::Interrupt,
+        64,
+        1,
+      )
+      .unwrap();
+
+    HidClass {
+      hid,
+      interface: alloc.interface(),
+      ep_in,
+      ep_out,
+      idle: 0,
+      protocol: HidProtocol::Report,
+    }
+  }
+
+  pub fn hid(&self) -> &H {
+    &self.hid
+  }
+
+  pub fn send(&mut self, inputs: &DeviceInputs) {
+    self.hid.update_inputs(inputs);
+    let data = self
+      .hid
+      .get_report(HidReportType::Input, 0, None)
+      .expect("failed to get report");
+    let result = self.ep_in.write(data);
+    if let Ok(len) = result {
+      if len != data.len() {
+        error!(
+          "write returned short: expected to write {} bytes, actually wrote {}",
+          data.len(),
+          len
+        );
+      }
+    }
+  }
+
+  fn get_report(&mut self, xfer: ControlIn<B>) {
+    debug!("HidClass::get_report");
+    let req = xfer.request();
+    let [report_type, report_id] = req.value.to_be_bytes();
+    let report_type = HidReportType::from(report_type);
+    match self.hid.get_report(report_type, report_id, Some(req.length)) {
+      Ok(data) => xfer.accept_with(data).unwrap(),
+      Err(()) => xfer.reject().unwrap(),
+    };
+  }
+
+  fn set_report(&mut self, xfer: ControlOut<B>) {
+    debug!("HidClass::set_report");
+    let req = xfer.request();
+    let [report_type, report_id] = req.value.to_be_bytes();
+    let report_type = HidReportType::from(report_type);
+    match self.hid.set_report(report_type, report_id, xfer.data()) {
+      Ok(()) => xfer.accept().unwrap(),
+      Err(()) => xfer.reject().unwrap(),
+    };
+  }
+
+  fn get_idle(&mut self, xfer: ControlIn<B>) {
+    let req = xfer.request();
+    let [should_be_zero, report_id] = req.value.to_be_bytes();
+    if should_be_zero != 0 {
+      error!("HidClass::get_idle({}, {}): invalid request", should_be_zero, report_id);
+      xfer.reject().unwrap();
+    } else {
+      if report_id != 0 {
+        warn!("HidClass::get_idle unimplemented for nonzero report id");
+      }
+      xfer.accept_with(core::slice::from_ref(&self.idle)).unwrap();
+    }
+  }
+
+  fn set_idle(&mut self, xfer: ControlOut<B>) {
+    let req = xfer.request();
+    let [idle, report_id] = req.value.to_be_bytes();
+    warn!("HidClass::set_idle({}, {}) mostly unimplemented", idle, report_id);
+    if report_id == 0 {
+      self.idle = idle;
+    }
+    xfer.accept().unwrap();
+  }
+
+  fn is_boot_interface(&self) -> bool {
+    let (subclass, _) = self.hid.interface_protocol();
+    subclass == 0x01
+  }
+
+  fn get_protocol(&mut self, xfer: ControlIn<B>) {
+    if !self.is_boot_interface() {
+      error!("HidClass::get_protocol on non-boot interface");
+      xfer.reject().unwrap();
+      return;
+    }
+
+    debug!("HidClass::get_protocol = {:?}", self.protocol);
+    let protocol = self.protocol as u8;
+    xfer.accept_with(core::slice::from_ref(&protocol)).unwrap();
+  }
+
+  fn set_protocol(&mut self, xfer: ControlOut<B>) {
+    let value = xfer.request().value;
+    if !self.is_boot_interface() {
+      error!("HidClass::set_protocol({}) on non-boot interface", value);
+      xfer.reject().unwrap();
+      return;
+    }
+
+    match HidProtocol::try_from(value as u8) {
+      Ok(protocol) => {
+        info!("HidClass::set_protocol({:?})", protocol);
+        self.protocol = protocol;
+        self.hid.set_protocol(protocol);
+        xfer.accept().unwrap();
+      }
+
+      Err(_) => {
+        error!("HidClass::set_protocol({}): invalid protocol", value);
+        xfer.reject().unwrap();
+      }
+    }
+  }
+}
+
+impl<H: Hid, B: UsbBus> UsbClass<B> for HidClass<'_, H, B> {
+  fn poll(&mut self) {
+    let mut buf = [0u8; 64];
+    match self.ep_out.read(&mut buf) {
+      Ok(len) => self.hid.output_report(&buf[..len]),
+      Err(UsbError::WouldBlock) => {}
+      Err(err) => error!("HidClass: failed to read output report: {:?}", err),
+    }
+  }
+
+  fn reset(&mut self) {
+    info!("HidDevice::reset");
+    self.idle = 0;
+
+    // The device must return to the report protocol on reset.
+    self.protocol = HidProtocol::Report;
+    self.hid.set_protocol(HidProtocol::Report);
+  }
+
+  #[rustfmt::skip]
+  fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> usb_device::Result<()> {
+    debug!("HidClass::get_configuration_descriptors");
+    let (subclass, protocol) = self.hid.interface_protocol();
+    writer.interface(
+      self.interface,
+      0x03, // HID class
+      subclass,
+      protocol,
+    )?;
+
+    let report_descriptor = self.hid.report_descriptor();
+    let descriptor_len = (report_descriptor.len() as u16).to_le_bytes();
+    writer.write(
+      0x21,                                   // bDescriptorType (HID)
+      &[
+        0x11, 0x1,                            // bcdHID (1.11)
+        0,                                    // bCountryCode
+        1,                                    // bNumDescriptors
+        DESCRIPTOR_TYPE_REPORT,               // bDescriptorType (Report)
+        descriptor_len[0], descriptor_len[1], // bDescriptorLength
+      ],
+    )?;
+
+    writer.endpoint(&self.ep_in)?;
+    writer.endpoint(&self.ep_out)?;
+
+    Ok(())
+  }
+
+  fn get_string(&self, _index: StringIndex, _lang_id: u16) -> Option<&str> {
+    None
+  }
+
+  fn control_in(&mut self, xfer: ControlIn<B>) {
+    let req = *xfer.request();
+    if req.recipient != Recipient::Interface || req.index as u8 != u8::from(self.interface) {
+      return;
+    }
+
+    match req.request_type {
+      RequestType::Standard => match req.request {
+        control::Request::GET_DESCRIPTOR => {
+          let (descriptor_type, index) = req.descriptor_type_index();
+          if descriptor_type == DESCRIPTOR_TYPE_REPORT && index == 0 {
+            debug!("fulfilling GET_DESCRIPTOR(Report, {})", index);
+            let descriptor = self.hid.report_descriptor();
+            xfer.accept_with(descriptor).ok();
+          } else {
+            warn!("unhandled GET_DESCRIPTOR({:#x}, {})", descriptor_type, index);
+          }
+        }
+
+        _ => {
+          warn!(
+            "unhandled standard in request: request = {}, value = {}, index = {}, length = {}",
+            req.request, req.value, req.index, req.length
+          );
+        }
+      },
+
+      RequestType::Class => {
+        if let Ok(request) = HidRequest::try_from(req.request) {
+          match request {
+            HidRequest::GetReport => self.get_report(xfer),
+            HidRequest::GetIdle => self.get_idle(xfer),
+            HidRequest::GetProtocol => self.get_protocol(xfer),
+            _ => warn!("unhandled class in request type: {:?}", request),
+          }
+        } else {
+          warn!("unexpected class in request: {}", req.request);
+        }
+      }
+
+      RequestType::Vendor => warn!("unhandled vendor in request: {:?}", req),
+      RequestType::Reserved => warn!("unhandled reserved in request: {:?}", req),
+    }
+  }
+
+  fn control_out(&mut self, xfer: ControlOut<B>) {
+    let req = *xfer.request();
+    if req.recipient != Recipient::Interface || req.index as u8 != u8::from(self.interface) {
+      return;
+    }
+
+    match req.request_type {
+      RequestType::Standard => warn!("unhandled standard out request: {:?}", req),
+      RequestType::Class => {
+        if let Ok(request) = HidRequest::try_from(req.request) {
+          match request {
+            HidRequest::SetReport => self.set_report(xfer),
+            HidRequest::SetIdle => self.set_idle(xfer),
+            HidRequest::SetProtocol => self.set_protocol(xfer),
+            _ => warn!("unhandled class out request type: {:?}", request),
+          }
+        } else {
+          warn!("unexpected class out request: {}", req.request);
+        }
+      }
+      RequestType::Vendor => warn!("unhandled vendor out request: {:?}", req),
+      RequestType::Reserved => warn!("unhandled reserved out request: {:?}", req),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,182 @@
+use crate::hid::{DeviceIdentity, Hid, HidReportType};
+use crate::input::{DeviceInputs, Hat};
+
+#[allow(unused)]
+#[repr(packed)]
+struct PCHidReport {
+  /// Buttons are ordered to line up with Linux's default gamepad mapping (BTN_A, BTN_B, BTN_C, BTN_X, ...):
+  /// ✖, ○, (unused), □, △, (unused), L1, R1, L2, R2, Select, Start, Home, L3, R3, Trackpad.
+  buttons: [u8; 2],
+
+  /// Hat position in the low 4 bits, with 8 being neutral.
+  hat: u8,
+
+  left_stick_x: u8,
+  left_stick_y: u8,
+  right_stick_x: u8,
+  right_stick_y: u8,
+  left_trigger: u8,
+  right_trigger: u8,
+}
+
+impl PCHidReport {
+  fn new() -> PCHidReport {
+    PCHidReport {
+      buttons: [0u8; 2],
+      hat: 8,
+      left_stick_x: 128,
+      left_stick_y: 128,
+      right_stick_x: 128,
+      right_stick_y: 128,
+      left_trigger: 0,
+      right_trigger: 0,
+    }
+  }
+
+  fn update(&mut self, inputs: &DeviceInputs) {
+    self.left_stick_x = inputs.axis_left_stick_x.get();
+    self.left_stick_y = inputs.axis_left_stick_y.get();
+    self.right_stick_x = inputs.axis_right_stick_x.get();
+    self.right_stick_y = inputs.axis_right_stick_y.get();
+    self.left_trigger = inputs.axis_left_trigger.get();
+    self.right_trigger = inputs.axis_right_trigger.get();
+
+    let button_1 = inputs.button_south.get() as u8;
+    let button_2 = inputs.button_east.get() as u8;
+    let button_4 = inputs.button_west.get() as u8;
+    let button_5 = inputs.button_north.get() as u8;
+    let button_7 = inputs.button_l1.get() as u8;
+    let button_8 = inputs.button_r1.get() as u8;
+    let button_9 = inputs.button_l2.get() as u8;
+    let button_10 = inputs.button_r2.get() as u8;
+    let button_11 = inputs.button_select.get() as u8;
+    let button_12 = inputs.button_start.get() as u8;
+    let button_13 = inputs.button_home.get() as u8;
+    let button_14 = inputs.button_l3.get() as u8;
+    let button_15 = inputs.button_r3.get() as u8;
+    let button_16 = inputs.button_trackpad.get() as u8;
+
+    self.buttons[0] = button_1 | button_2 << 1 | button_4 << 3 | button_5 << 4 | button_7 << 6 | button_8 << 7;
+    self.buttons[1] = button_9
+      | button_10 << 1
+      | button_11 << 2
+      | button_12 << 3
+      | button_13 << 4
+      | button_14 << 5
+      | button_15 << 6
+      | button_16 << 7;
+
+    self.hat = match inputs.hat_dpad {
+      Hat::North => 0,
+      Hat::NorthEast => 1,
+      Hat::East => 2,
+      Hat::SouthEast => 3,
+      Hat::South => 4,
+      Hat::SouthWest => 5,
+      Hat::West => 6,
+      Hat::NorthWest => 7,
+      Hat::Neutral => 8,
+    };
+  }
+}
+
+/// Plain HID gamepad, for use with PCs.
+pub struct PCHid {
+  report: PCHidReport,
+}
+
+impl PCHid {
+  pub fn new() -> PCHid {
+    PCHid {
+      report: PCHidReport::new(),
+    }
+  }
+}
+
+impl Hid for PCHid {
+  fn identity(&self) -> DeviceIdentity {
+    DeviceIdentity {
+      vid: 0x1209,
+      pid: 0x214E,
+      manufacturer: "jmgao",
+      product: "Passing Link (PC)",
+    }
+  }
+
+  fn update_inputs(&mut self, inputs: &DeviceInputs) {
+    self.report.update(inputs);
+  }
+
+  #[rustfmt::skip]
+  fn report_descriptor(&self) -> &[u8] {
+    &[
+      0x05, 0x01,        // Usage Page (Generic Desktop Ctrls)
+      0x09, 0x05,        // Usage (Game Pad)
+      0xA1, 0x01,        // Collection (Application)
+      0x05, 0x09,        //   Usage Page (Button)
+      0x19, 0x01,        //   Usage Minimum (0x01)
+      0x29, 0x10,        //   Usage Maximum (0x10)
+      0x15, 0x00,        //   Logical Minimum (0)
+      0x25, 0x01,        //   Logical Maximum (1)
+      0x75, 0x01,        //   Report Size (1)
+      0x95, 0x10,        //   Report Count (16)
+      0x81, 0x02,        //   Input (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position)
+
+      0x05, 0x01,        //   Usage Page (Generic Desktop Ctrls)
+      0x09, 0x39,        //   Usage (Hat switch)
+      0x15, 0x00,        //   Logical Minimum (0)
+      0x25, 0x07,        //   Logical Maximum (7)
+      0x35, 0x00,        //   Physical Minimum (0)
+      0x46, 0x3B, 0x01,  //   Physical Maximum (315)
+      0x65, 0x14,        //   Unit (System: English Rotation, Length: Centimeter)
+      0x75, 0x04,        //   Report Size (4)
+      0x95, 0x01,        //   Report Count (1)
+      0x81, 0x42,        //   Input (Data,Var,Abs,No Wrap,Linear,Preferred State,Null State)
+      0x65, 0x00,        //   Unit (None)
+      0x75, 0x04,        //   Report Size (4)
+      0x95, 0x01,        //   Report Count (1)
+      0x81, 0x01,        //   Input (Const,Array,Abs,No Wrap,Linear,Preferred State,No Null Position)
+
+      0x09, 0x30,        //   Usage (X)
+      0x09, 0x31,        //   Usage (Y)
+      0x09, 0x33,        //   Usage (Rx)
+      0x09, 0x34,        //   Usage (Ry)
+      0x09, 0x32,        //   Usage (Z)
+      0x09, 0x35,        //   Usage (Rz)
+      0x15, 0x00,        //   Logical Minimum (0)
+      0x26, 0xFF, 0x00,  //   Logical Maximum (255)
+      0x35, 0x00,        //   Physical Minimum (0)
+      0x46, 0xFF, 0x00,  //   Physical Maximum (255)
+      0x75, 0x08,        //   Report Size (8)
+      0x95, 0x06,        //   Report Count (6)
+      0x81, 0x02,        //   Input (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position)
+      0xC0,              // End Collection
+    ]
+  }
+
+  fn set_report(&mut self, report_type: HidReportType, report_id: u8, data: &[u8]) -> Result<(), ()> {
+    warn!(
+      "PCHid::set_report({:?}, {:#x}, {} bytes) unhandled, data = {:?}",
+      report_type,
+      report_id,
+      data.len(),
+      data
+    );
+    Err(())
+  }
+
+  fn get_report(&mut self, report_type: HidReportType, report_id: u8, _length: Option<u16>) -> Result<&[u8], ()> {
+    if report_type == HidReportType::Input && report_id == 0 {
+      let slice = unsafe {
+        core::slice::from_raw_parts(
+          (&self.report) as *const PCHidReport as *const u8,
+          core::mem::size_of_val(&self.report),
+        )
+      };
+      Ok(slice)
+    } else {
+      error!("PCHid: unexpected report: ({:?}, {:#x})", report_type, report_id);
+      Err(())
+    }
+  }
+}
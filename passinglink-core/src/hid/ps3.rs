@@ -0,0 +1,348 @@
+use crate::hid::{DeviceIdentity, FeatureBuffer, Hid, HidReportType};
+use crate::input::DeviceInputs;
+
+#[allow(unused)]
+#[repr(packed)]
+struct PS3HidReport {
+  report_id: u8, // Always 0x1.
+  reserved_0: u8,
+
+  /// Select, L3, R3, Start, Up, Right, Down, Left, L2, R2, L1, R1, △, ○, ✖, □, PS, followed by padding.
+  buttons: [u8; 4],
+
+  left_stick_x: u8,
+  left_stick_y: u8,
+  right_stick_x: u8,
+  right_stick_y: u8,
+
+  reserved_1: [u8; 4],
+
+  /// Pressure for Up, Right, Down, Left, L2, R2, L1, R1, △, ○, ✖, □.
+  pressure: [u8; 12],
+
+  reserved_2: [u8; 3],
+
+  /// Plugged state, battery state, connection type.
+  status: [u8; 3],
+
+  reserved_3: [u8; 9],
+
+  /// Accelerometer X, Y, Z and gyro Z, as big endian 10-bit values.
+  motion: [u8; 8],
+}
+
+impl PS3HidReport {
+  fn new() -> PS3HidReport {
+    PS3HidReport {
+      report_id: 0x1,
+      reserved_0: 0,
+      buttons: [0u8; 4],
+      left_stick_x: 128,
+      left_stick_y: 128,
+      right_stick_x: 128,
+      right_stick_y: 128,
+      reserved_1: [0u8; 4],
+      pressure: [0u8; 12],
+      reserved_2: [0u8; 3],
+
+      // Plugged in, fully charged, USB.
+      status: [0x02, 0xee, 0x12],
+      reserved_3: [0u8; 9],
+
+      // Sitting flat on a table.
+      motion: [0x02, 0x00, 0x02, 0x00, 0x01, 0x90, 0x00, 0x02],
+    }
+  }
+
+  fn update(&mut self, inputs: &DeviceInputs) {
+    self.left_stick_x = inputs.axis_left_stick_x.get();
+    self.left_stick_y = inputs.axis_left_stick_y.get();
+    self.right_stick_x = inputs.axis_right_stick_x.get();
+    self.right_stick_y = inputs.axis_right_stick_y.get();
+
+    let (horizontal, vertical) = inputs.hat_dpad.to_directions();
+    let up = vertical == Some(true);
+    let right = horizontal == Some(true);
+    let down = vertical == Some(false);
+    let left = horizontal == Some(false);
+
+    let buttons = [
+      inputs.button_select.get(),
+      inputs.button_l3.get(),
+      inputs.button_r3.get(),
+      inputs.button_start.get(),
+      up,
+      right,
+      down,
+      left,
+      inputs.button_l2.get(),
+      inputs.button_r2.get(),
+      inputs.button_l1.get(),
+      inputs.button_r1.get(),
+      inputs.button_north.get(),
+      inputs.button_east.get(),
+      inputs.button_south.get(),
+      inputs.button_west.get(),
+      inputs.button_home.get(),
+    ];
+
+    self.buttons = [0, 0, 0, 0];
+    for (index, &pressed) in buttons.iter().enumerate() {
+      self.buttons[index / 8] |= (pressed as u8) << (index % 8);
+    }
+
+    // The buttons are digital, so they're either not pressed at all, or pressed all the way.
+    let pressure = |pressed: bool| if pressed { 255 } else { 0 };
+    self.pressure = [
+      pressure(up),
+      pressure(right),
+      pressure(down),
+      pressure(left),
+      inputs.axis_left_trigger.get(),
+      inputs.axis_right_trigger.get(),
+      pressure(inputs.button_l1.get()),
+      pressure(inputs.button_r1.get()),
+      pressure(inputs.button_north.get()),
+      pressure(inputs.button_east.get()),
+      pressure(inputs.button_south.get()),
+      pressure(inputs.button_west.get()),
+    ];
+  }
+}
+
+pub struct PS3Hid {
+  report: PS3HidReport,
+
+  /// Bluetooth address of the host, set by the console via feature report 0xf5.
+  host_address: [u8; 6],
+
+  feature_buf: FeatureBuffer,
+}
+
+impl PS3Hid {
+  pub fn new() -> PS3Hid {
+    PS3Hid {
+      report: PS3HidReport::new(),
+      host_address: [0u8; 6],
+      feature_buf: FeatureBuffer::new(),
+    }
+  }
+}
+
+impl Hid for PS3Hid {
+  fn identity(&self) -> DeviceIdentity {
+    // The PS3 refuses to talk to controllers that don't identify themselves as a DualShock 3.
+    DeviceIdentity {
+      vid: 0x054C,
+      pid: 0x0268,
+      manufacturer: "Sony",
+      product: "PLAYSTATION(R)3 Controller",
+    }
+  }
+
+  fn update_inputs(&mut self, inputs: &DeviceInputs) {
+    self.report.update(inputs);
+  }
+
+  #[rustfmt::skip]
+  fn report_descriptor(&self) -> &[u8] {
+    // Exact dump of the DualShock 3's HID report descriptor.
+    &[
+      0x05, 0x01,        // Usage Page (Generic Desktop Ctrls)
+      0x09, 0x04,        // Usage (Joystick)
+      0xA1, 0x01,        // Collection (Application)
+      0xA1, 0x02,        //   Collection (Logical)
+      0x85, 0x01,        //     Report ID (1)
+      0x75, 0x08,        //     Report Size (8)
+      0x95, 0x01,        //     Report Count (1)
+      0x15, 0x00,        //     Logical Minimum (0)
+      0x26, 0xFF, 0x00,  //     Logical Maximum (255)
+      0x81, 0x03,        //     Input (Const,Var,Abs,No Wrap,Linear,Preferred State,No Null Position)
+
+      0x75, 0x01,        //     Report Size (1)
+      0x95, 0x13,        //     Report Count (19)
+      0x15, 0x00,        //     Logical Minimum (0)
+      0x25, 0x01,        //     Logical Maximum (1)
+      0x35, 0x00,        //     Physical Minimum (0)
+      0x45, 0x01,        //     Physical Maximum (1)
+      0x05, 0x09,        //     Usage Page (Button)
+      0x19, 0x01,        //     Usage Minimum (0x01)
+      0x29, 0x13,        //     Usage Maximum (0x13)
+      0x81, 0x02,        //     Input (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position)
+      0x75, 0x01,        //     Report Size (1)
+      0x95, 0x0D,        //     Report Count (13)
+      0x06, 0x00, 0xFF,  //     Usage Page (Vendor Defined 0xFF00)
+      0x81, 0x03,        //     Input (Const,Var,Abs,No Wrap,Linear,Preferred State,No Null Position)
+
+      0x15, 0x00,        //     Logical Minimum (0)
+      0x26, 0xFF, 0x00,  //     Logical Maximum (255)
+      0x05, 0x01,        //     Usage Page (Generic Desktop Ctrls)
+      0x09, 0x01,        //     Usage (Pointer)
+      0xA1, 0x00,        //     Collection (Physical)
+      0x75, 0x08,        //       Report Size (8)
+      0x95, 0x04,        //       Report Count (4)
+      0x35, 0x00,        //       Physical Minimum (0)
+      0x46, 0xFF, 0x00,  //       Physical Maximum (255)
+      0x09, 0x30,        //       Usage (X)
+      0x09, 0x31,        //       Usage (Y)
+      0x09, 0x32,        //       Usage (Z)
+      0x09, 0x35,        //       Usage (Rz)
+      0x81, 0x02,        //       Input (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position)
+      0xC0,              //     End Collection
+
+      0x05, 0x01,        //     Usage Page (Generic Desktop Ctrls)
+      0x95, 0x13,        //     Report Count (19)
+      0x09, 0x01,        //     Usage (Pointer)
+      0x81, 0x02,        //     Input (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position)
+      0x95, 0x0C,        //     Report Count (12)
+      0x81, 0x01,        //     Input (Const,Array,Abs,No Wrap,Linear,Preferred State,No Null Position)
+      0x75, 0x10,        //     Report Size (16)
+      0x95, 0x04,        //     Report Count (4)
+      0x26, 0xFF, 0x03,  //     Logical Maximum (1023)
+      0x46, 0xFF, 0x03,  //     Physical Maximum (1023)
+      0x09, 0x01,        //     Usage (Pointer)
+      0x81, 0x02,        //     Input (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position)
+      0xC0,              //   End Collection
+
+      0xA1, 0x02,        //   Collection (Logical)
+      0x85, 0x02,        //     Report ID (2)
+      0x75, 0x08,        //     Report Size (8)
+      0x95, 0x30,        //     Report Count (48)
+      0x09, 0x01,        //     Usage (Pointer)
+      0xB1, 0x02,        //     Feature (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position,Non-volatile)
+      0xC0,              //   End Collection
+
+      0xA1, 0x02,        //   Collection (Logical)
+      0x85, 0xEE,        //     Report ID (-18)
+      0x75, 0x08,        //     Report Size (8)
+      0x95, 0x30,        //     Report Count (48)
+      0x09, 0x01,        //     Usage (Pointer)
+      0xB1, 0x02,        //     Feature (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position,Non-volatile)
+      0xC0,              //   End Collection
+
+      0xA1, 0x02,        //   Collection (Logical)
+      0x85, 0xEF,        //     Report ID (-17)
+      0x75, 0x08,        //     Report Size (8)
+      0x95, 0x30,        //     Report Count (48)
+      0x09, 0x01,        //     Usage (Pointer)
+      0xB1, 0x02,        //     Feature (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position,Non-volatile)
+      0xC0,              //   End Collection
+      0xC0,              // End Collection
+    ]
+  }
+
+  fn set_report(&mut self, report_type: HidReportType, report_id: u8, data: &[u8]) -> Result<(), ()> {
+    match (report_type, report_id) {
+      // LEDs and rumble.
+      (HidReportType::Output, 0x01) => Ok(()),
+
+      // Operational mode, sent by the console to start the flow of input reports.
+      (HidReportType::Feature, 0xf4) => {
+        info!("PS3Hid: received enable request: {:x?}", data);
+        Ok(())
+      }
+
+      // Bluetooth address of the console.
+      (HidReportType::Feature, 0xf5) => {
+        if data.len() < 8 {
+          error!("PS3Hid: unexpected size for report 0xf5: {}", data.len());
+          return Err(());
+        }
+        self.host_address.copy_from_slice(&data[2..8]);
+        info!("PS3Hid: host address set to {:x?}", self.host_address);
+        Ok(())
+      }
+
+      (HidReportType::Feature, 0xef) => Ok(()),
+
+      _ => {
+        warn!(
+          "PS3Hid::set_report({:?}, {:#x}, {} bytes) unhandled, data = {:?}",
+          report_type,
+          report_id,
+          data.len(),
+          data
+        );
+        Err(())
+      }
+    }
+  }
+
+  fn get_report(&mut self, report_type: HidReportType, report_id: u8, length: Option<u16>) -> Result<&[u8], ()> {
+    if let Some(len) = length {
+      trace!(
+        "PS3Hid::get_report({:?}, {:#x}): expecting {} bytes",
+        report_type,
+        report_id,
+        len
+      );
+    }
+
+    match (report_type, report_id) {
+      (HidReportType::Input, 0) | (HidReportType::Input, 0x01) => {
+        let slice = unsafe {
+          core::slice::from_raw_parts(
+            (&self.report) as *const PS3HidReport as *const u8,
+            core::mem::size_of_val(&self.report),
+          )
+        };
+        Ok(slice)
+      }
+
+      // Magic bytes requested with GET_REPORT(Feature, 0), i.e. wValue = 0x0300.
+      // Without these, the console doesn't believe we're a controller.
+      (HidReportType::Feature, 0) => Ok(&[0x21, 0x26, 0x01, 0x07, 0x00, 0x00, 0x00, 0x00]),
+
+      // Everything below was copied from an actual DualShock 3.
+      (HidReportType::Feature, 0x01) => Ok(self.feature_buf.canned(
+        &[
+          0x00, 0x01, 0x04, 0x00, 0x08, 0x0c, 0x01, 0x02, 0x18, 0x18, 0x18, 0x18, 0x09, 0x0a, 0x10, 0x11, 0x12, 0x13,
+          0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x02, 0x02, 0x02, 0x02, 0x00, 0x00, 0x00, 0x04, 0x04, 0x04, 0x04, 0x00,
+          0x00, 0x04, 0x00, 0x01, 0x02, 0x07, 0x00, 0x17, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ],
+        length,
+      )),
+
+      // Bluetooth address of the controller.
+      (HidReportType::Feature, 0xf2) => Ok(self.feature_buf.canned(
+        &[
+          0xf2, 0xff, 0xff, 0x00, 0x00, 0x06, 0xf5, 0x48, 0xe2, 0x49, 0x00, 0x03, 0x50, 0x81, 0xd8, 0x01, 0x8a,
+        ],
+        length,
+      )),
+
+      // Bluetooth address of the host.
+      (HidReportType::Feature, 0xf5) => {
+        let mut data = [0u8; 8];
+        data[0] = 0x01;
+        data[2..8].copy_from_slice(&self.host_address);
+        Ok(self.feature_buf.canned(&data, length))
+      }
+
+      (HidReportType::Feature, 0xef) => Ok(self.feature_buf.canned(
+        &[
+          0xef, 0x04, 0x00, 0x08, 0x03, 0x01, 0xa0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+          0x00, 0x00, 0x00, 0x00, 0x01, 0xff, 0x01, 0xff, 0x01, 0xff, 0x01, 0xff, 0x01, 0xff, 0x01, 0xff, 0x01, 0xff,
+          0x01, 0xff, 0x01, 0xff,
+        ],
+        length,
+      )),
+
+      (HidReportType::Feature, 0xf7) => Ok(self.feature_buf.canned(
+        &[0x02, 0x01, 0xf8, 0x02, 0xe2, 0x01, 0x05, 0xff, 0x04, 0x33, 0x00],
+        length,
+      )),
+
+      (HidReportType::Feature, 0xf8) => Ok(
+        self
+          .feature_buf
+          .canned(&[0x00, 0x01, 0x00, 0x00, 0x07, 0x03, 0x01, 0xb0, 0x00, 0x00], length),
+      ),
+
+      _ => {
+        error!("PS3Hid: unexpected report: ({:?}, {:#x})", report_type, report_id);
+        Err(())
+      }
+    }
+  }
+}
@@ -0,0 +1,518 @@
+use crate::auth::{self, Authenticator};
+use crate::hid::{DeviceIdentity, FeatureBuffer, Hid, HidReportType};
+use crate::input::{DeviceInputs, Hat};
+use crate::provision::{self, Provisioner};
+
+#[allow(unused)]
+#[repr(packed)]
+struct PS4HidReport {
+  report_id: u8, // Always 0x1.
+  left_stick_x: u8,
+  left_stick_y: u8,
+  right_stick_x: u8,
+  right_stick_y: u8,
+
+  /// First 4 bits are the hat position, followed by 14 bits for buttons, followed by a 6-bit report counter.
+  hat_buttons: [u8; 3],
+
+  left_trigger: u8,
+  right_trigger: u8,
+
+  /// Trackpad and tilt, presumably.
+  mystery: [u8; 54],
+}
+
+impl PS4HidReport {
+  fn new() -> PS4HidReport {
+    PS4HidReport {
+      report_id: 0x1,
+      left_stick_x: 0,
+      left_stick_y: 0,
+      right_stick_x: 0,
+      right_stick_y: 0,
+      hat_buttons: [0u8; 3],
+      left_trigger: 0,
+      right_trigger: 0,
+      mystery: [0u8; 54],
+    }
+  }
+
+  fn update(&mut self, inputs: &DeviceInputs) {
+    self.left_stick_x = inputs.axis_left_stick_x.get();
+    self.left_stick_y = inputs.axis_left_stick_y.get();
+    self.right_stick_x = inputs.axis_right_stick_x.get();
+    self.right_stick_y = inputs.axis_right_stick_y.get();
+
+    let button_1 = inputs.button_west.get() as u8;
+    let button_2 = inputs.button_south.get() as u8;
+    let button_3 = inputs.button_east.get() as u8;
+    let button_4 = inputs.button_north.get() as u8;
+    let button_5 = inputs.button_l1.get() as u8;
+    let button_6 = inputs.button_r1.get() as u8;
+    let button_7 = inputs.button_l2.get() as u8;
+    let button_8 = inputs.button_r2.get() as u8;
+    let button_9 = inputs.button_select.get() as u8;
+    let button_10 = inputs.button_start.get() as u8;
+    let button_11 = inputs.button_l3.get() as u8;
+    let button_12 = inputs.button_r3.get() as u8;
+    let button_13 = inputs.button_home.get() as u8;
+    let button_14 = inputs.button_trackpad.get() as u8;
+    let hat_value = match inputs.hat_dpad {
+      Hat::North => 0,
+      Hat::NorthEast => 1,
+      Hat::East => 2,
+      Hat::SouthEast => 3,
+      Hat::South => 4,
+      Hat::SouthWest => 5,
+      Hat::West => 6,
+      Hat::NorthWest => 7,
+      Hat::Neutral => 15,
+    };
+
+    self.hat_buttons = [0, 0, 0];
+    self.hat_buttons[0] |= hat_value;
+    self.hat_buttons[0] |= button_1 << 4 | button_2 << 5 | button_3 << 6 | button_4 << 7;
+    self.hat_buttons[1] = button_5
+      | button_6 << 1
+      | button_7 << 2
+      | button_8 << 3
+      | button_9 << 4
+      | button_10 << 5
+      | button_11 << 6
+      | button_12 << 7;
+    self.hat_buttons[2] = button_13 | button_14 << 1;
+    self.hat_buttons[2] |= inputs.counter & 0b00111111;
+
+    self.left_trigger = inputs.axis_left_trigger.get();
+    self.right_trigger = inputs.axis_right_trigger.get();
+  }
+}
+
+/// Decoded state of output report 0x05, sent by the host to control rumble and the lightbar.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PS4OutputReport {
+  /// Right (high frequency) motor.
+  pub rumble_weak: u8,
+
+  /// Left (low frequency) motor.
+  pub rumble_strong: u8,
+
+  pub led_red: u8,
+  pub led_green: u8,
+  pub led_blue: u8,
+
+  /// Lightbar flash durations, in units of 10ms. The lightbar is solid if either is zero.
+  pub led_flash_on: u8,
+  pub led_flash_off: u8,
+}
+
+impl PS4OutputReport {
+  const FLAG_RUMBLE: u8 = 0x01;
+  const FLAG_LED_COLOR: u8 = 0x02;
+  const FLAG_LED_FLASH: u8 = 0x04;
+
+  /// Apply a raw report 0x05 (including the report ID) on top of the current state.
+  /// Only the fields selected by the flags in the second byte are updated.
+  pub fn update(&mut self, data: &[u8]) -> Result<(), ()> {
+    if data.len() < 11 || data[0] != 0x05 {
+      error!("PS4OutputReport: malformed report: {:x?}", data);
+      return Err(());
+    }
+
+    let flags = data[1];
+    if flags & PS4OutputReport::FLAG_RUMBLE != 0 {
+      self.rumble_weak = data[4];
+      self.rumble_strong = data[5];
+    }
+
+    if flags & PS4OutputReport::FLAG_LED_COLOR != 0 {
+      self.led_red = data[6];
+      self.led_green = data[7];
+      self.led_blue = data[8];
+    }
+
+    if flags & PS4OutputReport::FLAG_LED_FLASH != 0 {
+      self.led_flash_on = data[9];
+      self.led_flash_off = data[10];
+    }
+
+    Ok(())
+  }
+}
+
+/// Length of the prefix of REPORT_DESCRIPTOR that's an exact dump of the Razer Panthera's HID report descriptor.
+const PANTHERA_REPORT_DESCRIPTOR_LEN: usize = 160;
+
+/// Length of the prefix of REPORT_DESCRIPTOR that declares the diagnostics report as well.
+const DIAGNOSTICS_REPORT_DESCRIPTOR_LEN: usize = PANTHERA_REPORT_DESCRIPTOR_LEN + 16;
+
+#[rustfmt::skip]
+const REPORT_DESCRIPTOR: &[u8] = &[
+  0x05, 0x01,        // Usage Page (Generic Desktop Ctrls)
+  0x09, 0x05,        // Usage (Game Pad)
+  0xA1, 0x01,        // Collection (Application)
+  0x85, 0x01,        //   Report ID (1)
+  0x09, 0x30,        //   Usage (X)
+  0x09, 0x31,        //   Usage (Y)
+  0x09, 0x32,        //   Usage (Z)
+  0x09, 0x35,        //   Usage (Rz)
+  0x15, 0x00,        //   Logical Minimum (0)
+  0x26, 0xFF, 0x00,  //   Logical Maximum (255)
+  0x75, 0x08,        //   Report Size (8)
+  0x95, 0x04,        //   Report Count (4)
+  0x81, 0x02,        //   Input (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position)
+
+  0x09, 0x39,        //   Usage (Hat switch)
+  0x15, 0x00,        //   Logical Minimum (0)
+  0x25, 0x07,        //   Logical Maximum (7)
+  0x35, 0x00,        //   Physical Minimum (0)
+  0x46, 0x3B, 0x01,  //   Physical Maximum (315)
+  0x65, 0x14,        //   Unit (System: English Rotation, Length: Centimeter)
+  0x75, 0x04,        //   Report Size (4)
+  0x95, 0x01,        //   Report Count (1)
+  0x81, 0x42,        //   Input (Data,Var,Abs,No Wrap,Linear,Preferred State,Null State)
+
+  0x65, 0x00,        //   Unit (None)
+  0x05, 0x09,        //   Usage Page (Button)
+  0x19, 0x01,        //   Usage Minimum (0x01)
+  0x29, 0x0E,        //   Usage Maximum (0x0E)
+  0x15, 0x00,        //   Logical Minimum (0)
+  0x25, 0x01,        //   Logical Maximum (1)
+  0x75, 0x01,        //   Report Size (1)
+  0x95, 0x0E,        //   Report Count (14)
+  0x81, 0x02,        //   Input (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position)
+
+  0x06, 0x00, 0xFF,  //   Usage Page (Vendor Defined 0xFF00)
+  0x09, 0x20,        //   Usage (0x20)
+  0x75, 0x06,        //   Report Size (6)
+  0x95, 0x01,        //   Report Count (1)
+  0x81, 0x02,        //   Input (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position)
+
+  0x05, 0x01,        //   Usage Page (Generic Desktop Ctrls)
+  0x09, 0x33,        //   Usage (Rx)
+  0x09, 0x34,        //   Usage (Ry)
+  0x15, 0x00,        //   Logical Minimum (0)
+  0x26, 0xFF, 0x00,  //   Logical Maximum (255)
+  0x75, 0x08,        //   Report Size (8)
+  0x95, 0x02,        //   Report Count (2)
+  0x81, 0x02,        //   Input (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position)
+
+  0x06, 0x00, 0xFF,  //   Usage Page (Vendor Defined 0xFF00)
+  0x09, 0x21,        //   Usage (0x21)
+  0x95, 0x36,        //   Report Count (54)
+  0x81, 0x02,        //   Input (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position)
+
+  0x85, 0x05,        //   Report ID (5)
+  0x09, 0x22,        //   Usage (0x22)
+  0x95, 0x1F,        //   Report Count (31)
+  0x91, 0x02,        //   Output (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position,Non-volatile)
+
+  0x85, 0x03,        //   Report ID (3)
+  0x0A, 0x21, 0x27,  //   Usage (0x2721)
+  0x95, 0x2F,        //   Report Count (47)
+  0xB1, 0x02,        //   Feature (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position,Non-volatile)
+  0xC0,              // End Collection
+
+  0x06, 0xF0, 0xFF,  // Usage Page (Vendor Defined 0xFFF0)
+  0x09, 0x40,        // Usage (0x40)
+  0xA1, 0x01,        // Collection (Application)
+  0x85, 0xF0,        //   Report ID (-16)
+  0x09, 0x47,        //   Usage (0x47)
+  0x95, 0x3F,        //   Report Count (63)
+  0xB1, 0x02,        //   Feature (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position,Non-volatile)
+  0x85, 0xF1,        //   Report ID (-15)
+  0x09, 0x48,        //   Usage (0x48)
+  0x95, 0x3F,        //   Report Count (63)
+  0xB1, 0x02,        //   Feature (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position,Non-volatile)
+  0x85, 0xF2,        //   Report ID (-14)
+  0x09, 0x49,        //   Usage (0x49)
+  0x95, 0x0F,        //   Report Count (15)
+  0xB1, 0x02,        //   Feature (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position,Non-volatile)
+  0x85, 0xF3,        //   Report ID (-13)
+  0x0A, 0x01, 0x47,  //   Usage (0x4701)
+  0x95, 0x07,        //   Report Count (7)
+  0xB1, 0x02,        //   Feature (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position,Non-volatile)
+  0xC0,              // End Collection
+
+  // Diagnostics, only present when enabled.
+  0x06, 0xA0, 0xFF,  // Usage Page (Vendor Defined 0xFFA0)
+  0x09, 0x04,        // Usage (0x04)
+  0xA1, 0x01,        // Collection (Application)
+  0x85, 0xA3,        //   Report ID (-93)
+  0x09, 0x04,        //   Usage (0x04)
+  0x95, 0x23,        //   Report Count (35)
+  0xB1, 0x02,        //   Feature (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position,Non-volatile)
+  0xC0,              // End Collection
+
+  // Key provisioning, only present when enabled.
+  0x06, 0xA0, 0xFF,  // Usage Page (Vendor Defined 0xFFA0)
+  0x09, 0x01,        // Usage (0x01)
+  0xA1, 0x01,        // Collection (Application)
+  0x85, 0xA0,        //   Report ID (-96)
+  0x09, 0x01,        //   Usage (0x01)
+  0x95, 0x3F,        //   Report Count (63)
+  0xB1, 0x02,        //   Feature (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position,Non-volatile)
+  0x85, 0xA1,        //   Report ID (-95)
+  0x09, 0x02,        //   Usage (0x02)
+  0x95, 0x3F,        //   Report Count (63)
+  0xB1, 0x02,        //   Feature (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position,Non-volatile)
+  0x85, 0xA2,        //   Report ID (-94)
+  0x09, 0x03,        //   Usage (0x03)
+  0x95, 0x0F,        //   Report Count (15)
+  0xB1, 0x02,        //   Feature (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position,Non-volatile)
+  0xC0,              // End Collection
+];
+
+pub struct PS4Hid {
+  auth: &'static Authenticator,
+  provisioner: Option<&'static Provisioner>,
+  diagnostics: bool,
+  report: PS4HidReport,
+  output: Option<PS4OutputReport>,
+
+  feature_buf: FeatureBuffer,
+}
+
+impl PS4Hid {
+  pub fn new(auth: &'static Authenticator) -> PS4Hid {
+    PS4Hid {
+      auth,
+      provisioner: None,
+      diagnostics: false,
+      report: PS4HidReport::new(),
+      output: None,
+      feature_buf: FeatureBuffer::new(),
+    }
+  }
+
+  /// Declare the diagnostics report in the report descriptor, so that it's no longer identical to the Panthera's.
+  /// The report is answered either way, for hosts that don't check the descriptor.
+  pub fn with_diagnostics(mut self) -> PS4Hid {
+    self.diagnostics = true;
+    self
+  }
+
+  /// Accept a new DS4 key via the vendor feature reports in the provisioning module.
+  /// This adds another collection to the report descriptor, after the diagnostics one, which is declared as well.
+  pub fn with_provisioner(mut self, provisioner: &'static Provisioner) -> PS4Hid {
+    self.provisioner = Some(provisioner);
+    self
+  }
+
+  /// The most recent rumble and lightbar state requested by the host, if it has sent any.
+  pub fn output(&self) -> Option<PS4OutputReport> {
+    self.output
+  }
+}
+
+impl Hid for PS4Hid {
+  fn identity(&self) -> DeviceIdentity {
+    DeviceIdentity {
+      vid: 0x1209,
+      pid: 0x214D,
+      manufacturer: "jmgao",
+      product: "Passing Link",
+    }
+  }
+
+  fn update_inputs(&mut self, inputs: &DeviceInputs) {
+    self.report.update(inputs);
+  }
+
+  fn output_report(&mut self, data: &[u8]) {
+    if data.first() == Some(&0x05) {
+      let mut output = self.output.unwrap_or_default();
+      if output.update(data).is_ok() {
+        debug!("PS4Hid: output state = {:?}", output);
+        self.output = Some(output);
+      }
+    } else {
+      warn!("PS4Hid: unexpected output report: {:x?}", data);
+    }
+  }
+
+  fn report_descriptor(&self) -> &[u8] {
+    if self.provisioner.is_some() {
+      REPORT_DESCRIPTOR
+    } else if self.diagnostics {
+      &REPORT_DESCRIPTOR[..DIAGNOSTICS_REPORT_DESCRIPTOR_LEN]
+    } else {
+      &REPORT_DESCRIPTOR[..PANTHERA_REPORT_DESCRIPTOR_LEN]
+    }
+  }
+
+  fn set_report(&mut self, report_type: HidReportType, report_id: u8, data: &[u8]) -> Result<(), ()> {
+    if report_type == HidReportType::Feature {
+      if report_id == 0xf0 {
+        if data.len() != 64 {
+          error!("unexpected nonce packet size: {}", data.len());
+          return Err(());
+        }
+
+        self.auth.set_nonce(data)
+      } else if report_id == provision::REPORT_KEY_DATA || report_id == provision::REPORT_KEY_COMMIT {
+        match self.provisioner {
+          Some(provisioner) if report_id == provision::REPORT_KEY_DATA => provisioner.set_key_data(data),
+          Some(provisioner) => provisioner.commit(data),
+          None => {
+            error!("received key provisioning report while provisioning is disabled");
+            Err(())
+          }
+        }
+      } else {
+        Err(())
+      }
+    } else if report_type == HidReportType::Output && report_id == 0x05 {
+      self.output_report(data);
+      Ok(())
+    } else {
+      warn!(
+        "PS4Device::set_report({:?}, {:#x}, {} bytes) unhandled, data = {:?}",
+        report_type,
+        report_id,
+        data.len(),
+        data
+      );
+      Err(())
+    }
+  }
+
+  fn get_report(&mut self, report_type: HidReportType, report_id: u8, length: Option<u16>) -> Result<&[u8], ()> {
+    if let Some(len) = length {
+      info!(
+        "PS4Hid::get_report({:?}, {:#x}): expecting {} bytes",
+        report_type, report_id, len
+      );
+    }
+
+    if report_id == 0 {
+      let slice = unsafe {
+        core::slice::from_raw_parts(
+          (&self.report) as *const PS4HidReport as *const u8,
+          core::mem::size_of_val(&self.report),
+        )
+      };
+      Ok(slice)
+    } else if report_id == 0x3 {
+      // Unclear, copied from an actual device..
+      if length == Some(48) {
+        Ok(&[
+          0x3, 0x21, 0x27, 0x4, 0x40, 0x7, 0x2c, 0x56, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0xd, 0xd, 0x0,
+          0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0,
+          0x0, 0x0, 0x0, 0x0, 0x0,
+        ])
+      } else {
+        error!("unexpected length for report 0x3, expected 48, got {}", length.unwrap());
+        Err(())
+      }
+    } else if report_id == 0xf1 {
+      if length == Some(64) {
+        if self.auth.get_signature_chunk(&mut self.feature_buf[..]).is_ok() {
+          Ok(&self.feature_buf[..])
+        } else {
+          Err(())
+        }
+      } else {
+        error!(
+          "unexpected length for report 0xf1, expected 16, got {}",
+          length.unwrap()
+        );
+        Err(())
+      }
+    } else if report_id == 0xf2 {
+      // The console only understands 0 (ready) and 16 (busy) here, so signing progress isn't reported through this.
+      // The diagnostics report has the signing times instead.
+      if length == Some(16) {
+        let value = if self.auth.signature_ready() {
+          info!("signature ready");
+          0
+        } else {
+          info!("signature not ready yet");
+          16
+        };
+
+        self.feature_buf[0] = 0xf2;
+        self.feature_buf[1] = self.auth.get_nonce_id();
+        self.feature_buf[2] = value;
+        Ok(&self.feature_buf[..16])
+      } else {
+        error!(
+          "unexpected length for report 0xf2, expected 16, got {}",
+          length.unwrap()
+        );
+        Err(())
+      }
+    } else if report_id == 0xf3 {
+      if length == Some(8) {
+        Ok(&[0xf3, 0, 56, 56, 0, 0, 0, 0])
+      } else {
+        error!("unexpected length for report 0xf3, expected 8, got {}", length.unwrap());
+        Err(())
+      }
+    } else if report_id == auth::REPORT_DIAGNOSTICS {
+      self
+        .auth
+        .get_diagnostics(&mut self.feature_buf[..auth::DIAGNOSTICS_REPORT_SIZE]);
+      let len = length.map_or(auth::DIAGNOSTICS_REPORT_SIZE, |len| len as usize);
+      Ok(&self.feature_buf[..len.min(auth::DIAGNOSTICS_REPORT_SIZE)])
+    } else if report_id == provision::REPORT_KEY_STATUS {
+      match self.provisioner {
+        Some(provisioner) => {
+          provisioner.get_status(&mut self.feature_buf[..16]);
+          Ok(&self.feature_buf[..16])
+        }
+        None => {
+          error!("received key provisioning report while provisioning is disabled");
+          Err(())
+        }
+      }
+    } else {
+      error!("unexpected report id: {:#x}", report_id);
+      Err(())
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn report_bytes(report: &PS4HidReport) -> &[u8] {
+    unsafe {
+      core::slice::from_raw_parts(
+        report as *const PS4HidReport as *const u8,
+        core::mem::size_of_val(report),
+      )
+    }
+  }
+
+  #[test]
+  fn report_neutral() {
+    let mut report = PS4HidReport::new();
+    report.update(&DeviceInputs::default());
+
+    let bytes = report_bytes(&report);
+    assert_eq!(bytes.len(), 64);
+    assert_eq!(&bytes[..10], &[0x01, 128, 128, 128, 128, 0x0f, 0x00, 0x00, 128, 128]);
+  }
+
+  #[test]
+  fn report_buttons() {
+    let mut inputs = DeviceInputs::default();
+    inputs.hat_dpad = Hat::SouthWest;
+    inputs.button_south.set_value(true);
+    inputs.button_north.set_value(true);
+    inputs.button_r1.set_value(true);
+    inputs.button_r3.set_value(true);
+    inputs.button_trackpad.set_value(true);
+    inputs.axis_left_stick_x.set_value(0);
+    inputs.axis_right_stick_y.set_value(255);
+    inputs.axis_right_trigger.set_value(255);
+
+    let mut report = PS4HidReport::new();
+    report.update(&inputs);
+
+    let bytes = report_bytes(&report);
+    assert_eq!(&bytes[..10], &[0x01, 0, 128, 128, 255, 0xa5, 0x82, 0x02, 128, 255]);
+  }
+}
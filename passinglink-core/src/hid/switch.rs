@@ -0,0 +1,185 @@
+use crate::hid::{DeviceIdentity, Hid, HidReportType};
+use crate::input::{DeviceInputs, Hat};
+
+#[allow(unused)]
+#[repr(packed)]
+struct SwitchHidReport {
+  /// Y, B, A, X, L, R, ZL, ZR, -, +, LS, RS, Home, Capture, followed by 2 bits of padding.
+  buttons: [u8; 2],
+
+  /// Hat position, with 8 being neutral.
+  hat: u8,
+
+  left_stick_x: u8,
+  left_stick_y: u8,
+  right_stick_x: u8,
+  right_stick_y: u8,
+
+  vendor: u8,
+}
+
+impl SwitchHidReport {
+  fn new() -> SwitchHidReport {
+    SwitchHidReport {
+      buttons: [0u8; 2],
+      hat: 8,
+      left_stick_x: 128,
+      left_stick_y: 128,
+      right_stick_x: 128,
+      right_stick_y: 128,
+      vendor: 0,
+    }
+  }
+
+  fn update(&mut self, inputs: &DeviceInputs) {
+    self.left_stick_x = inputs.axis_left_stick_x.get();
+    self.left_stick_y = inputs.axis_left_stick_y.get();
+    self.right_stick_x = inputs.axis_right_stick_x.get();
+    self.right_stick_y = inputs.axis_right_stick_y.get();
+
+    // Buttons are mapped by position, not by label: the Switch's A is on the east.
+    let button_y = inputs.button_west.get() as u8;
+    let button_b = inputs.button_south.get() as u8;
+    let button_a = inputs.button_east.get() as u8;
+    let button_x = inputs.button_north.get() as u8;
+    let button_l = inputs.button_l1.get() as u8;
+    let button_r = inputs.button_r1.get() as u8;
+    let button_zl = inputs.button_l2.get() as u8;
+    let button_zr = inputs.button_r2.get() as u8;
+    let button_minus = inputs.button_select.get() as u8;
+    let button_plus = inputs.button_start.get() as u8;
+    let button_ls = inputs.button_l3.get() as u8;
+    let button_rs = inputs.button_r3.get() as u8;
+    let button_home = inputs.button_home.get() as u8;
+    let button_capture = inputs.button_trackpad.get() as u8;
+
+    self.buttons[0] = button_y
+      | button_b << 1
+      | button_a << 2
+      | button_x << 3
+      | button_l << 4
+      | button_r << 5
+      | button_zl << 6
+      | button_zr << 7;
+    self.buttons[1] =
+      button_minus | button_plus << 1 | button_ls << 2 | button_rs << 3 | button_home << 4 | button_capture << 5;
+
+    self.hat = match inputs.hat_dpad {
+      Hat::North => 0,
+      Hat::NorthEast => 1,
+      Hat::East => 2,
+      Hat::SouthEast => 3,
+      Hat::South => 4,
+      Hat::SouthWest => 5,
+      Hat::West => 6,
+      Hat::NorthWest => 7,
+      Hat::Neutral => 8,
+    };
+  }
+}
+
+pub struct SwitchHid {
+  report: SwitchHidReport,
+}
+
+impl SwitchHid {
+  pub fn new() -> SwitchHid {
+    SwitchHid {
+      report: SwitchHidReport::new(),
+    }
+  }
+}
+
+impl Hid for SwitchHid {
+  fn identity(&self) -> DeviceIdentity {
+    // The Switch only accepts generic HID controllers that it knows about, so pretend to be a HORI Pokkén controller.
+    DeviceIdentity {
+      vid: 0x0F0D,
+      pid: 0x0092,
+      manufacturer: "HORI CO.,LTD.",
+      product: "POKKEN CONTROLLER",
+    }
+  }
+
+  fn update_inputs(&mut self, inputs: &DeviceInputs) {
+    self.report.update(inputs);
+  }
+
+  #[rustfmt::skip]
+  fn report_descriptor(&self) -> &[u8] {
+    // Exact dump of the HORI Pokkén controller's HID report descriptor.
+    &[
+      0x05, 0x01,        // Usage Page (Generic Desktop Ctrls)
+      0x09, 0x05,        // Usage (Game Pad)
+      0xA1, 0x01,        // Collection (Application)
+      0x15, 0x00,        //   Logical Minimum (0)
+      0x25, 0x01,        //   Logical Maximum (1)
+      0x35, 0x00,        //   Physical Minimum (0)
+      0x45, 0x01,        //   Physical Maximum (1)
+      0x75, 0x01,        //   Report Size (1)
+      0x95, 0x10,        //   Report Count (16)
+      0x05, 0x09,        //   Usage Page (Button)
+      0x19, 0x01,        //   Usage Minimum (0x01)
+      0x29, 0x10,        //   Usage Maximum (0x10)
+      0x81, 0x02,        //   Input (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position)
+
+      0x05, 0x01,        //   Usage Page (Generic Desktop Ctrls)
+      0x25, 0x07,        //   Logical Maximum (7)
+      0x46, 0x3B, 0x01,  //   Physical Maximum (315)
+      0x75, 0x04,        //   Report Size (4)
+      0x95, 0x01,        //   Report Count (1)
+      0x65, 0x14,        //   Unit (System: English Rotation, Length: Centimeter)
+      0x09, 0x39,        //   Usage (Hat switch)
+      0x81, 0x42,        //   Input (Data,Var,Abs,No Wrap,Linear,Preferred State,Null State)
+      0x65, 0x00,        //   Unit (None)
+      0x95, 0x01,        //   Report Count (1)
+      0x81, 0x01,        //   Input (Const,Array,Abs,No Wrap,Linear,Preferred State,No Null Position)
+
+      0x26, 0xFF, 0x00,  //   Logical Maximum (255)
+      0x46, 0xFF, 0x00,  //   Physical Maximum (255)
+      0x09, 0x30,        //   Usage (X)
+      0x09, 0x31,        //   Usage (Y)
+      0x09, 0x32,        //   Usage (Z)
+      0x09, 0x35,        //   Usage (Rz)
+      0x75, 0x08,        //   Report Size (8)
+      0x95, 0x04,        //   Report Count (4)
+      0x81, 0x02,        //   Input (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position)
+
+      0x06, 0x00, 0xFF,  //   Usage Page (Vendor Defined 0xFF00)
+      0x09, 0x20,        //   Usage (0x20)
+      0x95, 0x01,        //   Report Count (1)
+      0x81, 0x02,        //   Input (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position)
+
+      0x0A, 0x21, 0x26,  //   Usage (0x2621)
+      0x95, 0x08,        //   Report Count (8)
+      0x91, 0x02,        //   Output (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position,Non-volatile)
+      0xC0,              // End Collection
+    ]
+  }
+
+  fn set_report(&mut self, report_type: HidReportType, report_id: u8, data: &[u8]) -> Result<(), ()> {
+    warn!(
+      "SwitchHid::set_report({:?}, {:#x}, {} bytes) unhandled, data = {:?}",
+      report_type,
+      report_id,
+      data.len(),
+      data
+    );
+    Err(())
+  }
+
+  fn get_report(&mut self, report_type: HidReportType, report_id: u8, _length: Option<u16>) -> Result<&[u8], ()> {
+    if report_type == HidReportType::Input && report_id == 0 {
+      let slice = unsafe {
+        core::slice::from_raw_parts(
+          (&self.report) as *const SwitchHidReport as *const u8,
+          core::mem::size_of_val(&self.report),
+        )
+      };
+      Ok(slice)
+    } else {
+      error!("SwitchHid: unexpected report: ({:?}, {:#x})", report_type, report_id);
+      Err(())
+    }
+  }
+}
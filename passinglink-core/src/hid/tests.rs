@@ -0,0 +1,477 @@
+use std::boxed::Box;
+use std::vec::Vec;
+
+use usb_device::prelude::*;
+
+use super::*;
+use crate::auth::{AuthStateType, Authenticator, MockSigner, Signer, DIAGNOSTICS_REPORT_SIZE, REPORT_DIAGNOSTICS};
+use crate::input::Hat;
+use crate::mock::{MockUsbBus, MockUsbHost, TransferError};
+
+const REQUEST_TYPE_STANDARD_INTERFACE_IN: u8 = 0x81;
+const REQUEST_TYPE_CLASS_INTERFACE_IN: u8 = 0xa1;
+const REQUEST_TYPE_CLASS_INTERFACE_OUT: u8 = 0x21;
+
+const REPORT_TYPE_INPUT: u8 = 1;
+const REPORT_TYPE_OUTPUT: u8 = 2;
+const REPORT_TYPE_FEATURE: u8 = 3;
+
+const EP_IN: u8 = 4;
+const EP_OUT: u8 = 3;
+
+type Host<H> = MockUsbHost<HidClass<'static, H, MockUsbBus>>;
+
+fn host<H: Hid>(hid: H) -> Host<H> {
+  let identity = hid.identity();
+  let mut host = MockUsbHost::new(|alloc| {
+    let class = HidClass::new(hid, alloc);
+    let device = UsbDeviceBuilder::new(alloc, UsbVidPid(identity.vid, identity.pid))
+      .manufacturer(identity.manufacturer)
+      .product(identity.product)
+      .max_packet_size_0(64)
+      .build();
+    (device, class)
+  });
+
+  host.enumerate().expect("enumeration failed");
+  host
+}
+
+fn ps4_host() -> (Host<PS4Hid>, &'static Authenticator) {
+  let auth = Box::leak(Box::new(Authenticator::new()));
+  (host(PS4Hid::new(auth)), auth)
+}
+
+fn get_report<H: Hid>(
+  host: &mut Host<H>,
+  report_type: u8,
+  report_id: u8,
+  length: u16,
+) -> Result<Vec<u8>, TransferError> {
+  let value = u16::from_be_bytes([report_type, report_id]);
+  host.control_in(
+    REQUEST_TYPE_CLASS_INTERFACE_IN,
+    HidRequest::GetReport as u8,
+    value,
+    0,
+    length,
+  )
+}
+
+fn set_report<H: Hid>(host: &mut Host<H>, report_type: u8, report_id: u8, data: &[u8]) -> Result<(), TransferError> {
+  let value = u16::from_be_bytes([report_type, report_id]);
+  host.control_out(
+    REQUEST_TYPE_CLASS_INTERFACE_OUT,
+    HidRequest::SetReport as u8,
+    value,
+    0,
+    data,
+  )
+}
+
+fn nonce_packet(nonce_id: u8, part: u8, nonce: &[u8]) -> [u8; 64] {
+  let start = part as usize * 56;
+  let len = if part == 4 { 32 } else { 56 };
+  crate::provision::packet(0xf0, [nonce_id, part], &nonce[start..start + len])
+}
+
+#[test]
+fn enumerate() {
+  let (mut host, _) = ps4_host();
+  assert_eq!(UsbDeviceState::Configured, host.device_state());
+
+  let configuration = host.get_descriptor(0x02, 0, 255).unwrap();
+  let report_descriptor_len = (host.class().hid().report_descriptor().len() as u16).to_le_bytes();
+
+  #[rustfmt::skip]
+  let expected: &[u8] = &[
+    // Interface: HID, two endpoints.
+    0x09, 0x04, 0x00, 0x00, 0x02, 0x03, 0x00, 0x00, 0x00,
+
+    // HID descriptor.
+    0x09, 0x21, 0x11, 0x01, 0x00, 0x01, 0x22, report_descriptor_len[0], report_descriptor_len[1],
+
+    // Endpoints: interrupt IN 4 and interrupt OUT 3, 64 bytes, 1ms.
+    0x07, 0x05, 0x84, 0x03, 0x40, 0x00, 0x01,
+    0x07, 0x05, 0x03, 0x03, 0x40, 0x00, 0x01,
+  ];
+  assert_eq!(expected, &configuration[9..]);
+}
+
+#[test]
+fn report_descriptor() {
+  let (mut host, _) = ps4_host();
+  let expected = host.class().hid().report_descriptor().to_vec();
+  let descriptor = host
+    .control_in(REQUEST_TYPE_STANDARD_INTERFACE_IN, 0x06, 0x2200, 0, 512)
+    .unwrap();
+  assert_eq!(expected, descriptor);
+
+  // Only the first report descriptor exists.
+  assert_eq!(
+    Err(TransferError::Stall),
+    host.control_in(REQUEST_TYPE_STANDARD_INTERFACE_IN, 0x06, 0x2201, 0, 512)
+  );
+}
+
+#[test]
+fn input_reports() {
+  let (mut host, _) = ps4_host();
+  assert_eq!(None, host.read_interrupt(EP_IN));
+
+  let mut inputs = DeviceInputs::default();
+  inputs.hat_dpad = Hat::East;
+  inputs.button_south.set_value(true);
+  host.class_mut().send(&inputs);
+
+  let packet = host.read_interrupt(EP_IN).expect("no input report sent");
+  assert_eq!(64, packet.len());
+  assert_eq!(&[0x01, 128, 128, 128, 128, 0x22], &packet[..6]);
+
+  let report = get_report(&mut host, REPORT_TYPE_INPUT, 0, 64).unwrap();
+  assert_eq!(packet, report);
+}
+
+#[test]
+fn output_reports() {
+  let (mut host, _) = ps4_host();
+  assert_eq!(None, host.class().hid().output());
+
+  let mut report = [0u8; 32];
+  report[0] = 0x05;
+  report[1] = 0x02; // LED color
+  report[6..9].copy_from_slice(&[0xff, 0x00, 0x40]);
+  host.write_interrupt(EP_OUT, &report).unwrap();
+
+  let output = host.class().hid().output().unwrap();
+  assert_eq!((0xff, 0x00, 0x40), (output.led_red, output.led_green, output.led_blue));
+
+  report[1] = 0x01; // Rumble
+  report[4..6].copy_from_slice(&[0x10, 0x20]);
+  set_report(&mut host, REPORT_TYPE_OUTPUT, 0x05, &report).unwrap();
+
+  let output = host.class().hid().output().unwrap();
+  assert_eq!((0x10, 0x20), (output.rumble_weak, output.rumble_strong));
+  assert_eq!(0xff, output.led_red);
+}
+
+#[test]
+fn feature_reports() {
+  let (mut host, _) = ps4_host();
+
+  let report = get_report(&mut host, REPORT_TYPE_FEATURE, 0x03, 48).unwrap();
+  assert_eq!(48, report.len());
+  assert_eq!(&[0x03, 0x21, 0x27], &report[..3]);
+
+  let report = get_report(&mut host, REPORT_TYPE_FEATURE, 0xf3, 8).unwrap();
+  assert_eq!(&[0xf3, 0, 56, 56, 0, 0, 0, 0], &report[..]);
+
+  assert_eq!(
+    Err(TransferError::Stall),
+    get_report(&mut host, REPORT_TYPE_FEATURE, 0x03, 64)
+  );
+  assert_eq!(
+    Err(TransferError::Stall),
+    get_report(&mut host, REPORT_TYPE_FEATURE, 0x99, 64)
+  );
+
+  // The control pipe recovers after a stall.
+  assert!(get_report(&mut host, REPORT_TYPE_FEATURE, 0xf3, 8).is_ok());
+}
+
+#[test]
+fn idle() {
+  let (mut host, _) = ps4_host();
+  let get_idle = |host: &mut Host<PS4Hid>| host.control_in(REQUEST_TYPE_CLASS_INTERFACE_IN, 0x02, 0x0000, 0, 1);
+  assert_eq!(Ok(vec![0]), get_idle(&mut host));
+
+  host
+    .control_out(REQUEST_TYPE_CLASS_INTERFACE_OUT, 0x0a, 0x7d00, 0, &[])
+    .unwrap();
+  assert_eq!(Ok(vec![0x7d]), get_idle(&mut host));
+
+  host.enumerate().unwrap();
+  assert_eq!(Ok(vec![0]), get_idle(&mut host));
+}
+
+#[test]
+fn protocol() {
+  let get_protocol = |host: &mut Host<KeyboardHid>| host.control_in(REQUEST_TYPE_CLASS_INTERFACE_IN, 0x03, 0, 0, 1);
+  let set_protocol = |host: &mut Host<KeyboardHid>, protocol: u16| {
+    host.control_out(REQUEST_TYPE_CLASS_INTERFACE_OUT, 0x0b, protocol, 0, &[])
+  };
+
+  let mut host = host(KeyboardHid::new(KeyMap::default()));
+  assert_eq!(Ok(vec![HidProtocol::Report as u8]), get_protocol(&mut host));
+
+  set_protocol(&mut host, HidProtocol::Boot as u16).unwrap();
+  assert_eq!(Ok(vec![HidProtocol::Boot as u8]), get_protocol(&mut host));
+  assert_eq!(Err(TransferError::Stall), set_protocol(&mut host, 2));
+
+  // Reports switch to the 8 byte boot format.
+  host.class_mut().send(&DeviceInputs::default());
+  assert_eq!(Some(8), host.read_interrupt(EP_IN).map(|packet| packet.len()));
+
+  host.enumerate().unwrap();
+  assert_eq!(Ok(vec![HidProtocol::Report as u8]), get_protocol(&mut host));
+
+  // Non-boot interfaces reject protocol requests.
+  let (mut host, _) = ps4_host();
+  assert_eq!(
+    Err(TransferError::Stall),
+    host.control_in(REQUEST_TYPE_CLASS_INTERFACE_IN, 0x03, 0, 0, 1)
+  );
+}
+
+#[test]
+fn ps4_auth() {
+  let (mut host, auth) = ps4_host();
+  let nonce: Vec<u8> = (0..256).map(|i| (i * 13) as u8).collect();
+
+  // No signature is available before a nonce has been sent.
+  assert_eq!(
+    Err(TransferError::Stall),
+    get_report(&mut host, REPORT_TYPE_FEATURE, 0xf1, 64)
+  );
+
+  for part in 0..5 {
+    set_report(&mut host, REPORT_TYPE_FEATURE, 0xf0, &nonce_packet(7, part, &nonce)).unwrap();
+  }
+  assert_eq!(AuthStateType::ReadyToSign, auth.state());
+
+  let status = get_report(&mut host, REPORT_TYPE_FEATURE, 0xf2, 16).unwrap();
+  assert_eq!(&[0xf2, 7, 16], &status[..3]);
+
+  assert!(auth.perform_work(Some(&MockSigner)));
+  let status = get_report(&mut host, REPORT_TYPE_FEATURE, 0xf2, 16).unwrap();
+  assert_eq!(&[0xf2, 7, 0], &status[..3]);
+
+  let mut signature = Vec::new();
+  for part in 0..19 {
+    let packet = get_report(&mut host, REPORT_TYPE_FEATURE, 0xf1, 64).unwrap();
+    assert_eq!(64, packet.len());
+    assert_eq!(&[0xf1, 7, part, 0], &packet[..4]);
+    assert_eq!(crc::crc32::checksum_ieee(&packet[..60]).to_le_bytes(), packet[60..]);
+    signature.extend_from_slice(&packet[4..60]);
+  }
+
+  let mut nonce_array = [0u8; 256];
+  nonce_array.copy_from_slice(&nonce);
+  let mut expected = [0u8; 1064];
+  MockSigner.sign(&nonce_array, &mut expected).unwrap();
+  assert_eq!(&expected[..], &signature[..]);
+  assert_eq!(AuthStateType::Waiting, auth.state());
+
+  let diagnostics = get_report(&mut host, REPORT_TYPE_FEATURE, REPORT_DIAGNOSTICS, 64).unwrap();
+  assert_eq!(DIAGNOSTICS_REPORT_SIZE, diagnostics.len());
+  assert_eq!(&[REPORT_DIAGNOSTICS, AuthStateType::Waiting as u8], &diagnostics[..2]);
+  assert_eq!(&1u32.to_le_bytes(), &diagnostics[4..8]);
+
+  // The diagnostics report is answered without being declared, and clamped to what the host asks for.
+  let declares_diagnostics = |descriptor: &[u8]| descriptor.windows(2).any(|item| item == [0x85, REPORT_DIAGNOSTICS]);
+  assert_eq!(160, host.class().hid().report_descriptor().len());
+  assert!(!declares_diagnostics(host.class().hid().report_descriptor()));
+  let diagnostic_host = self::host(PS4Hid::new(auth).with_diagnostics());
+  assert!(declares_diagnostics(diagnostic_host.class().hid().report_descriptor()));
+
+  let diagnostics = get_report(&mut host, REPORT_TYPE_FEATURE, REPORT_DIAGNOSTICS, 8).unwrap();
+  assert_eq!(&[REPORT_DIAGNOSTICS, AuthStateType::Waiting as u8], &diagnostics[..2]);
+  assert_eq!(8, diagnostics.len());
+
+  // Malformed nonce packets are rejected.
+  let mut packet = nonce_packet(8, 0, &nonce);
+  packet[10] ^= 0xff;
+  assert_eq!(
+    Err(TransferError::Stall),
+    set_report(&mut host, REPORT_TYPE_FEATURE, 0xf0, &packet)
+  );
+  assert_eq!(
+    Err(TransferError::Stall),
+    set_report(&mut host, REPORT_TYPE_FEATURE, 0xf0, &packet[..32])
+  );
+  assert_eq!(AuthStateType::Waiting, auth.state());
+}
+
+#[test]
+fn other_interface() {
+  // Requests for another interface, such as CDC-ACM's GET_ENCAPSULATED_RESPONSE, which shares GET_REPORT's number,
+  // aren't answered by the HID interface.
+  let (mut host, auth) = ps4_host();
+  let value = u16::from_be_bytes([REPORT_TYPE_FEATURE, 0xf3]);
+  assert_eq!(
+    Err(TransferError::Stall),
+    host.control_in(
+      REQUEST_TYPE_CLASS_INTERFACE_IN,
+      HidRequest::GetReport as u8,
+      value,
+      1,
+      8
+    )
+  );
+  assert_eq!(
+    Ok(8),
+    host
+      .control_in(
+        REQUEST_TYPE_CLASS_INTERFACE_IN,
+        HidRequest::GetReport as u8,
+        value,
+        0,
+        8
+      )
+      .map(|data| data.len())
+  );
+
+  let nonce = [0u8; 256];
+  let value = u16::from_be_bytes([REPORT_TYPE_FEATURE, 0xf0]);
+  assert_eq!(
+    Err(TransferError::Stall),
+    host.control_out(
+      REQUEST_TYPE_CLASS_INTERFACE_OUT,
+      HidRequest::SetReport as u8,
+      value,
+      1,
+      &nonce_packet(1, 0, &nonce)
+    )
+  );
+  assert_eq!(AuthStateType::Waiting, auth.state());
+}
+
+#[test]
+fn ps4_provisioning() {
+  use crate::provision::{self, ProvisionState, Provisioner, KEY_SIZE, PART_COUNT, PART_SIZE};
+
+  // Provisioning is disabled by default, and doesn't show up in the report descriptor.
+  let (mut disabled_host, _) = ps4_host();
+  let descriptor = disabled_host.class().hid().report_descriptor().to_vec();
+  assert_eq!(Some(&0xc0), descriptor.last());
+  assert_eq!(
+    Err(TransferError::Stall),
+    get_report(
+      &mut disabled_host,
+      REPORT_TYPE_FEATURE,
+      provision::REPORT_KEY_STATUS,
+      16
+    )
+  );
+
+  let auth = Box::leak(Box::new(Authenticator::new()));
+  let provisioner = Box::leak(Box::new(Provisioner::new()));
+  let mut host = host(PS4Hid::new(auth).with_provisioner(provisioner));
+  let provisioning_descriptor = host.class().hid().report_descriptor().to_vec();
+  assert_eq!(&descriptor[..], &provisioning_descriptor[..descriptor.len()]);
+  assert_eq!(
+    &[0x06, 0xa0, 0xff],
+    &provisioning_descriptor[descriptor.len()..descriptor.len() + 3]
+  );
+
+  let key: Vec<u8> = (0..KEY_SIZE).map(|i| (i * 3) as u8).collect();
+  for part in 0..PART_COUNT {
+    let start = part as usize * PART_SIZE;
+    let end = (start + PART_SIZE).min(KEY_SIZE);
+    let data = provision::packet(provision::REPORT_KEY_DATA, [part, 0], &key[start..end]);
+    set_report(&mut host, REPORT_TYPE_FEATURE, provision::REPORT_KEY_DATA, &data).unwrap();
+  }
+
+  let key_crc = crc::crc32::checksum_ieee(&key).to_le_bytes();
+  let commit = provision::packet(provision::REPORT_KEY_COMMIT, [0, 0], &key_crc);
+  set_report(&mut host, REPORT_TYPE_FEATURE, provision::REPORT_KEY_COMMIT, &commit).unwrap();
+
+  let status = get_report(&mut host, REPORT_TYPE_FEATURE, provision::REPORT_KEY_STATUS, 16).unwrap();
+  assert_eq!(
+    &[provision::REPORT_KEY_STATUS, ProvisionState::Pending as u8, PART_COUNT],
+    &status[..3]
+  );
+}
+
+#[test]
+fn ps3_reports() {
+  let mut host = host(PS3Hid::new());
+  assert_eq!(148, host.class().hid().report_descriptor().len());
+
+  let mut inputs = DeviceInputs::default();
+  inputs.hat_dpad = Hat::East;
+  inputs.button_select.set_value(true);
+  inputs.button_l2.set_value(true);
+  inputs.axis_left_trigger.set_value(255);
+  inputs.axis_right_trigger.set_value(0);
+  inputs.button_south.set_value(true);
+  inputs.button_home.set_value(true);
+  host.class_mut().send(&inputs);
+
+  let packet = host.read_interrupt(EP_IN).expect("no input report sent");
+  assert_eq!(49, packet.len());
+
+  #[rustfmt::skip]
+  let expected: &[u8] = &[
+    // Report ID, then Select and Right, L2 and ✖, PS.
+    0x01, 0x00, 0x21, 0x41, 0x01, 0x00,
+
+    // Sticks.
+    128, 128, 128, 128,
+    0, 0, 0, 0,
+
+    // Pressure for Up, Right, Down, Left, L2, R2, L1, R1, △, ○, ✖, □.
+    0, 255, 0, 0, 255, 0, 0, 0, 0, 0, 255, 0,
+  ];
+  assert_eq!(expected, &packet[..expected.len()]);
+  assert_eq!(&[0x02, 0xee, 0x12], &packet[29..32]);
+
+  // Feature reports are cut short to the requested length.
+  let report = get_report(&mut host, REPORT_TYPE_FEATURE, 0xf2, 8).unwrap();
+  assert_eq!(&[0xf2, 0xff, 0xff, 0x00, 0x00, 0x06, 0xf5, 0x48], &report[..]);
+
+  let address = [0x01, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66];
+  set_report(&mut host, REPORT_TYPE_FEATURE, 0xf5, &address).unwrap();
+  let report = get_report(&mut host, REPORT_TYPE_FEATURE, 0xf5, 8).unwrap();
+  assert_eq!(&address[..], &report[..]);
+  assert_eq!(
+    Err(TransferError::Stall),
+    set_report(&mut host, REPORT_TYPE_FEATURE, 0xf5, &address[..4])
+  );
+}
+
+#[test]
+fn switch_reports() {
+  let mut host = host(SwitchHid::new());
+  assert_eq!(86, host.class().hid().report_descriptor().len());
+
+  let mut inputs = DeviceInputs::default();
+  inputs.hat_dpad = Hat::NorthWest;
+  inputs.button_west.set_value(true);
+  inputs.button_r2.set_value(true);
+  inputs.button_start.set_value(true);
+  inputs.button_trackpad.set_value(true);
+  inputs.axis_left_stick_x.set_value(0);
+  host.class_mut().send(&inputs);
+
+  // Y and ZR, then + and Capture, the hat, the sticks and a vendor byte.
+  let packet = host.read_interrupt(EP_IN).expect("no input report sent");
+  assert_eq!(&[0x81, 0x22, 7, 0, 128, 128, 128, 0][..], &packet[..]);
+
+  inputs = DeviceInputs::default();
+  host.class_mut().send(&inputs);
+  let packet = host.read_interrupt(EP_IN).expect("no input report sent");
+  assert_eq!(&[0x00, 0x00, 8, 128, 128, 128, 128, 0][..], &packet[..]);
+}
+
+#[test]
+fn pc_reports() {
+  let mut host = host(PCHid::new());
+  assert_eq!(80, host.class().hid().report_descriptor().len());
+
+  let mut inputs = DeviceInputs::default();
+  inputs.hat_dpad = Hat::South;
+  inputs.button_south.set_value(true);
+  inputs.button_r1.set_value(true);
+  inputs.button_select.set_value(true);
+  inputs.button_trackpad.set_value(true);
+  inputs.button_r2.set_value(true);
+  inputs.axis_left_trigger.set_value(0);
+  inputs.axis_right_trigger.set_value(255);
+  inputs.axis_right_stick_y.set_value(255);
+  host.class_mut().send(&inputs);
+
+  // ✖ and R1, then R2, Select and Trackpad, the hat, the sticks and the triggers.
+  let packet = host.read_interrupt(EP_IN).expect("no input report sent");
+  assert_eq!(&[0x81, 0x86, 4, 128, 128, 128, 255, 0, 255][..], &packet[..]);
+}
@@ -0,0 +1,281 @@
+use super::RawInputs;
+
+/// How a debounced button reacts to a change in its raw input.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DebounceMode {
+  /// Report the change immediately, then ignore the raw input until the window has elapsed.
+  Eager,
+
+  /// Report the change only after the raw input has held the new value for the entire window.
+  Deferred,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct DebounceConfig {
+  /// Length of the debounce window, in input polls.
+  pub window: u8,
+
+  /// Behavior when a button is pressed.
+  pub press: DebounceMode,
+
+  /// Behavior when a button is released.
+  pub release: DebounceMode,
+}
+
+impl DebounceConfig {
+  pub const fn default() -> DebounceConfig {
+    DebounceConfig {
+      window: 5,
+      press: DebounceMode::Eager,
+      release: DebounceMode::Deferred,
+    }
+  }
+}
+
+/// Debounce state for a single button.
+#[derive(Clone, Copy, Debug)]
+pub struct DebouncedButton {
+  /// The debounced value.
+  state: bool,
+
+  /// Number of polls remaining before the raw input will be looked at again, after an eager transition.
+  lockout: u8,
+
+  /// Number of consecutive polls for which the raw input has disagreed with the debounced value.
+  pending: u8,
+}
+
+impl DebouncedButton {
+  pub const fn new() -> DebouncedButton {
+    DebouncedButton {
+      state: false,
+      lockout: 0,
+      pending: 0,
+    }
+  }
+
+  pub fn get(&self) -> bool {
+    self.state
+  }
+
+  pub fn update(&mut self, config: &DebounceConfig, raw: bool) -> bool {
+    if self.lockout > 0 {
+      self.lockout -= 1;
+      return self.state;
+    }
+
+    if raw == self.state {
+      self.pending = 0;
+      return self.state;
+    }
+
+    let mode = if raw { config.press } else { config.release };
+    match mode {
+      DebounceMode::Eager => {
+        self.state = raw;
+        self.pending = 0;
+        self.lockout = config.window;
+      }
+
+      DebounceMode::Deferred => {
+        self.pending = self.pending.saturating_add(1);
+        if self.pending >= config.window {
+          self.state = raw;
+          self.pending = 0;
+        }
+      }
+    }
+
+    self.state
+  }
+}
+
+/// Debouncing stage between the raw pin reads and DeviceInputs.
+pub struct InputDebouncer {
+  config: DebounceConfig,
+
+  stick_up: DebouncedButton,
+  stick_down: DebouncedButton,
+  stick_left: DebouncedButton,
+  stick_right: DebouncedButton,
+
+  button_north: DebouncedButton,
+  button_east: DebouncedButton,
+  button_south: DebouncedButton,
+  button_west: DebouncedButton,
+
+  button_l1: DebouncedButton,
+  button_l2: DebouncedButton,
+  button_l3: DebouncedButton,
+
+  button_r1: DebouncedButton,
+  button_r2: DebouncedButton,
+  button_r3: DebouncedButton,
+
+  button_start: DebouncedButton,
+  button_select: DebouncedButton,
+  button_home: DebouncedButton,
+  button_trackpad: DebouncedButton,
+
+  mode_lock: DebouncedButton,
+  mode_ls: DebouncedButton,
+  mode_rs: DebouncedButton,
+  mode_ps3: DebouncedButton,
+}
+
+impl InputDebouncer {
+  pub const fn new(config: DebounceConfig) -> InputDebouncer {
+    InputDebouncer {
+      config,
+
+      stick_up: DebouncedButton::new(),
+      stick_down: DebouncedButton::new(),
+      stick_left: DebouncedButton::new(),
+      stick_right: DebouncedButton::new(),
+
+      button_north: DebouncedButton::new(),
+      button_east: DebouncedButton::new(),
+      button_south: DebouncedButton::new(),
+      button_west: DebouncedButton::new(),
+
+      button_l1: DebouncedButton::new(),
+      button_l2: DebouncedButton::new(),
+      button_l3: DebouncedButton::new(),
+
+      button_r1: DebouncedButton::new(),
+      button_r2: DebouncedButton::new(),
+      button_r3: DebouncedButton::new(),
+
+      button_start: DebouncedButton::new(),
+      button_select: DebouncedButton::new(),
+      button_home: DebouncedButton::new(),
+      button_trackpad: DebouncedButton::new(),
+
+      mode_lock: DebouncedButton::new(),
+      mode_ls: DebouncedButton::new(),
+      mode_rs: DebouncedButton::new(),
+      mode_ps3: DebouncedButton::new(),
+    }
+  }
+
+  pub fn config(&self) -> DebounceConfig {
+    self.config
+  }
+
+  pub fn set_config(&mut self, config: DebounceConfig) {
+    self.config = config;
+  }
+
+  pub fn update(&mut self, raw: &RawInputs) -> RawInputs {
+    let config = &self.config;
+    RawInputs {
+      stick_up: self.stick_up.update(config, raw.stick_up),
+      stick_down: self.stick_down.update(config, raw.stick_down),
+      stick_left: self.stick_left.update(config, raw.stick_left),
+      stick_right: self.stick_right.update(config, raw.stick_right),
+
+      button_north: self.button_north.update(config, raw.button_north),
+      button_east: self.button_east.update(config, raw.button_east),
+      button_south: self.button_south.update(config, raw.button_south),
+      button_west: self.button_west.update(config, raw.button_west),
+
+      button_l1: self.button_l1.update(config, raw.button_l1),
+      button_l2: self.button_l2.update(config, raw.button_l2),
+      button_l3: self.button_l3.update(config, raw.button_l3),
+
+      button_r1: self.button_r1.update(config, raw.button_r1),
+      button_r2: self.button_r2.update(config, raw.button_r2),
+      button_r3: self.button_r3.update(config, raw.button_r3),
+
+      button_start: self.button_start.update(config, raw.button_start),
+      button_select: self.button_select.update(config, raw.button_select),
+      button_home: self.button_home.update(config, raw.button_home),
+      button_trackpad: self.button_trackpad.update(config, raw.button_trackpad),
+
+      mode_lock: self.mode_lock.update(config, raw.mode_lock),
+      mode_ls: self.mode_ls.update(config, raw.mode_ls),
+      mode_rs: self.mode_rs.update(config, raw.mode_rs),
+      mode_ps3: self.mode_ps3.update(config, raw.mode_ps3),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::string::String;
+
+  /// Feed a trace of raw values (as a string of '0' and '1') through a button, and compare against the expected
+  /// debounced trace.
+  fn check(config: DebounceConfig, raw: &str, expected: &str) {
+    assert_eq!(raw.len(), expected.len());
+    let mut button = DebouncedButton::new();
+    let actual: String = raw
+      .chars()
+      .map(|c| if button.update(&config, c == '1') { '1' } else { '0' })
+      .collect();
+    assert_eq!(expected, actual, "raw = {}", raw);
+  }
+
+  fn config(window: u8, press: DebounceMode, release: DebounceMode) -> DebounceConfig {
+    DebounceConfig { window, press, release }
+  }
+
+  #[test]
+  fn clean_press() {
+    let config = DebounceConfig::default();
+    check(config, "000111111111110000000000", "000111111111111111000000");
+  }
+
+  #[test]
+  fn eager_press_ignores_bounce() {
+    let config = config(3, DebounceMode::Eager, DebounceMode::Eager);
+    check(config, "0010101111100000", "0011111111100000");
+    check(config, "0011110101000000", "0011110000000000");
+  }
+
+  #[test]
+  fn deferred_release_ignores_bounce() {
+    let config = DebounceConfig::default();
+    check(config, "01111101011010000000", "01111111111111111000");
+  }
+
+  #[test]
+  fn deferred_press_ignores_glitch() {
+    let config = config(3, DebounceMode::Deferred, DebounceMode::Deferred);
+    check(config, "0100110111111000100000", "0000000001111110000000");
+  }
+
+  #[test]
+  fn zero_window() {
+    let eager = config(0, DebounceMode::Eager, DebounceMode::Eager);
+    let deferred = config(0, DebounceMode::Deferred, DebounceMode::Deferred);
+    check(eager, "0101100111010", "0101100111010");
+    check(deferred, "0101100111010", "0101100111010");
+  }
+
+  #[test]
+  fn per_button_state() {
+    let mut debouncer = InputDebouncer::new(DebounceConfig::default());
+    let mut raw = RawInputs::default();
+
+    raw.button_south = true;
+    let output = debouncer.update(&raw);
+    assert!(output.button_south);
+    assert!(!output.button_east);
+
+    raw.button_south = false;
+    raw.button_east = true;
+    let output = debouncer.update(&raw);
+    assert!(output.button_south);
+    assert!(output.button_east);
+
+    raw.button_east = false;
+    for _ in 0..10 {
+      debouncer.update(&raw);
+    }
+    let output = debouncer.update(&raw);
+    assert!(!output.button_south);
+    assert!(!output.button_east);
+  }
+}
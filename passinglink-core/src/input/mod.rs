@@ -0,0 +1,566 @@
+#![allow(unused)]
+
+mod debounce;
+pub use debounce::*;
+
+mod socd;
+pub use socd::*;
+
+use core::fmt;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Axis(u8);
+
+impl Axis {
+  pub fn get(self) -> u8 {
+    self.0
+  }
+
+  pub fn set_value(&mut self, value: u8) {
+    self.0 = value;
+  }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub enum AxisType {
+  LeftStickX,
+  LeftStickY,
+  RightStickX,
+  RightStickY,
+  LeftTrigger,
+  RightTrigger,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Button(bool);
+
+impl Button {
+  pub fn get(self) -> bool {
+    self.0
+  }
+
+  pub fn set_value(&mut self, value: bool) {
+    self.0 = value;
+  }
+
+  pub fn set(&mut self) {
+    self.set_value(true);
+  }
+
+  pub fn clear(&mut self) {
+    self.set_value(false);
+  }
+
+  pub const fn default() -> Button {
+    Button(false)
+  }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub enum ButtonType {
+  Start,
+  Select,
+  Home,
+  North,
+  East,
+  South,
+  West,
+  L1,
+  L2,
+  L3,
+  R1,
+  R2,
+  R3,
+  Trackpad,
+}
+
+impl ButtonType {
+  pub const ALL: [ButtonType; 14] = [
+    ButtonType::Start,
+    ButtonType::Select,
+    ButtonType::Home,
+    ButtonType::North,
+    ButtonType::East,
+    ButtonType::South,
+    ButtonType::West,
+    ButtonType::L1,
+    ButtonType::L2,
+    ButtonType::L3,
+    ButtonType::R1,
+    ButtonType::R2,
+    ButtonType::R3,
+    ButtonType::Trackpad,
+  ];
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Hat {
+  Neutral,
+  North,
+  NorthEast,
+  East,
+  SouthEast,
+  South,
+  SouthWest,
+  West,
+  NorthWest,
+}
+
+impl Hat {
+  pub const fn default() -> Hat {
+    Hat::Neutral
+  }
+}
+
+impl Hat {
+  /// Convert a pair of resolved directions into a hat position.
+  /// For horizontal, Some(false) is left, Some(true) is right.
+  /// For vertical, Some(false) is down, Some(true) is up.
+  pub fn from_directions(horizontal: Option<bool>, vertical: Option<bool>) -> Hat {
+    match (horizontal, vertical) {
+      (None, None) => Hat::Neutral,
+      (Some(true), None) => Hat::East,
+      (Some(true), Some(false)) => Hat::SouthEast,
+      (None, Some(false)) => Hat::South,
+      (Some(false), Some(false)) => Hat::SouthWest,
+      (Some(false), None) => Hat::West,
+      (Some(false), Some(true)) => Hat::NorthWest,
+      (None, Some(true)) => Hat::North,
+      (Some(true), Some(true)) => Hat::NorthEast,
+    }
+  }
+
+  /// Convert a hat position into a pair of directions, the inverse of from_directions.
+  pub fn to_directions(self) -> (Option<bool>, Option<bool>) {
+    match self {
+      Hat::Neutral => (None, None),
+      Hat::East => (Some(true), None),
+      Hat::SouthEast => (Some(true), Some(false)),
+      Hat::South => (None, Some(false)),
+      Hat::SouthWest => (Some(false), Some(false)),
+      Hat::West => (Some(false), None),
+      Hat::NorthWest => (Some(false), Some(true)),
+      Hat::North => (None, Some(true)),
+      Hat::NorthEast => (Some(true), Some(true)),
+    }
+  }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub enum HatType {
+  DPad,
+}
+
+/// What the lever drives.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StickMode {
+  DPad,
+  LeftStick,
+  RightStick,
+}
+
+impl StickMode {
+  /// Determine the stick mode from the LS/RS mode switches, with LS taking precedence.
+  pub fn from_switches(mode_ls: bool, mode_rs: bool) -> StickMode {
+    if mode_ls {
+      StickMode::LeftStick
+    } else if mode_rs {
+      StickMode::RightStick
+    } else {
+      StickMode::DPad
+    }
+  }
+}
+
+/// Raw state of every input pin, with true meaning pressed.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RawInputs {
+  pub stick_up: bool,
+  pub stick_down: bool,
+  pub stick_left: bool,
+  pub stick_right: bool,
+
+  pub button_north: bool,
+  pub button_east: bool,
+  pub button_south: bool,
+  pub button_west: bool,
+
+  pub button_l1: bool,
+  pub button_l2: bool,
+  pub button_l3: bool,
+
+  pub button_r1: bool,
+  pub button_r2: bool,
+  pub button_r3: bool,
+
+  pub button_start: bool,
+  pub button_select: bool,
+  pub button_home: bool,
+  pub button_trackpad: bool,
+
+  pub mode_lock: bool,
+  pub mode_ls: bool,
+  pub mode_rs: bool,
+  pub mode_ps3: bool,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct DeviceInputs {
+  pub counter: u8,
+
+  pub axis_left_stick_x: Axis,
+  pub axis_left_stick_y: Axis,
+
+  pub axis_right_stick_x: Axis,
+  pub axis_right_stick_y: Axis,
+
+  pub axis_left_trigger: Axis,
+  pub axis_right_trigger: Axis,
+
+  pub hat_dpad: Hat,
+
+  /// Start/Options
+  pub button_start: Button,
+
+  /// Back/Share
+  pub button_select: Button,
+
+  /// Xbox/PS
+  pub button_home: Button,
+
+  /// Y/△
+  pub button_north: Button,
+
+  /// B/○
+  pub button_east: Button,
+
+  /// A/✖
+  pub button_south: Button,
+
+  /// X/□
+  pub button_west: Button,
+
+  pub button_l1: Button,
+  pub button_l2: Button,
+  pub button_l3: Button,
+
+  pub button_r1: Button,
+  pub button_r2: Button,
+  pub button_r3: Button,
+
+  pub button_trackpad: Button,
+}
+
+impl DeviceInputs {
+  pub const fn default() -> DeviceInputs {
+    DeviceInputs {
+      counter: 0,
+
+      axis_left_stick_x: Axis(128),
+      axis_left_stick_y: Axis(128),
+
+      axis_right_stick_x: Axis(128),
+      axis_right_stick_y: Axis(128),
+
+      axis_left_trigger: Axis(128),
+      axis_right_trigger: Axis(128),
+
+      hat_dpad: Hat::default(),
+      button_start: Button::default(),
+      button_select: Button::default(),
+      button_home: Button::default(),
+
+      button_north: Button::default(),
+      button_east: Button::default(),
+      button_south: Button::default(),
+      button_west: Button::default(),
+
+      button_l1: Button::default(),
+      button_l2: Button::default(),
+      button_l3: Button::default(),
+
+      button_r1: Button::default(),
+      button_r2: Button::default(),
+      button_r3: Button::default(),
+
+      button_trackpad: Button::default(),
+    }
+  }
+
+  pub fn button(&self, button: ButtonType) -> Button {
+    match button {
+      ButtonType::Start => self.button_start,
+      ButtonType::Select => self.button_select,
+      ButtonType::Home => self.button_home,
+      ButtonType::North => self.button_north,
+      ButtonType::East => self.button_east,
+      ButtonType::South => self.button_south,
+      ButtonType::West => self.button_west,
+      ButtonType::L1 => self.button_l1,
+      ButtonType::L2 => self.button_l2,
+      ButtonType::L3 => self.button_l3,
+      ButtonType::R1 => self.button_r1,
+      ButtonType::R2 => self.button_r2,
+      ButtonType::R3 => self.button_r3,
+      ButtonType::Trackpad => self.button_trackpad,
+    }
+  }
+
+  /// Set the dpad and analog sticks from the resolved lever directions.
+  /// For horizontal, Some(false) is left, Some(true) is right.
+  /// For vertical, Some(false) is down, Some(true) is up.
+  pub fn set_lever(&mut self, mode: StickMode, horizontal: Option<bool>, vertical: Option<bool>) {
+    let x = match horizontal {
+      Some(true) => 255,
+      None => 128,
+      Some(false) => 0,
+    };
+    let y = match vertical {
+      Some(true) => 0,
+      None => 128,
+      Some(false) => 255,
+    };
+
+    self.hat_dpad = Hat::Neutral;
+    self.axis_left_stick_x.set_value(128);
+    self.axis_left_stick_y.set_value(128);
+    self.axis_right_stick_x.set_value(128);
+    self.axis_right_stick_y.set_value(128);
+
+    match mode {
+      StickMode::DPad => {
+        self.hat_dpad = Hat::from_directions(horizontal, vertical);
+      }
+
+      StickMode::LeftStick => {
+        self.axis_left_stick_x.set_value(x);
+        self.axis_left_stick_y.set_value(y);
+      }
+
+      StickMode::RightStick => {
+        self.axis_right_stick_x.set_value(x);
+        self.axis_right_stick_y.set_value(y);
+      }
+    }
+  }
+}
+
+/// A one line summary, for the debug console.
+impl fmt::Display for DeviceInputs {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "hat {:?}, left stick ({}, {}), right stick ({}, {}), triggers ({}, {}), pressed:",
+      self.hat_dpad,
+      self.axis_left_stick_x.get(),
+      self.axis_left_stick_y.get(),
+      self.axis_right_stick_x.get(),
+      self.axis_right_stick_y.get(),
+      self.axis_left_trigger.get(),
+      self.axis_right_trigger.get()
+    )?;
+
+    let mut any = false;
+    for &button in ButtonType::ALL.iter() {
+      if self.button(button).get() {
+        write!(f, " {:?}", button)?;
+        any = true;
+      }
+    }
+
+    if !any {
+      write!(f, " nothing")?;
+    }
+    Ok(())
+  }
+}
+
+/// Translates debounced raw inputs into the state reported to the host.
+pub struct InputProcessor {
+  pub horizontal: SocdResolver,
+  pub vertical: SocdResolver,
+}
+
+impl InputProcessor {
+  pub const fn new(horizontal: SocdType, vertical: SocdType) -> InputProcessor {
+    InputProcessor {
+      horizontal: SocdResolver::new(horizontal),
+      vertical: SocdResolver::new(vertical),
+    }
+  }
+
+  pub fn process(&mut self, inputs: &RawInputs, output: &mut DeviceInputs) {
+    output.button_north.set_value(inputs.button_north);
+    output.button_east.set_value(inputs.button_east);
+    output.button_south.set_value(inputs.button_south);
+    output.button_west.set_value(inputs.button_west);
+
+    output.button_l1.set_value(inputs.button_l1);
+    output.button_r1.set_value(inputs.button_r1);
+
+    output.button_l2.set_value(inputs.button_l2);
+    output
+      .axis_left_trigger
+      .set_value(if inputs.button_l2 { 255 } else { 0 });
+
+    output.button_r2.set_value(inputs.button_r2);
+    output
+      .axis_right_trigger
+      .set_value(if inputs.button_r2 { 255 } else { 0 });
+
+    output.button_l3.set_value(inputs.button_l3);
+    output.button_r3.set_value(inputs.button_r3);
+
+    if !inputs.mode_lock {
+      output.button_home.set_value(inputs.button_home);
+      output.button_start.set_value(inputs.button_start);
+      output.button_select.set_value(inputs.button_select);
+    }
+
+    output.button_trackpad.set_value(inputs.button_trackpad);
+
+    let (left, right) = (inputs.stick_left, inputs.stick_right);
+    let (up, down) = (inputs.stick_up, inputs.stick_down);
+
+    // None is neutral, Some(false) is left, Some(true) is right.
+    let horizontal = self.horizontal.resolve(left, right);
+
+    // None is neutral, Some(false) is down, Some(true) is up.
+    let vertical = self.vertical.resolve(down, up);
+
+    let mode = StickMode::from_switches(inputs.mode_ls, inputs.mode_rs);
+    output.set_lever(mode, horizontal, vertical);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn process(processor: &mut InputProcessor, inputs: &RawInputs) -> DeviceInputs {
+    let mut output = DeviceInputs::default();
+    processor.process(inputs, &mut output);
+    output
+  }
+
+  #[test]
+  fn buttons_and_triggers() {
+    let mut processor = InputProcessor::new(SocdType::Neutral, SocdType::Neutral);
+    let inputs = RawInputs {
+      button_south: true,
+      button_r2: true,
+      ..Default::default()
+    };
+
+    let output = process(&mut processor, &inputs);
+    assert!(output.button_south.get());
+    assert!(!output.button_north.get());
+    assert!(output.button_r2.get());
+    assert_eq!(output.axis_right_trigger.get(), 255);
+    assert!(!output.button_l2.get());
+    assert_eq!(output.axis_left_trigger.get(), 0);
+    assert!(format!("{}", output).ends_with("triggers (0, 255), pressed: South R2"));
+  }
+
+  #[test]
+  fn mode_lock_holds_menu_buttons() {
+    let mut processor = InputProcessor::new(SocdType::Neutral, SocdType::Neutral);
+    let mut output = DeviceInputs::default();
+
+    let locked = RawInputs {
+      button_start: true,
+      button_home: true,
+      mode_lock: true,
+      ..Default::default()
+    };
+    processor.process(&locked, &mut output);
+    assert!(!output.button_start.get());
+    assert!(!output.button_home.get());
+
+    let unlocked = RawInputs {
+      button_start: true,
+      ..Default::default()
+    };
+    processor.process(&unlocked, &mut output);
+    assert!(output.button_start.get());
+
+    // Engaging the lock freezes the current state rather than releasing the buttons.
+    processor.process(&locked, &mut output);
+    assert!(output.button_start.get());
+    assert!(!output.button_home.get());
+  }
+
+  #[test]
+  fn socd_dpad() {
+    let mut processor = InputProcessor::new(SocdType::Neutral, SocdType::Positive);
+    let inputs = RawInputs {
+      stick_left: true,
+      stick_right: true,
+      stick_up: true,
+      stick_down: true,
+      ..Default::default()
+    };
+
+    let output = process(&mut processor, &inputs);
+    assert_eq!(output.hat_dpad, Hat::North);
+    assert_eq!(output.axis_left_stick_x.get(), 128);
+    assert_eq!(output.axis_left_stick_y.get(), 128);
+  }
+
+  #[test]
+  fn stick_modes() {
+    let mut processor = InputProcessor::new(SocdType::Neutral, SocdType::Neutral);
+    let mut inputs = RawInputs {
+      stick_right: true,
+      stick_up: true,
+      mode_ls: true,
+      mode_rs: true,
+      ..Default::default()
+    };
+
+    let output = process(&mut processor, &inputs);
+    assert_eq!(output.hat_dpad, Hat::Neutral);
+    assert_eq!(output.axis_left_stick_x.get(), 255);
+    assert_eq!(output.axis_left_stick_y.get(), 0);
+    assert_eq!(output.axis_right_stick_x.get(), 128);
+
+    inputs.mode_ls = false;
+    let output = process(&mut processor, &inputs);
+    assert_eq!(output.axis_left_stick_x.get(), 128);
+    assert_eq!(output.axis_right_stick_x.get(), 255);
+    assert_eq!(output.axis_right_stick_y.get(), 0);
+
+    inputs.mode_rs = false;
+    let output = process(&mut processor, &inputs);
+    assert_eq!(output.hat_dpad, Hat::NorthEast);
+    assert_eq!(output.axis_right_stick_x.get(), 128);
+  }
+
+  #[test]
+  fn change_socd_mode() {
+    let mut processor = InputProcessor::new(SocdType::Neutral, SocdType::Positive);
+    let inputs = RawInputs {
+      stick_left: true,
+      stick_right: true,
+      stick_up: true,
+      stick_down: true,
+      ..Default::default()
+    };
+    assert_eq!(process(&mut processor, &inputs).hat_dpad, Hat::North);
+
+    processor.horizontal.set_mode(SocdType::Positive);
+    processor.vertical.set_mode(SocdType::Negative);
+    assert_eq!(process(&mut processor, &inputs).hat_dpad, Hat::SouthEast);
+
+    processor.horizontal.set_mode(SocdType::Negative);
+    processor.vertical.set_mode(SocdType::Neutral);
+    assert_eq!(process(&mut processor, &inputs).hat_dpad, Hat::West);
+  }
+}
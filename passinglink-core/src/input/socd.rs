@@ -0,0 +1,264 @@
+/// How to resolve simultaneous opposing cardinal directions (SOCD) on a single axis.
+///
+/// Axes are described in terms of a negative and a positive direction: left and right on the horizontal axis, down
+/// and up on the vertical axis.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SocdType {
+  /// Opposing inputs cancel out.
+  Neutral,
+
+  /// The most recently pressed direction wins.
+  LastInputWins,
+
+  /// The direction that was held first wins.
+  FirstInputWins,
+
+  /// The negative direction (left, or down) always wins.
+  Negative,
+
+  /// The positive direction (right, or up) always wins.
+  Positive,
+}
+
+impl core::str::FromStr for SocdType {
+  type Err = ();
+
+  /// Parse the short names used on the debug console.
+  fn from_str(s: &str) -> Result<SocdType, ()> {
+    match s {
+      "neutral" => Ok(SocdType::Neutral),
+      "last" => Ok(SocdType::LastInputWins),
+      "first" => Ok(SocdType::FirstInputWins),
+      "negative" => Ok(SocdType::Negative),
+      "positive" => Ok(SocdType::Positive),
+      _ => Err(()),
+    }
+  }
+}
+
+/// SOCD resolver for a single axis.
+#[derive(Clone, Copy, Debug)]
+pub struct SocdResolver {
+  mode: SocdType,
+
+  /// Raw state of the (negative, positive) directions at the last call to resolve.
+  previous: (bool, bool),
+
+  /// Direction that was most recently pressed, if it can be determined.
+  /// None if both directions were pressed at the same time.
+  last_pressed: Option<bool>,
+}
+
+impl SocdResolver {
+  pub const fn new(mode: SocdType) -> SocdResolver {
+    SocdResolver {
+      mode,
+      previous: (false, false),
+      last_pressed: None,
+    }
+  }
+
+  pub fn mode(&self) -> SocdType {
+    self.mode
+  }
+
+  pub fn set_mode(&mut self, mode: SocdType) {
+    self.mode = mode;
+  }
+
+  /// Resolve the raw state of an axis into a direction.
+  /// None is neutral, Some(false) is the negative direction, Some(true) is the positive direction.
+  pub fn resolve(&mut self, negative: bool, positive: bool) -> Option<bool> {
+    let (previous_negative, previous_positive) = self.previous;
+    self.previous = (negative, positive);
+
+    match (negative && !previous_negative, positive && !previous_positive) {
+      (true, true) => self.last_pressed = None,
+      (true, false) => self.last_pressed = Some(false),
+      (false, true) => self.last_pressed = Some(true),
+      (false, false) => {}
+    }
+
+    match (negative, positive) {
+      (false, false) => None,
+      (true, false) => Some(false),
+      (false, true) => Some(true),
+      (true, true) => match self.mode {
+        SocdType::Neutral => None,
+        SocdType::LastInputWins => self.last_pressed,
+        SocdType::FirstInputWins => self.last_pressed.map(|last| !last),
+        SocdType::Negative => Some(false),
+        SocdType::Positive => Some(true),
+      },
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const ALL_MODES: [SocdType; 5] = [
+    SocdType::Neutral,
+    SocdType::LastInputWins,
+    SocdType::FirstInputWins,
+    SocdType::Negative,
+    SocdType::Positive,
+  ];
+
+  fn check(mode: SocdType, sequence: &[((bool, bool), Option<bool>)]) {
+    let mut resolver = SocdResolver::new(mode);
+    for (step, &((negative, positive), expected)) in sequence.iter().enumerate() {
+      assert_eq!(
+        expected,
+        resolver.resolve(negative, positive),
+        "mode = {:?}, step = {}, input = {:?}",
+        mode,
+        step,
+        (negative, positive)
+      );
+    }
+  }
+
+  #[test]
+  fn no_opposing_inputs() {
+    for &mode in ALL_MODES.iter() {
+      check(
+        mode,
+        &[
+          ((false, false), None),
+          ((true, false), Some(false)),
+          ((false, false), None),
+          ((false, true), Some(true)),
+          ((true, false), Some(false)),
+          ((false, true), Some(true)),
+          ((false, false), None),
+        ],
+      );
+    }
+  }
+
+  #[test]
+  fn negative_then_positive() {
+    let expected = [
+      (SocdType::Neutral, None),
+      (SocdType::LastInputWins, Some(true)),
+      (SocdType::FirstInputWins, Some(false)),
+      (SocdType::Negative, Some(false)),
+      (SocdType::Positive, Some(true)),
+    ];
+
+    for &(mode, result) in expected.iter() {
+      check(
+        mode,
+        &[
+          ((true, false), Some(false)),
+          ((true, true), result),
+          ((true, true), result),
+          ((false, true), Some(true)),
+          ((false, false), None),
+        ],
+      );
+    }
+  }
+
+  #[test]
+  fn positive_then_negative() {
+    let expected = [
+      (SocdType::Neutral, None),
+      (SocdType::LastInputWins, Some(false)),
+      (SocdType::FirstInputWins, Some(true)),
+      (SocdType::Negative, Some(false)),
+      (SocdType::Positive, Some(true)),
+    ];
+
+    for &(mode, result) in expected.iter() {
+      check(
+        mode,
+        &[
+          ((false, true), Some(true)),
+          ((true, true), result),
+          ((true, true), result),
+          ((true, false), Some(false)),
+          ((false, false), None),
+        ],
+      );
+    }
+  }
+
+  #[test]
+  fn simultaneous_press() {
+    let expected = [
+      (SocdType::Neutral, None),
+      (SocdType::LastInputWins, None),
+      (SocdType::FirstInputWins, None),
+      (SocdType::Negative, Some(false)),
+      (SocdType::Positive, Some(true)),
+    ];
+
+    for &(mode, result) in expected.iter() {
+      check(
+        mode,
+        &[
+          ((false, false), None),
+          ((true, true), result),
+          ((true, false), Some(false)),
+          ((false, false), None),
+          ((true, true), result),
+          ((false, true), Some(true)),
+        ],
+      );
+    }
+  }
+
+  #[test]
+  fn repress_while_held() {
+    // Hold negative, press positive, release and repress negative while positive is held.
+    let expected = [
+      (SocdType::Neutral, None, None),
+      (SocdType::LastInputWins, Some(true), Some(false)),
+      (SocdType::FirstInputWins, Some(false), Some(true)),
+      (SocdType::Negative, Some(false), Some(false)),
+      (SocdType::Positive, Some(true), Some(true)),
+    ];
+
+    for &(mode, before, after) in expected.iter() {
+      check(
+        mode,
+        &[
+          ((true, false), Some(false)),
+          ((true, true), before),
+          ((false, true), Some(true)),
+          ((true, true), after),
+          ((true, false), Some(false)),
+          ((true, true), before),
+        ],
+      );
+    }
+  }
+
+  #[test]
+  fn change_mode() {
+    let mut resolver = SocdResolver::new(SocdType::Neutral);
+    assert_eq!(Some(false), resolver.resolve(true, false));
+    assert_eq!(None, resolver.resolve(true, true));
+
+    // History is retained across mode changes.
+    resolver.set_mode(SocdType::LastInputWins);
+    assert_eq!(SocdType::LastInputWins, resolver.mode());
+    assert_eq!(Some(true), resolver.resolve(true, true));
+
+    resolver.set_mode(SocdType::FirstInputWins);
+    assert_eq!(Some(false), resolver.resolve(true, true));
+  }
+
+  #[test]
+  fn parse() {
+    assert_eq!(Ok(SocdType::Neutral), "neutral".parse());
+    assert_eq!(Ok(SocdType::LastInputWins), "last".parse());
+    assert_eq!(Ok(SocdType::FirstInputWins), "first".parse());
+    assert_eq!(Ok(SocdType::Negative), "negative".parse());
+    assert_eq!(Ok(SocdType::Positive), "positive".parse());
+    assert_eq!(Err(()), "Neutral".parse::<SocdType>());
+  }
+}
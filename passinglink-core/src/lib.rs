@@ -0,0 +1,26 @@
+//! Hardware-independent logic for passinglink.
+//! Everything in here builds for the host, so that it can be tested with `cargo test`.
+
+#![no_std]
+
+#[cfg(any(test, feature = "mock"))]
+#[macro_use]
+extern crate std;
+
+#[macro_use]
+extern crate log;
+
+#[macro_use]
+extern crate proper;
+
+pub mod allocator;
+pub mod auth;
+pub mod console;
+pub mod hid;
+pub mod input;
+
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+
+pub mod provision;
+pub mod xinput;
@@ -0,0 +1,456 @@
+//! Software stand-in for the USB peripheral and the host on the other end of the cable, so that UsbClass
+//! implementations can be exercised without hardware.
+
+use std::boxed::Box;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::vec::Vec;
+
+use usb_device::bus::{PollResult, UsbBus, UsbBusAllocator};
+use usb_device::class::UsbClass;
+use usb_device::device::{UsbDevice, UsbDeviceState};
+use usb_device::endpoint::{EndpointAddress, EndpointType};
+use usb_device::{UsbDirection, UsbError};
+
+const ENDPOINT_COUNT: usize = 16;
+
+/// Number of times the device gets polled while waiting for it to respond before giving up.
+const POLL_LIMIT: usize = 16;
+
+const DESCRIPTOR_TYPE_DEVICE: u8 = 0x01;
+const DESCRIPTOR_TYPE_CONFIGURATION: u8 = 0x02;
+
+const REQUEST_GET_DESCRIPTOR: u8 = 0x06;
+const REQUEST_SET_ADDRESS: u8 = 0x05;
+const REQUEST_SET_CONFIGURATION: u8 = 0x09;
+
+/// Address assigned to the device during enumeration.
+pub const DEVICE_ADDRESS: u8 = 0x10;
+
+#[derive(Default)]
+struct Endpoint {
+  allocated: bool,
+  max_packet_size: u16,
+  stalled: bool,
+
+  /// Packets in flight: written by the host and waiting for the device to read for OUT endpoints, or written by the
+  /// device and waiting for the host to read for IN endpoints.
+  packets: VecDeque<Vec<u8>>,
+}
+
+#[derive(Default)]
+struct BusState {
+  enabled: bool,
+  address: u8,
+  reset_pending: bool,
+
+  ep_in: [Endpoint; ENDPOINT_COUNT],
+  ep_out: [Endpoint; ENDPOINT_COUNT],
+
+  setup: Option<[u8; 8]>,
+  in_complete: u16,
+}
+
+impl BusState {
+  fn endpoint(&mut self, ep_addr: EndpointAddress) -> Result<&mut Endpoint, UsbError> {
+    let endpoints = match ep_addr.direction() {
+      UsbDirection::In => &mut self.ep_in,
+      UsbDirection::Out => &mut self.ep_out,
+    };
+
+    match endpoints.get_mut(ep_addr.index()) {
+      Some(ep) if ep.allocated => Ok(ep),
+      _ => Err(UsbError::InvalidEndpoint),
+    }
+  }
+}
+
+/// UsbBus implementation backed by in-memory packet queues.
+/// Every endpoint behaves like a single-buffered hardware endpoint: writes fail with WouldBlock until the host has
+/// picked up the previous packet.
+pub struct MockUsbBus {
+  state: Arc<Mutex<BusState>>,
+}
+
+impl MockUsbBus {
+  fn state(&self) -> MutexGuard<'_, BusState> {
+    self.state.lock().unwrap()
+  }
+}
+
+impl UsbBus for MockUsbBus {
+  fn alloc_ep(
+    &mut self,
+    ep_dir: UsbDirection,
+    ep_addr: Option<EndpointAddress>,
+    _ep_type: EndpointType,
+    max_packet_size: u16,
+    _interval: u8,
+  ) -> usb_device::Result<EndpointAddress> {
+    let mut state = self.state();
+    let endpoints = match ep_dir {
+      UsbDirection::In => &mut state.ep_in,
+      UsbDirection::Out => &mut state.ep_out,
+    };
+
+    let index = match ep_addr {
+      Some(addr) => addr.index(),
+      None => (1..ENDPOINT_COUNT)
+        .find(|&i| !endpoints[i].allocated)
+        .ok_or(UsbError::EndpointOverflow)?,
+    };
+
+    match endpoints.get_mut(index) {
+      None => Err(UsbError::EndpointOverflow),
+      Some(ep) if ep.allocated => Err(UsbError::InvalidEndpoint),
+      Some(ep) => {
+        ep.allocated = true;
+        ep.max_packet_size = max_packet_size;
+        Ok(EndpointAddress::from_parts(index, ep_dir))
+      }
+    }
+  }
+
+  fn enable(&mut self) {
+    self.state().enabled = true;
+  }
+
+  fn reset(&self) {
+    let mut guard = self.state();
+    let state = &mut *guard;
+    state.address = 0;
+    state.setup = None;
+    state.in_complete = 0;
+    for ep in state.ep_in.iter_mut().chain(state.ep_out.iter_mut()) {
+      ep.stalled = false;
+      ep.packets.clear();
+    }
+  }
+
+  fn set_device_address(&self, addr: u8) {
+    self.state().address = addr;
+  }
+
+  fn write(&self, ep_addr: EndpointAddress, buf: &[u8]) -> usb_device::Result<usize> {
+    let mut state = self.state();
+    let ep = state.endpoint(ep_addr)?;
+    if buf.len() > ep.max_packet_size as usize {
+      return Err(UsbError::BufferOverflow);
+    }
+
+    if !ep.packets.is_empty() {
+      return Err(UsbError::WouldBlock);
+    }
+
+    ep.packets.push_back(buf.to_vec());
+    Ok(buf.len())
+  }
+
+  fn read(&self, ep_addr: EndpointAddress, buf: &mut [u8]) -> usb_device::Result<usize> {
+    let mut state = self.state();
+    if ep_addr.index() == 0 {
+      if let Some(setup) = state.setup {
+        if buf.len() < setup.len() {
+          return Err(UsbError::BufferOverflow);
+        }
+
+        state.setup = None;
+        buf[..setup.len()].copy_from_slice(&setup);
+        return Ok(setup.len());
+      }
+    }
+
+    let ep = state.endpoint(ep_addr)?;
+    let len = match ep.packets.front() {
+      None => return Err(UsbError::WouldBlock),
+      Some(packet) if packet.len() > buf.len() => return Err(UsbError::BufferOverflow),
+      Some(packet) => packet.len(),
+    };
+
+    let packet = ep.packets.pop_front().unwrap();
+    buf[..len].copy_from_slice(&packet);
+    Ok(len)
+  }
+
+  fn set_stalled(&self, ep_addr: EndpointAddress, stalled: bool) {
+    if let Ok(ep) = self.state().endpoint(ep_addr) {
+      ep.stalled = stalled;
+    }
+  }
+
+  fn is_stalled(&self, ep_addr: EndpointAddress) -> bool {
+    self.state().endpoint(ep_addr).map(|ep| ep.stalled).unwrap_or(false)
+  }
+
+  fn suspend(&self) {}
+  fn resume(&self) {}
+
+  fn poll(&self) -> PollResult {
+    let mut state = self.state();
+    if state.reset_pending {
+      state.reset_pending = false;
+      return PollResult::Reset;
+    }
+
+    let ep_setup = state.setup.is_some() as u16;
+    let ep_in_complete = state.in_complete;
+    state.in_complete = 0;
+
+    let mut ep_out = 0;
+    for (i, ep) in state.ep_out.iter().enumerate() {
+      if !ep.packets.is_empty() {
+        ep_out |= 1 << i;
+      }
+    }
+
+    if ep_setup | ep_in_complete | ep_out == 0 {
+      PollResult::None
+    } else {
+      PollResult::Data {
+        ep_out,
+        ep_in_complete,
+        ep_setup,
+      }
+    }
+  }
+}
+
+/// Reasons a transfer issued by MockUsbHost can fail.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TransferError {
+  /// The device stalled the control pipe, i.e. it rejected the request.
+  Stall,
+
+  /// The device didn't respond.
+  Timeout,
+
+  /// The device responded, but with something other than expected.
+  Protocol,
+}
+
+/// Host end of a MockUsbBus, which owns the device and the class under test.
+pub struct MockUsbHost<C: UsbClass<MockUsbBus>> {
+  state: Arc<Mutex<BusState>>,
+  device: UsbDevice<'static, MockUsbBus>,
+  class: C,
+}
+
+impl<C: UsbClass<MockUsbBus>> MockUsbHost<C> {
+  /// Create a bus and hand it to `build`, which should allocate the class and then build the device.
+  /// The allocator is leaked, since UsbDevice and the endpoints borrow it for their entire lifetime.
+  pub fn new<F>(build: F) -> MockUsbHost<C>
+  where
+    F: FnOnce(&'static UsbBusAllocator<MockUsbBus>) -> (UsbDevice<'static, MockUsbBus>, C),
+  {
+    let state = Arc::new(Mutex::new(BusState::default()));
+    let bus = MockUsbBus { state: state.clone() };
+    let alloc: &'static UsbBusAllocator<MockUsbBus> = Box::leak(Box::new(UsbBusAllocator::new(bus)));
+    let (device, class) = build(alloc);
+    MockUsbHost { state, device, class }
+  }
+
+  fn state(&self) -> MutexGuard<'_, BusState> {
+    self.state.lock().unwrap()
+  }
+
+  pub fn class(&self) -> &C {
+    &self.class
+  }
+
+  pub fn class_mut(&mut self) -> &mut C {
+    &mut self.class
+  }
+
+  pub fn device_state(&self) -> UsbDeviceState {
+    self.device.state()
+  }
+
+  pub fn address(&self) -> u8 {
+    self.state().address
+  }
+
+  /// Poll the device once, as the USB interrupt handler would.
+  pub fn poll(&mut self) -> bool {
+    self.device.poll(&mut [&mut self.class])
+  }
+
+  /// Signal a bus reset, and let the device process it.
+  pub fn reset(&mut self) {
+    self.state().reset_pending = true;
+    self.poll();
+  }
+
+  /// Reset the device, then walk it through the standard enumeration sequence up to SET_CONFIGURATION.
+  /// Returns the configuration descriptor.
+  pub fn enumerate(&mut self) -> Result<Vec<u8>, TransferError> {
+    self.reset();
+
+    // Like real hosts, read the start of the device descriptor to find bMaxPacketSize0 before assigning an address.
+    let device_descriptor = self.get_descriptor(DESCRIPTOR_TYPE_DEVICE, 0, 64)?;
+    if device_descriptor.len() != 18 || device_descriptor[1] != DESCRIPTOR_TYPE_DEVICE {
+      return Err(TransferError::Protocol);
+    }
+
+    self.control_out(0x00, REQUEST_SET_ADDRESS, DEVICE_ADDRESS as u16, 0, &[])?;
+    if self.address() != DEVICE_ADDRESS {
+      return Err(TransferError::Protocol);
+    }
+
+    let header = self.get_descriptor(DESCRIPTOR_TYPE_CONFIGURATION, 0, 9)?;
+    if header.len() != 9 || header[1] != DESCRIPTOR_TYPE_CONFIGURATION {
+      return Err(TransferError::Protocol);
+    }
+
+    let total_length = u16::from_le_bytes([header[2], header[3]]);
+    let configuration = self.get_descriptor(DESCRIPTOR_TYPE_CONFIGURATION, 0, total_length)?;
+    if configuration.len() != total_length as usize {
+      return Err(TransferError::Protocol);
+    }
+
+    self.control_out(0x00, REQUEST_SET_CONFIGURATION, configuration[5] as u16, 0, &[])?;
+    if self.device_state() != UsbDeviceState::Configured {
+      return Err(TransferError::Protocol);
+    }
+
+    Ok(configuration)
+  }
+
+  /// Standard GET_DESCRIPTOR request addressed to the device.
+  pub fn get_descriptor(&mut self, descriptor_type: u8, index: u8, length: u16) -> Result<Vec<u8>, TransferError> {
+    let value = u16::from_be_bytes([descriptor_type, index]);
+    self.control_in(0x80, REQUEST_GET_DESCRIPTOR, value, 0, length)
+  }
+
+  /// Issue a control transfer with a device-to-host data stage, and return the data received.
+  pub fn control_in(
+    &mut self,
+    request_type: u8,
+    request: u8,
+    value: u16,
+    index: u16,
+    length: u16,
+  ) -> Result<Vec<u8>, TransferError> {
+    assert!(request_type & 0x80 != 0, "control_in called with an OUT request");
+    self.setup(request_type, request, value, index, length);
+
+    let max_packet_size = self.state().ep_in[0].max_packet_size as usize;
+    let mut data = Vec::new();
+    loop {
+      let packet = self.wait_ep0_in()?;
+      let short = packet.len() < max_packet_size;
+      data.extend_from_slice(&packet);
+      if data.len() > length as usize {
+        return Err(TransferError::Protocol);
+      }
+
+      if short || data.len() == length as usize {
+        break;
+      }
+    }
+
+    // Let the device see that the last packet was delivered before starting the status stage.
+    self.poll();
+
+    // Status stage.
+    self.state().ep_out[0].packets.push_back(Vec::new());
+    self.poll_until(|state| state.ep_out[0].packets.is_empty())?;
+    Ok(data)
+  }
+
+  /// Issue a control transfer with a host-to-device data stage (which might be empty).
+  pub fn control_out(
+    &mut self,
+    request_type: u8,
+    request: u8,
+    value: u16,
+    index: u16,
+    data: &[u8],
+  ) -> Result<(), TransferError> {
+    assert!(request_type & 0x80 == 0, "control_out called with an IN request");
+    self.setup(request_type, request, value, index, data.len() as u16);
+
+    let max_packet_size = self.state().ep_out[0].max_packet_size as usize;
+    for chunk in data.chunks(max_packet_size) {
+      self.state().ep_out[0].packets.push_back(chunk.to_vec());
+      self.poll_until(|state| state.ep_out[0].packets.is_empty())?;
+    }
+
+    // Status stage.
+    let status = self.wait_ep0_in()?;
+    if !status.is_empty() {
+      return Err(TransferError::Protocol);
+    }
+
+    // Deliver the acknowledgement of the status stage, which is when requests like SET_ADDRESS take effect.
+    self.poll();
+    Ok(())
+  }
+
+  /// Send a packet to an OUT endpoint, and let the device process it.
+  pub fn write_interrupt(&mut self, ep: u8, data: &[u8]) -> Result<(), TransferError> {
+    let addr = EndpointAddress::from_parts(ep as usize, UsbDirection::Out);
+    self
+      .state()
+      .endpoint(addr)
+      .map_err(|_| TransferError::Protocol)?
+      .packets
+      .push_back(data.to_vec());
+    self.poll_until(|state| state.ep_out[ep as usize].packets.is_empty())
+  }
+
+  /// Take the packet waiting on an IN endpoint, if the device has written one.
+  pub fn read_interrupt(&mut self, ep: u8) -> Option<Vec<u8>> {
+    let mut state = self.state();
+    let packet = state.ep_in.get_mut(ep as usize)?.packets.pop_front()?;
+    state.in_complete |= 1 << ep;
+    Some(packet)
+  }
+
+  fn setup(&mut self, request_type: u8, request: u8, value: u16, index: u16, length: u16) {
+    let value = value.to_le_bytes();
+    let index = index.to_le_bytes();
+    let length = length.to_le_bytes();
+
+    // Like the hardware, receiving a SETUP packet clears any stall on the control endpoints.
+    let mut state = self.state();
+    state.ep_in[0].stalled = false;
+    state.ep_out[0].stalled = false;
+    state.ep_in[0].packets.clear();
+    state.ep_out[0].packets.clear();
+    state.setup = Some([
+      request_type,
+      request,
+      value[0],
+      value[1],
+      index[0],
+      index[1],
+      length[0],
+      length[1],
+    ]);
+  }
+
+  /// Wait for the device to write to the control IN endpoint, and acknowledge the packet.
+  fn wait_ep0_in(&mut self) -> Result<Vec<u8>, TransferError> {
+    self.poll_until(|state| !state.ep_in[0].packets.is_empty())?;
+    Ok(self.read_interrupt(0).unwrap())
+  }
+
+  fn poll_until<F>(&mut self, condition: F) -> Result<(), TransferError>
+  where
+    F: Fn(&BusState) -> bool,
+  {
+    for _ in 0..POLL_LIMIT {
+      self.poll();
+
+      let state = self.state();
+      if state.ep_in[0].stalled || state.ep_out[0].stalled {
+        return Err(TransferError::Stall);
+      }
+
+      if condition(&state) {
+        return Ok(());
+      }
+    }
+    Err(TransferError::Timeout)
+  }
+}
@@ -0,0 +1,362 @@
+//! Provisioning of the DS4 key over USB, via vendor feature reports.
+//!
+//! The key is streamed in 56 byte parts with report 0xa0, which are staged in RAM until report 0xa1 arrives with the
+//! CRC of the entire key. The key is then tested and written to flash from the idle loop, since both are far too slow
+//! for an interrupt handler. Report 0xa2 reports progress.
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::AtomicU8;
+use core::sync::atomic::Ordering::SeqCst;
+
+/// Size of ds4auth::DS4KeyEncoded.
+pub const KEY_SIZE: usize = 1424;
+
+pub const REPORT_KEY_DATA: u8 = 0xa0;
+pub const REPORT_KEY_COMMIT: u8 = 0xa1;
+pub const REPORT_KEY_STATUS: u8 = 0xa2;
+
+pub const PART_SIZE: usize = 56;
+pub const PART_COUNT: u8 = ((KEY_SIZE + PART_SIZE - 1) / PART_SIZE) as u8;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub enum ProvisionState {
+  Idle = 0,
+  Receiving = 1,
+
+  /// The entire key has been received, and is waiting for the idle loop to pick it up.
+  Pending = 2,
+
+  /// The key is being tested and written.
+  Busy = 3,
+
+  Done = 4,
+  Failed = 5,
+}
+
+impl ProvisionState {
+  pub fn from_u8(value: u8) -> Option<ProvisionState> {
+    match value {
+      0 => Some(ProvisionState::Idle),
+      1 => Some(ProvisionState::Receiving),
+      2 => Some(ProvisionState::Pending),
+      3 => Some(ProvisionState::Busy),
+      4 => Some(ProvisionState::Done),
+      5 => Some(ProvisionState::Failed),
+      _ => None,
+    }
+  }
+}
+
+/// Somewhere to put a provisioned key.
+pub trait KeyStore {
+  /// Check that the key works before anything gets overwritten with it.
+  fn verify(&mut self, key: &[u8; KEY_SIZE]) -> Result<(), ()>;
+
+  /// Write the key to persistent storage.
+  fn commit(&mut self, key: &[u8; KEY_SIZE]) -> Result<(), ()>;
+}
+
+/// Check the key for consistency, sign a nonce with it, and check the result.
+#[cfg(feature = "ds4auth")]
+pub fn test_sign(key: &[u8; KEY_SIZE]) -> Result<(), ()> {
+  let encoded = ds4auth::DS4KeyEncoded::from_bytes(key).ok_or(())?;
+  encoded.self_test().map(|_| ()).map_err(|err| {
+    error!("provisioned key failed self-test: {}", err);
+  })
+}
+
+fn crc(data: &[u8]) -> u32 {
+  crc::crc32::checksum_ieee(data)
+}
+
+/// Build a 64 byte feature report in the layout shared by the provisioning reports and the DS4's nonce reports: the
+/// report ID, two header bytes, a reserved byte, up to 56 bytes of data, and the CRC32 of everything before it.
+#[cfg(any(test, feature = "mock"))]
+pub fn packet(report_id: u8, header: [u8; 2], data: &[u8]) -> [u8; 64] {
+  let mut packet = [0u8; 64];
+  packet[0] = report_id;
+  packet[1..3].copy_from_slice(&header);
+  packet[4..4 + data.len()].copy_from_slice(data);
+  let crc = crc(&packet[..60]).to_le_bytes();
+  packet[60..].copy_from_slice(&crc);
+  packet
+}
+
+/// Receives a key from the USB interrupt handlers, and hands it off to the idle loop.
+///
+/// The interrupt handlers only touch the buffer while receiving, and the idle loop only while pending or busy, and
+/// neither side will leave the other's states, so the state doesn't need to be updated atomically with the buffer.
+pub struct Provisioner {
+  state: AtomicU8,
+  next_part: AtomicU8,
+  data: UnsafeCell<[u8; KEY_SIZE]>,
+}
+
+unsafe impl Sync for Provisioner {}
+
+impl Provisioner {
+  pub const fn new() -> Provisioner {
+    Provisioner {
+      state: AtomicU8::new(ProvisionState::Idle as u8),
+      next_part: AtomicU8::new(0),
+      data: UnsafeCell::new([0; KEY_SIZE]),
+    }
+  }
+
+  pub fn state(&self) -> ProvisionState {
+    ProvisionState::from_u8(self.state.load(SeqCst)).unwrap()
+  }
+
+  fn set_state(&self, state: ProvisionState) {
+    self.state.store(state as u8, SeqCst);
+  }
+
+  #[allow(clippy::mut_from_ref)]
+  unsafe fn data(&self) -> &mut [u8; KEY_SIZE] {
+    &mut *self.data.get()
+  }
+
+  /// Whether a key is waiting for perform_work.
+  pub fn pending(&self) -> bool {
+    self.state() == ProvisionState::Pending
+  }
+
+  fn check_packet(&self, bytes: &[u8]) -> Result<(), ()> {
+    if bytes.len() != 64 {
+      error!("received provisioning packet of incorrect length: {}", bytes.len());
+      return Err(());
+    }
+
+    let received_crc = &bytes[60..];
+    let calculated_crc = crc(&bytes[..60]).to_le_bytes();
+    if received_crc != calculated_crc {
+      error!("CRC mismatch for provisioning packet {:#x}", bytes[0]);
+      return Err(());
+    }
+
+    match self.state() {
+      ProvisionState::Pending | ProvisionState::Busy => {
+        error!("received provisioning packet while busy");
+        Err(())
+      }
+      _ => Ok(()),
+    }
+  }
+
+  /// Handle report 0xa0: [0xa0, part, 0, 0, data[56], crc32(bytes[0..60])].
+  /// Parts must arrive in order, and part 0 always starts over.
+  pub fn set_key_data(&self, bytes: &[u8]) -> Result<(), ()> {
+    self.check_packet(bytes)?;
+
+    let part = bytes[1];
+    let expected_part = match self.state() {
+      ProvisionState::Receiving => self.next_part.load(SeqCst),
+      _ => 0,
+    };
+
+    if part != 0 && part != expected_part {
+      error!("received wrong key part (expected {}, got {})", expected_part, part);
+      self.set_state(ProvisionState::Idle);
+      return Err(());
+    }
+
+    if part >= PART_COUNT {
+      error!("received key part {} beyond the end of the key", part);
+      self.set_state(ProvisionState::Idle);
+      return Err(());
+    }
+
+    let start = part as usize * PART_SIZE;
+    let len = PART_SIZE.min(KEY_SIZE - start);
+    unsafe { self.data()[start..start + len].copy_from_slice(&bytes[4..4 + len]) }
+
+    debug!("received key part {}/{}", part + 1, PART_COUNT);
+    self.next_part.store(part + 1, SeqCst);
+    self.set_state(ProvisionState::Receiving);
+    Ok(())
+  }
+
+  /// Handle report 0xa1: [0xa1, 0, 0, 0, crc32(key), 0..., crc32(bytes[0..60])].
+  pub fn commit(&self, bytes: &[u8]) -> Result<(), ()> {
+    self.check_packet(bytes)?;
+
+    if self.state() != ProvisionState::Receiving || self.next_part.load(SeqCst) != PART_COUNT {
+      error!("received key commit before the entire key");
+      self.set_state(ProvisionState::Idle);
+      return Err(());
+    }
+
+    let expected_crc = &bytes[4..8];
+    let calculated_crc = crc(unsafe { self.data() }).to_le_bytes();
+    if expected_crc != calculated_crc {
+      error!("CRC mismatch for provisioned key");
+      self.set_state(ProvisionState::Idle);
+      return Err(());
+    }
+
+    info!("received key, waiting to commit");
+    self.set_state(ProvisionState::Pending);
+    Ok(())
+  }
+
+  /// Fill in report 0xa2: [0xa2, state, next part, ...].
+  pub fn get_status(&self, buf: &mut [u8]) {
+    for byte in buf.iter_mut() {
+      *byte = 0;
+    }
+    buf[0] = REPORT_KEY_STATUS;
+    buf[1] = self.state() as u8;
+    buf[2] = self.next_part.load(SeqCst);
+  }
+
+  /// Verify and commit the received key, if there is one.
+  /// Returns whether any work was done.
+  pub fn perform_work<S: KeyStore>(&self, store: &mut S) -> bool {
+    if !self.pending() {
+      return false;
+    }
+
+    self.set_state(ProvisionState::Busy);
+    info!("verifying provisioned key");
+
+    let key = unsafe { &*self.data() };
+    let result = store.verify(key).and_then(|_| {
+      info!("writing provisioned key");
+      store.commit(key)
+    });
+
+    if result.is_ok() {
+      info!("key provisioned");
+      self.set_state(ProvisionState::Done);
+    } else {
+      error!("failed to provision key");
+      self.set_state(ProvisionState::Failed);
+    }
+    true
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::vec::Vec;
+
+  struct MockKeyStore {
+    valid: bool,
+    committed: Option<Vec<u8>>,
+  }
+
+  impl KeyStore for MockKeyStore {
+    fn verify(&mut self, _key: &[u8; KEY_SIZE]) -> Result<(), ()> {
+      if self.valid {
+        Ok(())
+      } else {
+        Err(())
+      }
+    }
+
+    fn commit(&mut self, key: &[u8; KEY_SIZE]) -> Result<(), ()> {
+      self.committed = Some(key.to_vec());
+      Ok(())
+    }
+  }
+
+  fn key() -> Vec<u8> {
+    (0..KEY_SIZE).map(|i| (i * 7) as u8).collect()
+  }
+
+  fn data_packet(key: &[u8], part: u8) -> [u8; 64] {
+    let start = part as usize * PART_SIZE;
+    let end = (start + PART_SIZE).min(key.len());
+    packet(REPORT_KEY_DATA, [part, 0], &key[start..end])
+  }
+
+  fn commit_packet(key: &[u8]) -> [u8; 64] {
+    packet(REPORT_KEY_COMMIT, [0, 0], &crc(key).to_le_bytes())
+  }
+
+  fn send_key(provisioner: &Provisioner, key: &[u8]) {
+    for part in 0..PART_COUNT {
+      provisioner.set_key_data(&data_packet(key, part)).unwrap();
+    }
+  }
+
+  #[test]
+  fn provision() {
+    let provisioner = Provisioner::new();
+    let mut store = MockKeyStore {
+      valid: true,
+      committed: None,
+    };
+    let key = key();
+
+    assert!(!provisioner.perform_work(&mut store));
+    send_key(&provisioner, &key);
+    assert_eq!(ProvisionState::Receiving, provisioner.state());
+    assert!(!provisioner.pending());
+
+    provisioner.commit(&commit_packet(&key)).unwrap();
+    assert!(provisioner.pending());
+
+    // Nothing is accepted until the key has been dealt with.
+    assert_eq!(Err(()), provisioner.set_key_data(&data_packet(&key, 0)));
+
+    let mut status = [0u8; 16];
+    provisioner.get_status(&mut status);
+    assert_eq!(
+      &[REPORT_KEY_STATUS, ProvisionState::Pending as u8, PART_COUNT],
+      &status[..3]
+    );
+
+    assert!(provisioner.perform_work(&mut store));
+    assert_eq!(ProvisionState::Done, provisioner.state());
+    assert_eq!(Some(key), store.committed);
+  }
+
+  #[test]
+  fn verification_failure() {
+    let provisioner = Provisioner::new();
+    let mut store = MockKeyStore {
+      valid: false,
+      committed: None,
+    };
+    let key = key();
+
+    send_key(&provisioner, &key);
+    provisioner.commit(&commit_packet(&key)).unwrap();
+    assert!(provisioner.perform_work(&mut store));
+    assert_eq!(ProvisionState::Failed, provisioner.state());
+    assert_eq!(None, store.committed);
+
+    // Another attempt can be made afterwards.
+    provisioner.set_key_data(&data_packet(&key, 0)).unwrap();
+    assert_eq!(ProvisionState::Receiving, provisioner.state());
+  }
+
+  #[test]
+  fn malformed() {
+    let provisioner = Provisioner::new();
+    let key = key();
+
+    // Parts out of order.
+    assert_eq!(Err(()), provisioner.set_key_data(&data_packet(&key, 1)));
+    provisioner.set_key_data(&data_packet(&key, 0)).unwrap();
+    assert_eq!(Err(()), provisioner.set_key_data(&data_packet(&key, 2)));
+    assert_eq!(ProvisionState::Idle, provisioner.state());
+
+    // Bad packet CRC.
+    let mut packet = data_packet(&key, 0);
+    packet[10] ^= 0xff;
+    assert_eq!(Err(()), provisioner.set_key_data(&packet));
+
+    // Commit before the entire key has arrived.
+    provisioner.set_key_data(&data_packet(&key, 0)).unwrap();
+    assert_eq!(Err(()), provisioner.commit(&commit_packet(&key)));
+
+    // Commit with the wrong key CRC.
+    send_key(&provisioner, &key);
+    assert_eq!(Err(()), provisioner.commit(&commit_packet(&key[1..])));
+    assert_eq!(ProvisionState::Idle, provisioner.state());
+  }
+}
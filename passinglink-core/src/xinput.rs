@@ -0,0 +1,308 @@
+use usb_device::class_prelude::*;
+use usb_device::control::RequestType;
+use usb_device::UsbDirection;
+
+use crate::hid::DeviceIdentity;
+use crate::input::DeviceInputs;
+
+pub const IDENTITY: DeviceIdentity = DeviceIdentity {
+  vid: 0x045E,
+  pid: 0x028E,
+  manufacturer: "©Microsoft Corporation",
+  product: "Controller",
+};
+
+/// Vendor request code advertised in the Microsoft OS string descriptor.
+const MS_VENDOR_CODE: u8 = 0x90;
+
+/// String index that Windows queries for the Microsoft OS string descriptor.
+const MS_OS_STRING_INDEX: u8 = 0xEE;
+
+/// wIndex of the vendor request for the extended compat ID descriptor.
+const MS_EXTENDED_COMPAT_ID: u16 = 0x0004;
+
+/// "MSFT100" followed by the vendor code and a padding byte.
+/// The vendor code is smuggled in as U+0090, which encodes in UTF-16LE as [MS_VENDOR_CODE, 0x00].
+const MS_OS_STRING: &str = "MSFT100\u{90}";
+
+#[rustfmt::skip]
+const MS_EXTENDED_COMPAT_ID_DESCRIPTOR: [u8; 40] = [
+  0x28, 0x00, 0x00, 0x00,                         // dwLength
+  0x00, 0x01,                                     // bcdVersion (1.00)
+  0x04, 0x00,                                     // wIndex (extended compat ID)
+  0x01,                                           // bCount
+  0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,       // reserved
+
+  0x00,                                           // bFirstInterfaceNumber
+  0x01,                                           // reserved
+  b'X', b'U', b'S', b'B', b'1', b'0', 0x00, 0x00, // compatibleID
+  0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // subCompatibleID
+  0x00, 0x00, 0x00, 0x00, 0x00, 0x00,             // reserved
+];
+
+#[allow(unused)]
+#[repr(packed)]
+struct XInputReport {
+  report_type: u8, // Always 0x00.
+  report_size: u8, // Always 0x14.
+
+  /// Up, Down, Left, Right, Start, Back, LS, RS, LB, RB, Guide, (unused), A, B, X, Y.
+  buttons: [u8; 2],
+
+  left_trigger: u8,
+  right_trigger: u8,
+
+  /// Little endian i16s, with positive Y being up.
+  left_stick_x: [u8; 2],
+  left_stick_y: [u8; 2],
+  right_stick_x: [u8; 2],
+  right_stick_y: [u8; 2],
+
+  reserved: [u8; 6],
+}
+
+/// Scale an axis from [0, 255] to [-32768, 32767], with 128 at exactly 0.
+fn scale_axis(value: u8) -> i16 {
+  match value {
+    255 => i16::max_value(),
+    _ => (i16::from(value) - 128) << 8,
+  }
+}
+
+impl XInputReport {
+  fn new() -> XInputReport {
+    XInputReport {
+      report_type: 0x00,
+      report_size: 0x14,
+      buttons: [0u8; 2],
+      left_trigger: 0,
+      right_trigger: 0,
+      left_stick_x: [0u8; 2],
+      left_stick_y: [0u8; 2],
+      right_stick_x: [0u8; 2],
+      right_stick_y: [0u8; 2],
+      reserved: [0u8; 6],
+    }
+  }
+
+  fn update(&mut self, inputs: &DeviceInputs) {
+    let (horizontal, vertical) = inputs.hat_dpad.to_directions();
+    let up = (vertical == Some(true)) as u8;
+    let down = (vertical == Some(false)) as u8;
+    let left = (horizontal == Some(false)) as u8;
+    let right = (horizontal == Some(true)) as u8;
+
+    let start = inputs.button_start.get() as u8;
+    let back = inputs.button_select.get() as u8;
+    let ls = inputs.button_l3.get() as u8;
+    let rs = inputs.button_r3.get() as u8;
+    let lb = inputs.button_l1.get() as u8;
+    let rb = inputs.button_r1.get() as u8;
+    let guide = inputs.button_home.get() as u8;
+    let a = inputs.button_south.get() as u8;
+    let b = inputs.button_east.get() as u8;
+    let x = inputs.button_west.get() as u8;
+    let y = inputs.button_north.get() as u8;
+
+    self.buttons[0] = up | down << 1 | left << 2 | right << 3 | start << 4 | back << 5 | ls << 6 | rs << 7;
+    self.buttons[1] = lb | rb << 1 | guide << 2 | a << 4 | b << 5 | x << 6 | y << 7;
+
+    self.left_trigger = inputs.axis_left_trigger.get();
+    self.right_trigger = inputs.axis_right_trigger.get();
+
+    // Y is flipped after scaling rather than before, so that the center stays at 0.
+    self.left_stick_x = scale_axis(inputs.axis_left_stick_x.get()).to_le_bytes();
+    self.left_stick_y = scale_axis(inputs.axis_left_stick_y.get())
+      .saturating_neg()
+      .to_le_bytes();
+    self.right_stick_x = scale_axis(inputs.axis_right_stick_x.get()).to_le_bytes();
+    self.right_stick_y = scale_axis(inputs.axis_right_stick_y.get())
+      .saturating_neg()
+      .to_le_bytes();
+  }
+
+  fn as_bytes(&self) -> &[u8] {
+    unsafe {
+      core::slice::from_raw_parts(
+        self as *const XInputReport as *const u8,
+        core::mem::size_of::<XInputReport>(),
+      )
+    }
+  }
+}
+
+/// Vendor-specific interface of a wired Xbox 360 controller.
+pub struct XInputClass<'a, B: UsbBus> {
+  report: XInputReport,
+  interface: InterfaceNumber,
+  ep_in: EndpointIn<'a, B>,
+  ep_out: EndpointOut<'a, B>,
+}
+
+impl<B: UsbBus> XInputClass<'_, B> {
+  pub fn new(alloc: &UsbBusAllocator<B>) -> XInputClass<'_, B> {
+    let ep_in = alloc
+      .alloc(
+        Some(EndpointAddress::from_parts(1, UsbDirection::In)),
+        EndpointType::Interrupt,
+        32,
+        4,
+      )
+      .unwrap();
+
+    let ep_out = alloc
+      .alloc(
+        Some(EndpointAddress::from_parts(1, UsbDirection::Out)),
+        EndpointType::Interrupt,
+        32,
+        8,
+      )
+      .unwrap();
+
+    XInputClass {
+      report: XInputReport::new(),
+      interface: alloc.interface(),
+      ep_in,
+      ep_out,
+    }
+  }
+
+  pub fn send(&mut self, inputs: &DeviceInputs) {
+    self.report.update(inputs);
+
+    let data = self.report.as_bytes();
+    let result = self.ep_in.write(data);
+    if let Ok(len) = result {
+      if len != data.len() {
+        error!(
+          "write returned short: expected to write {} bytes, actually wrote {}",
+          data.len(),
+          len
+        );
+      }
+    }
+  }
+}
+
+impl<B: UsbBus> UsbClass<B> for XInputClass<'_, B> {
+  fn poll(&mut self) {
+    let mut buf = [0u8; 32];
+    if let Ok(len) = self.ep_out.read(&mut buf) {
+      // Rumble (type 0x00) and LED (type 0x01) commands, neither of which we can do anything with.
+      debug!("XInputClass: received output report: {:x?}", &buf[..len]);
+    }
+  }
+
+  fn reset(&mut self) {
+    info!("XInputClass::reset");
+  }
+
+  #[rustfmt::skip]
+  fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> usb_device::Result<()> {
+    debug!("XInputClass::get_configuration_descriptors");
+    writer.interface(
+      self.interface,
+      0xFF, // Vendor specific
+      0x5D, // XInput subclass
+      0x01, // XInput protocol
+    )?;
+
+    // Undocumented descriptor that's present on every Xbox 360 controller, which contains the endpoint addresses.
+    writer.write(
+      0x21,
+      &[
+        0x00, 0x01, 0x01, 0x25,
+        u8::from(self.ep_in.address()), 0x14,
+        0x00, 0x00, 0x00, 0x00, 0x13,
+        u8::from(self.ep_out.address()), 0x08,
+        0x00, 0x00,
+      ],
+    )?;
+
+    writer.endpoint(&self.ep_in)?;
+    writer.endpoint(&self.ep_out)?;
+
+    Ok(())
+  }
+
+  fn get_string(&self, index: StringIndex, _lang_id: u16) -> Option<&str> {
+    if u8::from(index) == MS_OS_STRING_INDEX {
+      Some(MS_OS_STRING)
+    } else {
+      None
+    }
+  }
+
+  fn control_in(&mut self, xfer: ControlIn<B>) {
+    let req = *xfer.request();
+    if req.request_type == RequestType::Vendor && req.request == MS_VENDOR_CODE {
+      if req.index == MS_EXTENDED_COMPAT_ID {
+        debug!("fulfilling extended compat ID descriptor request");
+        xfer.accept_with(&MS_EXTENDED_COMPAT_ID_DESCRIPTOR).ok();
+      } else {
+        warn!("unhandled Microsoft OS descriptor request: {:?}", req);
+        xfer.reject().ok();
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::input::Hat;
+
+  #[test]
+  fn axes() {
+    assert_eq!(-32768, scale_axis(0));
+    assert_eq!(-256, scale_axis(127));
+    assert_eq!(0, scale_axis(128));
+    assert_eq!(256, scale_axis(129));
+    assert_eq!(32256, scale_axis(254));
+    assert_eq!(32767, scale_axis(255));
+  }
+
+  #[test]
+  fn report() {
+    let mut report = XInputReport::new();
+    let mut inputs = DeviceInputs::default();
+    report.update(&inputs);
+
+    #[rustfmt::skip]
+    let expected: &[u8] = &[
+      0x00, 0x14,
+      0x00, 0x00,
+      128, 128,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+    assert_eq!(expected, report.as_bytes());
+
+    inputs.hat_dpad = Hat::NorthWest;
+    inputs.button_start.set_value(true);
+    inputs.button_south.set_value(true);
+    inputs.button_home.set_value(true);
+    inputs.axis_left_trigger.set_value(0);
+    inputs.axis_right_trigger.set_value(255);
+    inputs.axis_left_stick_x.set_value(0);
+    inputs.axis_left_stick_y.set_value(0);
+    inputs.axis_right_stick_x.set_value(255);
+    inputs.axis_right_stick_y.set_value(255);
+    report.update(&inputs);
+
+    #[rustfmt::skip]
+    let expected: &[u8] = &[
+      0x00, 0x14,
+
+      // Up, Left and Start, then Guide and A.
+      0x15, 0x14,
+      0, 255,
+
+      // Left stick all the way left and up, right stick all the way right and down.
+      0x00, 0x80, 0xff, 0x7f,
+      0xff, 0x7f, 0x01, 0x80,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+    assert_eq!(expected, report.as_bytes());
+  }
+}
@@ -1,6 +1,10 @@
 /* Linker script for the STM32F103C8T6 */
 MEMORY
 {
-  FLASH : ORIGIN = 0x08000000, LENGTH = 128K
+  /* The last two 1K pages of flash are reserved for the DS4 key (see ds4auth::DS4KeyEncoded). */
+  FLASH : ORIGIN = 0x08000000, LENGTH = 126K
+  DS4KEY : ORIGIN = 0x0801F800, LENGTH = 2K
   RAM : ORIGIN = 0x20000000, LENGTH = 20K
 }
+
+_ds4_key = ORIGIN(DS4KEY);
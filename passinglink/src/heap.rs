@@ -0,0 +1,23 @@
+//! The global allocator, for code that wants `alloc`. Signing doesn't need a heap, so this is only built with the heap
+//! feature.
+//!
+//! Every allocation is traced, so that the heap console command can show what the pools need to hold.
+
+use cortex_m::peripheral::DWT;
+
+use passinglink_core::allocator::{Allocator, Pool, Trace};
+
+pub type Pools = (Pool<32, 16>, Pool<128, 8>, Pool<512, 2>);
+
+#[global_allocator]
+pub static HEAP: Allocator<Pools, Trace<32>> =
+  Allocator::with_tracer((Pool::new(), Pool::new(), Pool::new()), Trace::new(cycle_count));
+
+fn cycle_count() -> u32 {
+  DWT::get_cycle_count()
+}
+
+#[alloc_error_handler]
+fn alloc_error_handler(layout: core::alloc::Layout) -> ! {
+  panic!("failed to allocate {} bytes", layout.size());
+}
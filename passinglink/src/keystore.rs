@@ -0,0 +1,128 @@
+//! Storage for the DS4 key, in the flash pages reserved by memory.x.
+
+use ds4auth::{DS4Key, DS4KeyEncoded, SelfTestError};
+use passinglink_core::provision::{self, KeyStore, KEY_SIZE};
+use stm32f1xx_hal::stm32;
+
+extern "C" {
+  /// The DS4 key pages reserved in memory.x.
+  static _ds4_key: DS4KeyEncoded;
+}
+
+const PAGE_SIZE: usize = 1024;
+
+const FLASH_KEY1: u32 = 0x4567_0123;
+const FLASH_KEY2: u32 = 0xCDEF_89AB;
+
+fn stored_key() -> &'static DS4KeyEncoded {
+  unsafe { &_ds4_key }
+}
+
+/// Load and self-test the provisioned key. With the embedded_key feature, the key built into the firmware image is
+/// used if there isn't one.
+pub fn load() -> Option<Result<DS4Key, SelfTestError>> {
+  let encoded = stored_key();
+  if !encoded.is_blank() {
+    info!("ds4 keypair loaded from flash");
+    return Some(self_test(encoded));
+  }
+  info!("ds4 key page is blank");
+
+  #[cfg(feature = "embedded_key")]
+  {
+    if let Some(encoded) = DS4KeyEncoded::embedded() {
+      info!("ds4 keypair loaded from firmware image");
+      return Some(self_test(&encoded));
+    }
+  }
+
+  warn!("no ds4 keypair available, PS4 authentication disabled");
+  None
+}
+
+/// Check that a key is consistent and actually works, so that a broken one shows up at boot instead of partway through
+/// a session.
+fn self_test(encoded: &DS4KeyEncoded) -> Result<DS4Key, SelfTestError> {
+  let result = encoded.self_test();
+  match result {
+    Ok(_) => info!("ds4 key self-test passed"),
+    Err(err) => error!("ds4 key self-test failed: {}", err),
+  }
+  result
+}
+
+/// Writes provisioned keys to the reserved flash pages.
+///
+/// This pokes at the flash registers directly, since the HAL doesn't know how to program flash. Anything that executes
+/// from flash (i.e. everything, including interrupt handlers) stalls while a page is being erased.
+pub struct FlashKeyStore;
+
+impl FlashKeyStore {
+  fn wait(flash: &stm32::flash::RegisterBlock) -> Result<(), ()> {
+    while flash.sr.read().bsy().bit_is_set() {}
+
+    let sr = flash.sr.read();
+    if sr.pgerr().bit_is_set() || sr.wrprterr().bit_is_set() {
+      error!(
+        "flash operation failed: pgerr = {}, wrprterr = {}",
+        sr.pgerr().bit_is_set(),
+        sr.wrprterr().bit_is_set()
+      );
+      flash.sr.write(|w| w.pgerr().set_bit().wrprterr().set_bit());
+      return Err(());
+    }
+
+    Ok(())
+  }
+
+  fn erase_and_program(flash: &stm32::flash::RegisterBlock, base: usize, key: &[u8; KEY_SIZE]) -> Result<(), ()> {
+    for page in (base..base + KEY_SIZE).step_by(PAGE_SIZE) {
+      flash.cr.modify(|_, w| w.per().set_bit());
+      flash.ar.write(|w| unsafe { w.far().bits(page as u32) });
+      flash.cr.modify(|_, w| w.strt().set_bit());
+      let result = FlashKeyStore::wait(flash);
+      flash.cr.modify(|_, w| w.per().clear_bit());
+      result?;
+    }
+
+    // Flash can only be programmed a half-word at a time.
+    flash.cr.modify(|_, w| w.pg().set_bit());
+    let mut result = Ok(());
+    for (i, halfword) in key.chunks(2).enumerate() {
+      let address = (base + i * 2) as *mut u16;
+      unsafe { core::ptr::write_volatile(address, u16::from_le_bytes([halfword[0], halfword[1]])) };
+      result = FlashKeyStore::wait(flash);
+      if result.is_err() {
+        break;
+      }
+    }
+    flash.cr.modify(|_, w| w.pg().clear_bit());
+    result
+  }
+}
+
+impl KeyStore for FlashKeyStore {
+  fn verify(&mut self, key: &[u8; KEY_SIZE]) -> Result<(), ()> {
+    provision::test_sign(key)
+  }
+
+  fn commit(&mut self, key: &[u8; KEY_SIZE]) -> Result<(), ()> {
+    let flash = unsafe { &*stm32::FLASH::ptr() };
+    let base = stored_key() as *const DS4KeyEncoded as usize;
+
+    flash.keyr.write(|w| unsafe { w.key().bits(FLASH_KEY1) });
+    flash.keyr.write(|w| unsafe { w.key().bits(FLASH_KEY2) });
+    let result = FlashKeyStore::erase_and_program(flash, base, key);
+    flash.cr.modify(|_, w| w.lock().set_bit());
+    result?;
+
+    // Read back with volatile reads, since the compiler is entitled to assume that an immutable static never changes.
+    let written = (0..KEY_SIZE).map(|i| unsafe { core::ptr::read_volatile((base + i) as *const u8) });
+    if !written.eq(key.iter().cloned()) {
+      error!("ds4 key page doesn't match after writing");
+      return Err(());
+    }
+
+    Ok(())
+  }
+}
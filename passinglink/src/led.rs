@@ -0,0 +1,147 @@
+use embedded_hal::PwmPin;
+
+use passinglink_core::hid::PS4OutputReport;
+
+/// Interval at which RgbLed::tick should be called.
+pub const TICK_INTERVAL_MS: u32 = 10;
+
+/// Placeholder PWM channel for boards that don't have an RGB LED.
+pub struct NoPwm;
+
+impl PwmPin for NoPwm {
+  type Duty = u16;
+
+  fn disable(&mut self) {}
+  fn enable(&mut self) {}
+
+  fn get_duty(&self) -> u16 {
+    0
+  }
+
+  fn get_max_duty(&self) -> u16 {
+    0
+  }
+
+  fn set_duty(&mut self, _duty: u16) {}
+}
+
+/// RGB LED that mirrors the DualShock 4 lightbar.
+pub struct RgbLed<R, G, B> {
+  channels: Option<(R, G, B)>,
+
+  color: [u8; 3],
+
+  /// Flash durations, in units of 10ms. The LED is solid if either is zero.
+  flash_on: u8,
+  flash_off: u8,
+
+  /// Number of ticks elapsed in the current flash cycle.
+  ticks: u16,
+
+  /// Whether the fault pattern is being displayed instead of the host's requested state.
+  fault: bool,
+}
+
+impl<R, G, B> RgbLed<R, G, B>
+where
+  R: PwmPin<Duty = u16>,
+  G: PwmPin<Duty = u16>,
+  B: PwmPin<Duty = u16>,
+{
+  pub fn new(mut channels: Option<(R, G, B)>) -> RgbLed<R, G, B> {
+    if let Some((r, g, b)) = channels.as_mut() {
+      r.enable();
+      g.enable();
+      b.enable();
+    }
+
+    // Stay white until the host tells us otherwise, which is what the board has always done.
+    let mut led = RgbLed {
+      channels,
+      color: [255, 255, 255],
+      flash_on: 0,
+      flash_off: 0,
+      ticks: 0,
+      fault: false,
+    };
+    led.write(true);
+    led
+  }
+
+  /// Update the color and flash pattern from the state requested by the host.
+  pub fn set(&mut self, output: &PS4OutputReport) {
+    if self.fault {
+      return;
+    }
+
+    let color = [output.led_red, output.led_green, output.led_blue];
+    if (output.led_flash_on, output.led_flash_off) != (self.flash_on, self.flash_off) {
+      self.flash_on = output.led_flash_on;
+      self.flash_off = output.led_flash_off;
+      self.ticks = 0;
+    } else if color == self.color {
+      return;
+    }
+
+    debug!(
+      "RgbLed: color = {:?}, flash = ({}, {})",
+      color, self.flash_on, self.flash_off
+    );
+    self.color = color;
+    self.write(self.lit());
+  }
+
+  /// Flash red regardless of what the host asks for, e.g. when the DS4 key fails its self-test.
+  pub fn set_fault(&mut self, fault: bool) {
+    if fault == self.fault {
+      return;
+    }
+
+    self.fault = fault;
+    if fault {
+      self.color = [255, 0, 0];
+      self.flash_on = 25;
+      self.flash_off = 25;
+    } else {
+      self.color = [255, 255, 255];
+      self.flash_on = 0;
+      self.flash_off = 0;
+    }
+    self.ticks = 0;
+    self.write(self.lit());
+  }
+
+  pub fn fault(&self) -> bool {
+    self.fault
+  }
+
+  /// Advance the flash pattern by one tick.
+  pub fn tick(&mut self) {
+    if self.flash_on == 0 || self.flash_off == 0 {
+      return;
+    }
+
+    let period = self.flash_on as u16 + self.flash_off as u16;
+    let was_lit = self.lit();
+    self.ticks = (self.ticks + 1) % period;
+    let lit = self.lit();
+    if lit != was_lit {
+      self.write(lit);
+    }
+  }
+
+  /// Whether the LED is currently on in its flash pattern.
+  pub fn lit(&self) -> bool {
+    self.flash_on == 0 || self.flash_off == 0 || self.ticks < self.flash_on as u16
+  }
+
+  fn write(&mut self, lit: bool) {
+    let color = if lit { self.color } else { [0, 0, 0] };
+    if let Some((r, g, b)) = self.channels.as_mut() {
+      let scale = |max: u16, value: u8| (max as u32 * value as u32 / 255) as u16;
+      r.set_duty(scale(r.get_max_duty(), color[0]));
+      g.set_duty(scale(g.get_max_duty(), color[1]));
+      b.set_duty(scale(b.get_max_duty(), color[2]));
+    }
+  }
+}
@@ -0,0 +1,134 @@
+//! The log backend, which timestamps each record and writes it to every output that's built in: the UART, and the
+//! USB serial port.
+
+use core::cell::RefCell;
+use core::fmt::Write;
+
+use cortex_m::interrupt;
+use cortex_m::peripheral::DWT;
+
+struct Clock {
+  /// Number of seconds that have elapsed so far.
+  seconds: u32,
+
+  /// Cycle count at the last second.
+  cycles: u32,
+}
+
+impl Clock {
+  fn tick(&mut self) {
+    self.seconds += 1;
+    self.cycles = DWT::get_cycle_count();
+  }
+
+  fn elapsed_s(&self) -> u32 {
+    self.seconds
+  }
+
+  fn elapsed_us(&self) -> u32 {
+    let current = DWT::get_cycle_count();
+    let cycles = if current >= self.cycles {
+      current - self.cycles
+    } else {
+      core::u32::MAX - self.cycles + current
+    };
+
+    cycles / 72
+  }
+}
+
+/// Every output that's built in.
+pub struct Output;
+
+impl core::fmt::Write for Output {
+  fn write_str(&mut self, s: &str) -> core::fmt::Result {
+    unsafe {
+      #[cfg(not(feature = "no_serial"))]
+      {
+        if let Some(ref mut serial) = crate::SERIAL {
+          let _ = serial.write_str(s);
+        }
+      }
+
+      #[cfg(feature = "usb_serial")]
+      {
+        if let Some(ref usb_log) = crate::USB_LOG {
+          usb_log.write(s.as_bytes());
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+pub struct Logger {
+  clock: RefCell<Clock>,
+}
+
+unsafe impl Send for Logger {}
+unsafe impl Sync for Logger {}
+
+impl Logger {
+  pub fn new() -> Logger {
+    Logger {
+      clock: RefCell::new(Clock {
+        seconds: 0,
+        cycles: DWT::get_cycle_count(),
+      }),
+    }
+  }
+
+  pub fn tick(&self) {
+    interrupt::free(|_| {
+      self.clock.borrow_mut().tick();
+    })
+  }
+}
+
+impl log::Log for Logger {
+  fn enabled(&self, _: &log::Metadata) -> bool {
+    true
+  }
+
+  fn log(&self, record: &log::Record) {
+    interrupt::free(|_| {
+      let (s, us) = {
+        let clock = self.clock.borrow();
+        (clock.elapsed_s(), clock.elapsed_us())
+      };
+
+      if cfg!(feature = "color") {
+        const GREEN: &str = "\x1b[32m";
+        const RED: &str = "\x1b[31m";
+        const ORANGE: &str = "\x1b[31;1m";
+        const BRIGHT_WHITE: &str = "\x1b[37;1m";
+        const WHITE: &str = "\x1b[37m";
+        const GREY: &str = "\x1b[30;1m";
+        const RESET: &str = "\x1b[0m";
+
+        let color = match record.level() {
+          log::Level::Error => RED,
+          log::Level::Warn => ORANGE,
+          log::Level::Info => BRIGHT_WHITE,
+          log::Level::Debug => WHITE,
+          log::Level::Trace => GREY,
+        };
+
+        let _ = write!(
+          Output,
+          "{}[{:5}.{:06}] {}{}{}\r\n",
+          GREEN,
+          s,
+          us,
+          color,
+          record.args(),
+          RESET
+        );
+      } else {
+        let _ = write!(Output, "[{:5}.{:06}] {}\r\n", s, us, record.args());
+      }
+    });
+  }
+
+  fn flush(&self) {}
+}
@@ -1,33 +1,17 @@
 #![no_main]
 #![no_std]
 #![allow(non_snake_case)]
-#![feature(asm, alloc_error_handler)]
-
-extern crate alloc;
-
-mod allocator;
-
-#[global_allocator]
-static ALLOC: allocator::Allocator = allocator::Allocator::new();
-
-#[alloc_error_handler]
-fn alloc_error_handler(_: alloc::alloc::Layout) -> ! {
-  panic!("failed to allocate");
-}
+#![cfg_attr(feature = "heap", feature(alloc_error_handler))]
 
 extern crate panic_semihosting;
 
 #[macro_use]
 extern crate log;
 
-#[macro_use]
-extern crate proper;
-
 #[cfg(not(feature = "no_serial"))]
 use core::fmt::Write;
 
 use cortex_m::asm::delay;
-use cortex_m::interrupt;
 
 use rtfm::app;
 use rtfm::Instant;
@@ -39,24 +23,81 @@ use stm32f1xx_hal::serial::{Parity, Serial, StopBits};
 
 use stm32_usbd::{UsbBus, UsbPinsType};
 use usb_device::bus;
+use usb_device::class::UsbClass;
 use usb_device::prelude::*;
 
-pub mod auth;
-mod hid;
+use heapless::consts::U2;
+
+use passinglink_core::auth::{self, Authenticator, Signer};
+use passinglink_core::console::Console;
+#[cfg(not(feature = "no_serial"))]
+use passinglink_core::console::{self, Command, SocdAxis};
+use passinglink_core::hid::{self, Hid};
+use passinglink_core::input::*;
+use passinglink_core::provision::Provisioner;
+use passinglink_core::xinput;
+
+#[cfg(feature = "heap")]
+mod heap;
+
+mod keystore;
+mod led;
 
 #[macro_use]
-mod input;
-use input::*;
+mod pins;
+use pins::*;
+
+#[cfg(any(not(feature = "no_serial"), feature = "usb_serial"))]
+mod logger;
 
 #[cfg(not(feature = "no_serial"))]
 mod serial;
 
+mod usb_serial;
+
 const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
+#[cfg(any(not(feature = "no_serial"), feature = "usb_serial"))]
+static mut LOGGER: Option<logger::Logger> = None;
+
 #[cfg(not(feature = "no_serial"))]
 static mut SERIAL: Option<serial::BufferedSerial> = None;
 
-static mut OUTPUT: DeviceInputs = DeviceInputs::default();
+#[cfg(feature = "usb_serial")]
+static mut USB_LOG: Option<usb_serial::UsbLog> = None;
+
+/// Answer a debug console command. Answers go straight to the log's outputs, so that the log level can't hide them.
+#[cfg(not(feature = "no_serial"))]
+macro_rules! reply {
+  ($($arg:tt)*) => {
+    {
+      let _ = write!(logger::Output, $($arg)*);
+      let _ = logger::Output.write_str("\r\n");
+    }
+  };
+}
+
+static AUTH: Authenticator = Authenticator::new();
+static PROVISIONER: Provisioner = Provisioner::new();
+
+/// Commands typed on the UART, which never get any without serial.
+static CONSOLE: Console = Console::new();
+
+/// Times each signature with the DWT cycle counter, for the statistics in the diagnostics report.
+struct TimedSigner<'a>(&'a ds4auth::DS4Key);
+
+impl<'a> Signer for TimedSigner<'a> {
+  fn sign(&self, nonce: &[u8; 256], response: &mut [u8; 1064]) -> Result<(), ()> {
+    // The cycle counter wraps after a minute at 72MHz, which is far longer than signing takes.
+    let start = cortex_m::peripheral::DWT::get_cycle_count();
+    let result = Signer::sign(self.0, nonce, response);
+    let elapsed = cortex_m::peripheral::DWT::get_cycle_count().wrapping_sub(start);
+    if result.is_ok() {
+      AUTH.record_signing_time(elapsed / 72);
+    }
+    result
+  }
+}
 
 trait InfallibleInputPin {
   fn is_low(&self) -> bool;
@@ -104,9 +145,17 @@ impl<T: embedded_hal::digital::v2::OutputPin> InfallibleOutputPin for T {
 const APP: () = {
   static mut INPUT: InputPins = ();
   static mut LED: LedPins = ();
+  static mut PCB_LED: PcbLed = ();
+
+  static mut DEBOUNCER: InputDebouncer = InputDebouncer::new(DebounceConfig::default());
+
+  static mut PROCESSOR: InputProcessor = InputProcessor::new(SocdType::Neutral, SocdType::Positive);
+  static mut OUTPUT: DeviceInputs = DeviceInputs::default();
 
   static mut USB_DEV: UsbDevice<'static, UsbBus<UsbPinsType>> = ();
-  static mut USB_HID: hid::HidClass<'static, hid::PS4Hid, UsbBus<UsbPinsType>> = ();
+  static mut USB_HID: Option<hid::HidClass<'static, hid::AnyHid, UsbBus<UsbPinsType>>> = ();
+  static mut USB_XINPUT: Option<xinput::XInputClass<'static, UsbBus<UsbPinsType>>> = ();
+  static mut USB_SERIAL: Option<usb_serial::UsbSerial<UsbBus<UsbPinsType>>> = ();
 
   #[init]
   fn init() {
@@ -124,6 +173,15 @@ const APP: () = {
 
     assert!(clocks.usbclk_valid());
 
+    // The idle loop sleeps when there's nothing to do, but the cycle counter that the timer queue runs on stops along
+    // with the core clock, which would stretch every scheduled task by however long the core slept. DBG_SLEEP keeps
+    // HCLK running during sleep so that it keeps counting. That costs most of what sleeping would otherwise save,
+    // since only the core itself stops switching, and it leans on the debug block in production firmware. RTFM 0.4's
+    // timer queue is hardwired to the cycle counter, so getting the power back means moving the scheduled tasks onto a
+    // timer peripheral (which keeps running during sleep) instead. Until then, sleeping is about not busy-spinning the
+    // idle loop, rather than about power.
+    device.DBGMCU.cr.modify(|_, w| w.dbg_sleep().set_bit());
+
     let mut gpioa = device.GPIOA.split(&mut rcc.apb2);
     let mut gpiob = device.GPIOB.split(&mut rcc.apb2);
     let mut gpioc = device.GPIOC.split(&mut rcc.apb2);
@@ -132,20 +190,13 @@ const APP: () = {
     let input = assign_inputs!(gpioa, gpiob, gpioc, gpiod);
     let mut led = assign_leds!(gpioa, gpiob, gpioc, gpiod);
 
+    let mut afio = device.AFIO.constrain(&mut rcc.apb2);
+
     led.front.set_high();
-    if let Some(ref mut r) = led.pcb_r {
-      r.set_high();
-    }
-    if let Some(ref mut g) = led.pcb_g {
-      g.set_high();
-    }
-    if let Some(ref mut b) = led.pcb_b {
-      b.set_high();
-    }
+    let pcb_led = assign_pcb_led!(led, device.TIM3, &mut afio.mapr, clocks, &mut rcc.apb1);
 
     #[cfg(not(feature = "no_serial"))]
     {
-      let mut afio = device.AFIO.constrain(&mut rcc.apb2);
       let pin_tx = gpioa.pa2.into_alternate_push_pull(&mut gpioa.crl);
       let pin_rx = gpioa.pa3;
 
@@ -169,11 +220,21 @@ const APP: () = {
       unsafe {
         let _ = write!(buffered_serial, "\r\n\r\n");
         SERIAL = Some(buffered_serial);
-        log::set_logger(SERIAL.as_ref().unwrap()).unwrap();
-        log::set_max_level(log::LevelFilter::Trace);
       }
     }
 
+    #[cfg(feature = "usb_serial")]
+    unsafe {
+      USB_LOG = Some(usb_serial::UsbLog::new());
+    }
+
+    #[cfg(any(not(feature = "no_serial"), feature = "usb_serial"))]
+    unsafe {
+      LOGGER = Some(logger::Logger::new());
+      log::set_logger(LOGGER.as_ref().unwrap()).unwrap();
+      log::set_max_level(log::LevelFilter::Trace);
+    }
+
     // BluePill board has a pull-up resistor on the D+ line.
     // Pull the D+ pin down to send a RESET condition to the USB bus.
     let mut usb_dp = gpioa.pa12.into_push_pull_output(&mut gpioa.crh);
@@ -184,123 +245,121 @@ const APP: () = {
     let usb_dp = usb_dp.into_floating_input(&mut gpioa.crh);
     *USB_BUS = Some(UsbBus::new(device.USB, (usb_dm, usb_dp)));
 
-    let ps4_hid = hid::PS4Hid::new(unsafe { &mut OUTPUT as *mut DeviceInputs });
-    let usb_hid = hid::HidClass::new(ps4_hid, USB_BUS.as_ref().unwrap());
-    let usb_dev = UsbDeviceBuilder::new(USB_BUS.as_ref().unwrap(), UsbVidPid(0x1209, 0x214D))
-      .manufacturer("jmgao")
-      .product("Passing Link")
+    let boot_inputs = read_inputs(&input);
+
+    // The PS3 switch takes precedence, otherwise hold a face button while plugging in to select a mode:
+    //   West (X/□): Switch
+    //   North (Y/△): XInput
+    //   East (B/○): PC
+    //   South (A/✖): Keyboard
+    //   Start: PS4, with DS4 key provisioning enabled
+    let device_hid = if boot_inputs.mode_ps3 {
+      info!("PS3 mode selected");
+      Some(hid::AnyHid::PS3(hid::PS3Hid::new()))
+    } else if boot_inputs.button_west {
+      info!("Switch mode selected");
+      Some(hid::AnyHid::Switch(hid::SwitchHid::new()))
+    } else if boot_inputs.button_north {
+      info!("XInput mode selected");
+      None
+    } else if boot_inputs.button_east {
+      info!("PC mode selected");
+      Some(hid::AnyHid::PC(hid::PCHid::new()))
+    } else if boot_inputs.button_south {
+      info!("Keyboard mode selected");
+      Some(hid::AnyHid::Keyboard(hid::KeyboardHid::new(hid::KeyMap::default())))
+    } else if boot_inputs.button_start {
+      info!("PS4 mode selected, with key provisioning");
+      Some(hid::AnyHid::PS4(hid::PS4Hid::new(&AUTH).with_provisioner(&PROVISIONER)))
+    } else {
+      Some(hid::AnyHid::PS4(hid::PS4Hid::new(&AUTH)))
+    };
+
+    let usb_bus = USB_BUS.as_ref().unwrap();
+    let (identity, device_class) = match device_hid {
+      Some(ref hid) => (hid.identity(), 0x00),
+      None => (xinput::IDENTITY, 0xFF),
+    };
+
+    let usb_xinput = match device_hid {
+      Some(_) => None,
+      None => Some(xinput::XInputClass::new(usb_bus)),
+    };
+    let usb_hid = device_hid.map(|hid| hid::HidClass::new(hid, usb_bus));
+    let usb_serial = usb_serial::new(usb_bus);
+
+    // A composite device with a CDC interface has to declare that it uses interface association descriptors.
+    let (device_class, device_sub_class, device_protocol) = match usb_serial {
+      Some(_) => (0xEF, 0x02, 0x01),
+      None => (device_class, device_class, device_class),
+    };
+
+    let usb_dev = UsbDeviceBuilder::new(usb_bus, UsbVidPid(identity.vid, identity.pid))
+      .manufacturer(identity.manufacturer)
+      .product(identity.product)
       .serial_number("66C623A66B214BB226X76C236B214A214CC6C236B")
-      .device_class(0x00)
-      .device_sub_class(0x00)
+      .device_class(device_class)
+      .device_sub_class(device_sub_class)
+      .device_protocol(device_protocol)
       .max_power(500)
       .max_packet_size_0(64)
       .build();
 
     INPUT = input;
     LED = led;
+    PCB_LED = pcb_led;
     USB_DEV = usb_dev;
     USB_HID = usb_hid;
+    USB_XINPUT = usb_xinput;
+    USB_SERIAL = usb_serial;
   }
 
-  #[task(resources = [INPUT, USB_DEV, USB_HID])]
+  #[task(resources = [INPUT, DEBOUNCER, PROCESSOR, OUTPUT, USB_DEV, USB_HID, USB_XINPUT])]
   fn input_poll() {
-    interrupt::free(|_| unsafe {
-      OUTPUT.button_north.set_value(resources.INPUT.button_north.is_low());
-      OUTPUT.button_east.set_value(resources.INPUT.button_east.is_low());
-      OUTPUT.button_south.set_value(resources.INPUT.button_south.is_low());
-      OUTPUT.button_west.set_value(resources.INPUT.button_west.is_low());
-
-      OUTPUT.button_l1.set_value(resources.INPUT.button_l1.is_low());
-      OUTPUT.button_r1.set_value(resources.INPUT.button_r1.is_low());
-
-      let l2 = resources.INPUT.button_l2.is_low();
-      OUTPUT.button_l2.set_value(l2);
-      OUTPUT.axis_left_trigger.set_value(if l2 { 255 } else { 0 });
-
-      let r2 = resources.INPUT.button_r2.is_low();
-      OUTPUT.button_r2.set_value(r2);
-      OUTPUT.axis_right_trigger.set_value(if r2 { 255 } else { 0 });
-
-      OUTPUT.button_l3.set_value(resources.INPUT.button_l3.is_low());
-      OUTPUT.button_r3.set_value(resources.INPUT.button_r3.is_low());
-
-      if !resources.INPUT.mode_lock.is_low() {
-        OUTPUT.button_home.set_value(resources.INPUT.button_home.is_low());
-        OUTPUT.button_start.set_value(resources.INPUT.button_start.is_low());
-        OUTPUT.button_select.set_value(resources.INPUT.button_select.is_low());
-      }
+    let raw = read_inputs(&resources.INPUT);
+    let inputs = resources.DEBOUNCER.update(&raw);
+    resources.PROCESSOR.process(&inputs, &mut resources.OUTPUT);
 
-      let _ = resources.INPUT.mode_ls.is_low();
-      let _ = resources.INPUT.mode_rs.is_low();
-      let _ = resources.INPUT.mode_ps3.is_low();
+    if let Some(hid) = resources.USB_HID.as_mut() {
+      hid.send(&resources.OUTPUT);
+    }
+    if let Some(xinput) = resources.USB_XINPUT.as_mut() {
+      xinput.send(&resources.OUTPUT);
+    }
+  }
 
-      OUTPUT
-        .button_trackpad
-        .set_value(resources.INPUT.button_trackpad.is_low());
+  #[task(schedule = [led_tick], resources = [USB_HID, LED, PCB_LED])]
+  fn led_tick() {
+    if let Some(output) = resources.USB_HID.as_ref().and_then(|hid| hid.hid().ps4_output()) {
+      resources.PCB_LED.set(&output);
+    }
+    resources.PCB_LED.tick();
 
-      let (left, right) = (
-        resources.INPUT.stick_left.is_low(),
-        resources.INPUT.stick_right.is_low(),
-      );
-      let (up, down) = (resources.INPUT.stick_up.is_low(), resources.INPUT.stick_down.is_low());
-
-      // None is neutral, Some(false) is left, Some(true) is right.
-      let horizontal = match (left, right) {
-        // Horizontal SOCD = neutral.
-        (true, true) => None,
-        (true, false) => Some(false),
-        (false, true) => Some(true),
-        (false, false) => None,
-      };
+    // Mirror faults on the front LED too, since not every board has an RGB LED.
+    if !resources.PCB_LED.fault() || resources.PCB_LED.lit() {
+      resources.LED.front.set_high();
+    } else {
+      resources.LED.front.set_low();
+    }
 
-      // None is neutral, Some(false) is down, Some(true) is up.
-      let vertical = match (up, down) {
-        // Vertical SOCD = up.
-        (true, true) => Some(true),
-        (true, false) => Some(true),
-        (false, true) => Some(false),
-        (false, false) => None,
-      };
+    let interval = (72_000 * led::TICK_INTERVAL_MS).cycles();
+    schedule.led_tick(scheduled + interval).unwrap();
+  }
 
-      if resources.INPUT.mode_ls.is_low() {
-        OUTPUT.hat_dpad = Hat::Neutral;
-        OUTPUT.axis_left_stick_x.set_value(match horizontal {
-          Some(true) => 255,
-          None => 127,
-          Some(false) => 0,
-        });
-        OUTPUT.axis_left_stick_y.set_value(match vertical {
-          Some(true) => 0,
-          None => 127,
-          Some(false) => 255,
-        });
-      } else {
-        // RS is stupid, use DPad for that as well.
-        OUTPUT.hat_dpad = match (horizontal, vertical) {
-          (None, None) => Hat::Neutral,
-          (Some(true), None) => Hat::East,
-          (Some(true), Some(false)) => Hat::SouthEast,
-          (None, Some(false)) => Hat::South,
-          (Some(false), Some(false)) => Hat::SouthWest,
-          (Some(false), None) => Hat::West,
-          (Some(false), Some(true)) => Hat::NorthWest,
-          (None, Some(true)) => Hat::North,
-          (Some(true), Some(true)) => Hat::NorthEast,
-        };
-        OUTPUT.axis_left_stick_x.set_value(127);
-        OUTPUT.axis_left_stick_y.set_value(127);
-      }
-    });
+  #[task(schedule = [auth_timeout])]
+  fn auth_timeout() {
+    AUTH.expire_stalled();
 
-    resources.USB_HID.send();
+    let interval = (72_000 * auth::STALL_CHECK_INTERVAL_MS).cycles();
+    schedule.auth_timeout(scheduled + interval).unwrap();
   }
 
   #[task(priority = 16, schedule = [timer_tick])]
   fn timer_tick() {
-    #[cfg(not(feature = "no_serial"))]
+    #[cfg(any(not(feature = "no_serial"), feature = "usb_serial"))]
     unsafe {
-      if let Some(ref mut buffered_serial) = SERIAL {
-        buffered_serial.tick();
+      if let Some(ref logger) = LOGGER {
+        logger.tick();
       }
     }
 
@@ -313,22 +372,33 @@ const APP: () = {
     unsafe {
       if let Some(ref mut serial) = SERIAL {
         serial.poll();
+        serial.receive(&CONSOLE);
       }
     }
   }
 
-  #[interrupt(resources = [USB_DEV, USB_HID])]
+  #[interrupt(resources = [USB_DEV, USB_HID, USB_XINPUT, USB_SERIAL])]
   fn USB_HP_CAN_TX() {
-    usb_poll(&mut resources.USB_DEV, &mut resources.USB_HID);
+    usb_poll(
+      &mut resources.USB_DEV,
+      &mut resources.USB_HID,
+      &mut resources.USB_XINPUT,
+      &mut resources.USB_SERIAL,
+    );
   }
 
-  #[interrupt(schedule = [input_poll], resources = [USB_DEV, USB_HID])]
+  #[interrupt(schedule = [input_poll], resources = [USB_DEV, USB_HID, USB_XINPUT, USB_SERIAL])]
   fn USB_LP_CAN_RX0() {
     // 900 us
     let poll_interval = (72 * 900).cycles();
     let _ = schedule.input_poll(Instant::now() + poll_interval);
 
-    usb_poll(&mut resources.USB_DEV, &mut resources.USB_HID);
+    usb_poll(
+      &mut resources.USB_DEV,
+      &mut resources.USB_HID,
+      &mut resources.USB_XINPUT,
+      &mut resources.USB_SERIAL,
+    );
   }
 
   extern "C" {
@@ -336,33 +406,230 @@ const APP: () = {
     fn EXTI1();
   }
 
-  #[idle(schedule = [timer_tick, input_poll])]
+  #[idle(
+    schedule = [timer_tick, input_poll, led_tick, auth_timeout],
+    resources = [PCB_LED, OUTPUT, PROCESSOR, USB_DEV, USB_HID, USB_SERIAL]
+  )]
   fn idle() -> ! {
     schedule.timer_tick(Instant::now() + 72_000_000.cycles()).unwrap();
     schedule.input_poll(Instant::now() + 72_000.cycles()).unwrap();
+    schedule.led_tick(Instant::now() + 72_000.cycles()).unwrap();
+    schedule.auth_timeout(Instant::now() + 72_000.cycles()).unwrap();
 
     info!("passinglink v{} initialized", VERSION);
 
-    auth::read_keypair();
-    info!("ds4 keypair loaded");
+    // Signing a test nonce takes a while, so only do it at boot when there's a console around to need the key.
+    let ps4 = resources.USB_HID.lock(|hid| match hid.as_ref().map(|hid| hid.hid()) {
+      Some(hid::AnyHid::PS4(_)) => true,
+      _ => false,
+    });
+    let mut keypair = if ps4 {
+      load_keypair(&mut resources.PCB_LED)
+    } else {
+      None
+    };
+    let mut key_store = keystore::FlashKeyStore;
+
+    loop {
+      // Logging doesn't raise a USB interrupt, so whatever was logged since the last one would sit in the queue until
+      // the host polls the controller again.
+      resources.USB_SERIAL.lock(|serial| {
+        if let Some(serial) = serial.as_mut() {
+          usb_serial::flush(serial);
+        }
+      });
+
+      if PROVISIONER.perform_work(&mut key_store) {
+        keypair = load_keypair(&mut resources.PCB_LED);
+      }
 
-    allocator::dump_state();
+      if AUTH.perform_work(keypair.as_ref().map(TimedSigner).as_ref()) {
+        continue;
+      }
 
-    auth::perform_work();
+      #[cfg(not(feature = "no_serial"))]
+      {
+        if let Some(command) = CONSOLE.take() {
+          match command {
+            Ok(Command::Help) => {
+              for (usage, description) in console::HELP {
+                reply!("{}: {}", usage, description);
+              }
+            }
+
+            Ok(Command::Status) => {
+              let mode = resources
+                .USB_HID
+                .lock(|hid| hid.as_ref().map_or("XInput", |hid| hid.hid().name()));
+              let usb_state = resources.USB_DEV.lock(|usb_dev| usb_dev.state());
+              let socd = resources
+                .PROCESSOR
+                .lock(|processor| (processor.horizontal.mode(), processor.vertical.mode()));
+              let stats = AUTH.stats();
+
+              reply!("mode: {}, USB: {:?}, SOCD: {:?}/{:?}", mode, usb_state, socd.0, socd.1);
+              reply!("key: {}", if keypair.is_some() { "loaded" } else { "none" });
+              reply!(
+                "auth: {:?}, {} completed, {} aborted, {} timed out",
+                AUTH.state(),
+                stats.completed,
+                stats.aborted,
+                stats.timed_out
+              );
+            }
+
+            Ok(Command::Inputs) => {
+              let inputs = resources.OUTPUT.lock(|output| *output);
+              reply!("{}", inputs);
+            }
+
+            Ok(Command::LogLevel(level)) => {
+              log::set_max_level(level);
+              reply!("log level set to {}", level);
+            }
+
+            Ok(Command::Socd(axis, mode)) => {
+              let socd = resources.PROCESSOR.lock(|processor| {
+                if axis != SocdAxis::Vertical {
+                  processor.horizontal.set_mode(mode);
+                }
+                if axis != SocdAxis::Horizontal {
+                  processor.vertical.set_mode(mode);
+                }
+                (processor.horizontal.mode(), processor.vertical.mode())
+              });
+              reply!("SOCD: {:?}/{:?}", socd.0, socd.1);
+            }
+
+            Ok(Command::AuthReset) => {
+              AUTH.reset();
+              reply!("auth: {:?}", AUTH.state());
+            }
+
+            Ok(Command::SelfTest) => {
+              keypair = load_keypair(&mut resources.PCB_LED);
+              reply!("self-test {}", if keypair.is_some() { "passed" } else { "failed" });
+            }
+
+            Ok(Command::Heap) => {
+              #[cfg(feature = "heap")]
+              {
+                heap::HEAP.stats(&mut |stats| {
+                  reply!(
+                    "{} byte slots: {}/{} used, high water {}",
+                    stats.size,
+                    stats.used,
+                    stats.count,
+                    stats.high_water
+                  );
+                });
+
+                // The trace is too long to reply with in one go.
+                heap::HEAP.tracer().dump();
+              }
+
+              #[cfg(not(feature = "heap"))]
+              reply!("no heap, build with the heap feature");
+            }
+
+            Ok(Command::Reboot) => {
+              reply!("rebooting");
+
+              // Give the UART a chance to send that first.
+              delay(72_000_000 / 100);
+              cortex_m::peripheral::SCB::sys_reset();
+            }
+
+            Err(err) => reply!("{}", err),
+          }
+          continue;
+        }
+      }
+
+      // Sleep until the next interrupt, which is where new work comes from. Interrupts are masked while checking so
+      // that work can't arrive between the check and the wfi, which still wakes up for a masked interrupt.
+      cortex_m::interrupt::free(|_| {
+        if !AUTH.has_work() && !PROVISIONER.pending() && !CONSOLE.pending() {
+          cortex_m::asm::wfi();
+        }
+      });
+    }
   }
 };
 
-fn usb_poll<B: bus::UsbBus>(usb_dev: &mut UsbDevice<'static, B>, hid: &mut hid::HidClass<'static, hid::PS4Hid, B>) {
-  if !usb_dev.poll(&mut [hid]) {
-    return;
+/// Load the key, and flag a fault on the LEDs if it doesn't pass its self-test.
+/// Keys that fail are dropped, since they'd only fail again once the console asks for a signature.
+fn load_keypair(pcb_led: &mut impl rtfm::Mutex<T = PcbLed>) -> Option<ds4auth::DS4Key> {
+  let (keypair, fault) = match keystore::load() {
+    Some(Ok(keypair)) => (Some(keypair), false),
+    Some(Err(_)) => (None, true),
+    None => (None, false),
+  };
+  pcb_led.lock(|led| led.set_fault(fault));
+  keypair
+}
+
+fn read_inputs(pins: &InputPins) -> RawInputs {
+  RawInputs {
+    stick_up: pins.stick_up.is_low(),
+    stick_down: pins.stick_down.is_low(),
+    stick_left: pins.stick_left.is_low(),
+    stick_right: pins.stick_right.is_low(),
+
+    button_north: pins.button_north.is_low(),
+    button_east: pins.button_east.is_low(),
+    button_south: pins.button_south.is_low(),
+    button_west: pins.button_west.is_low(),
+
+    button_l1: pins.button_l1.is_low(),
+    button_l2: pins.button_l2.is_low(),
+    button_l3: pins.button_l3.is_low(),
+
+    button_r1: pins.button_r1.is_low(),
+    button_r2: pins.button_r2.is_low(),
+    button_r3: pins.button_r3.is_low(),
+
+    button_start: pins.button_start.is_low(),
+    button_select: pins.button_select.is_low(),
+    button_home: pins.button_home.is_low(),
+    button_trackpad: pins.button_trackpad.is_low(),
+
+    mode_lock: pins.mode_lock.is_low(),
+    mode_ls: pins.mode_ls.is_low(),
+    mode_rs: pins.mode_rs.is_low(),
+    mode_ps3: pins.mode_ps3.is_low(),
   }
 }
 
-// Symbol used by BoringSSL functions compiled by ring.
-#[no_mangle]
-pub extern "C" fn __assert_func(_file: u32, _line: u32, _func: u32, _expr: u32) {
-  error!("assertion triggered");
-  loop {
-    unsafe { asm!("nop") };
+fn usb_poll<B: bus::UsbBus>(
+  usb_dev: &mut UsbDevice<'static, B>,
+  hid: &mut Option<hid::HidClass<'static, hid::AnyHid, B>>,
+  xinput: &mut Option<xinput::XInputClass<'static, B>>,
+  serial: &mut Option<usb_serial::UsbSerial<B>>,
+) {
+  {
+    let mut classes: heapless::Vec<&mut dyn UsbClass<B>, U2> = heapless::Vec::new();
+    match (hid.as_mut(), xinput.as_mut()) {
+      (Some(hid), _) => {
+        let _ = classes.push(hid);
+      }
+      (None, Some(xinput)) => {
+        let _ = classes.push(xinput);
+      }
+      (None, None) => {}
+    }
+
+    if let Some(serial) = serial.as_mut() {
+      let _ = classes.push(serial);
+    }
+
+    if classes.is_empty() {
+      return;
+    }
+    usb_dev.poll(&mut classes);
+  }
+
+  if let Some(serial) = serial.as_mut() {
+    usb_serial::poll(serial);
   }
 }
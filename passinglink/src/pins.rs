@@ -93,6 +93,15 @@ mod detail {
       }
     }}
   }
+
+  pub type PcbLed = crate::led::RgbLed<crate::led::NoPwm, crate::led::NoPwm, crate::led::NoPwm>;
+
+  macro_rules! assign_pcb_led {
+    ($led: expr, $tim3: expr, $mapr: expr, $clocks: expr, $apb1: expr) => {{
+      let _ = (&mut $led, $tim3, $mapr, $clocks, $apb1);
+      PcbLed::new(None)
+    }}
+  }
 }
 
 // 0.4.
@@ -103,7 +112,9 @@ mod detail {
   use stm32f1xx_hal::gpio::gpiob::*;
   use stm32f1xx_hal::gpio::gpioc::*;
   use stm32f1xx_hal::gpio::gpiod::*;
-  use stm32f1xx_hal::gpio::{Input, Output, PullUp, PushPull};
+  use stm32f1xx_hal::gpio::{Alternate, Input, Output, PullUp, PushPull};
+  use stm32f1xx_hal::pwm::{Pwm, C2, C3, C4};
+  use stm32f1xx_hal::stm32::TIM3;
 
   pub struct InputPins {
     pub stick_down: PA10<Input<PullUp>>,
@@ -174,21 +185,34 @@ mod detail {
 
   pub struct LedPins {
     pub front: PA6<Output<PushPull>>,
-    pub pcb_r: Option<PA7<Output<PushPull>>>,
-    pub pcb_g: Option<PB0<Output<PushPull>>>,
-    pub pcb_b: Option<PB1<Output<PushPull>>>,
+    pub pcb_r: Option<PA7<Alternate<PushPull>>>,
+    pub pcb_g: Option<PB0<Alternate<PushPull>>>,
+    pub pcb_b: Option<PB1<Alternate<PushPull>>>,
   }
 
   macro_rules! assign_leds {
     ($gpioa: expr, $gpiob: expr, $gpioc: expr, $gpiod: expr) => {{
       LedPins {
         front: $gpioa.pa6.into_push_pull_output(&mut $gpioa.crl),
-        pcb_r: Some($gpioa.pa7.into_push_pull_output(&mut $gpioa.crl)),
-        pcb_g: Some($gpiob.pb0.into_push_pull_output(&mut $gpiob.crl)),
-        pcb_b: Some($gpiob.pb1.into_push_pull_output(&mut $gpiob.crl)),
+        pcb_r: Some($gpioa.pa7.into_alternate_push_pull(&mut $gpioa.crl)),
+        pcb_g: Some($gpiob.pb0.into_alternate_push_pull(&mut $gpiob.crl)),
+        pcb_b: Some($gpiob.pb1.into_alternate_push_pull(&mut $gpiob.crl)),
       }
     }}
   }
+
+  // The RGB LED is on TIM3 channels 2 through 4.
+  pub type PcbLed = crate::led::RgbLed<Pwm<TIM3, C2>, Pwm<TIM3, C3>, Pwm<TIM3, C4>>;
+
+  macro_rules! assign_pcb_led {
+    ($led: expr, $tim3: expr, $mapr: expr, $clocks: expr, $apb1: expr) => {{
+      let channels = match ($led.pcb_r.take(), $led.pcb_g.take(), $led.pcb_b.take()) {
+        (Some(r), Some(g), Some(b)) => Some($tim3.pwm((r, g, b), $mapr, 1.khz(), $clocks, $apb1)),
+        _ => None,
+      };
+      PcbLed::new(channels)
+    }}
+  }
 }
 
 // Bluepill
@@ -284,5 +308,14 @@ mod detail {
       }
     }}
   }
+
+  pub type PcbLed = crate::led::RgbLed<crate::led::NoPwm, crate::led::NoPwm, crate::led::NoPwm>;
+
+  macro_rules! assign_pcb_led {
+    ($led: expr, $tim3: expr, $mapr: expr, $clocks: expr, $apb1: expr) => {{
+      let _ = (&mut $led, $tim3, $mapr, $clocks, $apb1);
+      PcbLed::new(None)
+    }}
+  }
 }
 pub use detail::*;
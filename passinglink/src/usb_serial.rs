@@ -0,0 +1,125 @@
+//! A CDC-ACM serial port alongside the controller's own interface, which mirrors the log.
+//!
+//! Without the usb_serial feature, UsbSerial is a stand-in that never gets created, so that the USB resources don't
+//! need to change shape.
+
+#[cfg(feature = "usb_serial")]
+use core::cell::RefCell;
+
+#[cfg(feature = "usb_serial")]
+use cortex_m::interrupt;
+#[cfg(feature = "usb_serial")]
+use heapless::consts::U512;
+#[cfg(feature = "usb_serial")]
+use heapless::spsc::{Queue, SingleCore};
+
+use usb_device::bus::{UsbBus, UsbBusAllocator};
+
+#[cfg(feature = "usb_serial")]
+pub type UsbSerial<B> = usbd_serial::SerialPort<'static, B>;
+
+#[cfg(not(feature = "usb_serial"))]
+pub struct UsbSerial<B>(core::marker::PhantomData<B>);
+
+#[cfg(not(feature = "usb_serial"))]
+impl<B: UsbBus> usb_device::class::UsbClass<B> for UsbSerial<B> {}
+
+#[cfg(feature = "usb_serial")]
+pub fn new<B: UsbBus>(usb_bus: &'static UsbBusAllocator<B>) -> Option<UsbSerial<B>> {
+  Some(usbd_serial::SerialPort::new(usb_bus))
+}
+
+#[cfg(not(feature = "usb_serial"))]
+pub fn new<B: UsbBus>(_usb_bus: &'static UsbBusAllocator<B>) -> Option<UsbSerial<B>> {
+  None
+}
+
+/// Log output that's waiting for the host to read it.
+#[cfg(feature = "usb_serial")]
+pub struct UsbLog {
+  buffer: RefCell<Queue<u8, U512, u16, SingleCore>>,
+}
+
+#[cfg(feature = "usb_serial")]
+impl UsbLog {
+  pub fn new() -> UsbLog {
+    UsbLog {
+      buffer: RefCell::new(unsafe { Queue::u16_sc() }),
+    }
+  }
+
+  /// Queue all of bytes, or none of them if there isn't room. There might not be anyone listening, so output is
+  /// dropped instead of waiting for room.
+  pub fn write(&self, bytes: &[u8]) {
+    interrupt::free(|_| {
+      let mut buffer = self.buffer.borrow_mut();
+      let available = buffer.capacity() - buffer.len();
+      if (available as usize) < bytes.len() {
+        return;
+      }
+
+      for byte in bytes {
+        unsafe {
+          buffer.enqueue_unchecked(*byte);
+        }
+      }
+    })
+  }
+
+  /// Hand the queued output to write, in chunks, until it accepts less than it's given.
+  fn drain<F: FnMut(&[u8]) -> usize>(&self, mut write: F) {
+    interrupt::free(|_| {
+      let mut buffer = self.buffer.borrow_mut();
+      loop {
+        let mut chunk = [0u8; 64];
+        let mut len = 0;
+        for (dst, src) in chunk.iter_mut().zip(buffer.iter()) {
+          *dst = *src;
+          len += 1;
+        }
+
+        if len == 0 {
+          return;
+        }
+
+        let written = write(&chunk[..len]);
+        for _ in 0..written {
+          buffer.dequeue();
+        }
+
+        if written < len {
+          return;
+        }
+      }
+    })
+  }
+}
+
+/// Handle a USB interrupt: throw away anything sent by the host, and send whatever has been logged.
+#[cfg(feature = "usb_serial")]
+pub fn poll<B: UsbBus>(serial: &mut UsbSerial<B>) {
+  let mut discard = [0u8; 64];
+  while let Ok(len) = serial.read(&mut discard) {
+    if len == 0 {
+      break;
+    }
+  }
+
+  flush(serial);
+}
+
+#[cfg(not(feature = "usb_serial"))]
+pub fn poll<B: UsbBus>(_serial: &mut UsbSerial<B>) {}
+
+/// Send whatever has been logged since the last flush, as far as the endpoint has room for it.
+#[cfg(feature = "usb_serial")]
+pub fn flush<B: UsbBus>(serial: &mut UsbSerial<B>) {
+  unsafe {
+    if let Some(ref usb_log) = crate::USB_LOG {
+      usb_log.drain(|bytes| serial.write(bytes).unwrap_or(0));
+    }
+  }
+}
+
+#[cfg(not(feature = "usb_serial"))]
+pub fn flush<B: UsbBus>(_serial: &mut UsbSerial<B>) {}